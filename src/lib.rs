@@ -6,30 +6,55 @@ mod value;
 mod tree;
 
 
+#[cfg(feature = "json")]
+pub use self::value::JsonError;
+
 pub use self::{
-    value::{ExtValue, Value, Values, IntoValues, TryFromValues},
+    value::{
+        ExtValue, Value, Values, IntoValues, TryFromValues, ValueKind, ConversionError,
+        ValueParseError,
+    },
     str::{is_symbol, is_variable},
     tree::{
         BehaviorTree,
+        NodeHandle,
+        BoundNode,
+        CacheStats,
+        PersistentCache,
         Effect, External,
-        ArityError, KindError, IdError,
+        ArityError, KindError, IdError, EvalError, EvaluateError,
+        AliasError, NamedArgumentsError,
         Kind, Kinds, KindsDisplay,
         outcome::{
             Outcome,
             Action,
+            Explanation,
+            ExplainedOutcome,
+            FailedCondition,
+            SelectionFailure,
+            DiagnosedOutcome,
+            Step,
         },
         builder::{
             BehaviorTreeBuilder,
+            HostModule,
         },
         script::{
             ScriptSource,
             ScriptError,
             CompileError,
+            CompilePhase,
             ConflictError,
+            DEFAULT_MAX_NESTING,
+            Diagnostic,
+            DiagnosticSeverity,
         },
     },
 };
 
+#[cfg(feature = "snapshot")]
+pub use self::tree::snapshot::SnapshotError;
+
 #[macro_export]
 macro_rules! custom_fn {
     (
@@ -90,6 +115,26 @@ macro_rules! effect_fn {
     }
 }
 
+#[macro_export]
+macro_rules! effects_fn {
+    (
+        $ctx:pat $( , $arg:ident : $arg_ty:ty )*
+        => $body:expr $(,)?
+    ) => {
+        ($crate::__count_usize!($($arg)*), |$ctx, args: &[$crate::Value<_>]| {
+            let args = args.iter().cloned();
+            let args: ($($arg_ty,)*) = match $crate::TryFromValues::try_from_values(args) {
+                Some(values) => values,
+                None => {
+                    return None;
+                },
+            };
+            let ($($arg,)*): ($($arg_ty,)*) = args;
+            Some(IntoIterator::into_iter($body).collect())
+        })
+    }
+}
+
 #[macro_export]
 macro_rules! query_fn {
     (
@@ -1,10 +1,17 @@
+use unicode_ident::{is_xid_start, is_xid_continue};
 
+
+// namespaced symbols like `faction/orc` are made of multiple segments
 pub fn is_symbol(value: &str) -> bool {
-    !value.is_empty()
-    && !value.starts_with(|c: char| c == '-' || c.is_ascii_digit())
-    && !value.chars().any(|c| c.is_whitespace() || "$?:;()[]{}".contains(c))
+    value.split('/').all(is_symbol_segment)
 }
 
 pub fn is_variable(value: &str) -> bool {
-    value.starts_with('$') && is_symbol(&value[1..])
-}
\ No newline at end of file
+    value.starts_with('$') && is_symbol_segment(&value[1..])
+}
+
+fn is_symbol_segment(value: &str) -> bool {
+    let mut chars = value.chars();
+    chars.next().is_some_and(is_xid_start)
+    && chars.all(|c| c == '-' || is_xid_continue(c))
+}
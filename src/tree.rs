@@ -1,5 +1,9 @@
 
+use std::any::Any;
 use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use id_map::*;
 pub use id_space::*;
@@ -9,7 +13,13 @@ use smol_str::SmolStr;
 use crate::value::IntoValues;
 use crate::{Outcome, Action, Value};
 
-use self::context::{EvalContext, DiscoveryContext, Context, ContextCache};
+use self::context::{
+    EvalContext, DiscoveryContext, Context, ContextCache, GlobalOverrides, Fuel, ExplainStack,
+    Diagnosis, Stepper, StepCell, Scratch,
+};
+use self::outcome::{Explanation, ExplainedOutcome, DiagnosedOutcome, Step};
+
+pub use self::context::{CacheStats, PersistentCache};
 
 
 pub mod outcome;
@@ -18,12 +28,24 @@ pub mod id_space;
 pub mod script;
 pub mod builder;
 
+#[cfg(feature = "snapshot")]
+pub mod snapshot;
+
 mod context;
 
+static NEXT_TREE_ID: AtomicU64 = AtomicU64::new(0);
+
 #[derive(derivative::Derivative)]
 #[derivative(Clone(bound=""))]
 pub struct BehaviorTree<Ctx, Ext, Eff> {
     ids: IdSpace<Ctx, Ext, Eff>,
+    id: u64,
+}
+
+impl<Ctx, Ext, Eff> BehaviorTree<Ctx, Ext, Eff> {
+    fn new(ids: IdSpace<Ctx, Ext, Eff>) -> Self {
+        Self { ids, id: NEXT_TREE_ID.fetch_add(1, Ordering::Relaxed) }
+    }
 }
 
 impl<Ctx, Ext, Eff> BehaviorTree<Ctx, Ext, Eff>
@@ -36,38 +58,388 @@ where
         ctx: EvalContext<Ctx, Ext, Eff>,
         node: &str,
         arguments: &[Value<Ext>],
-    ) -> Result<Outcome<Ext, Eff>, IdError> {
-        match self.ids.resolve_ref(node, arguments.len())? {
-            RefIdx::Action(index) => Ok(self.ids.get(index).eval(&ctx, &arguments)),
-            RefIdx::Node(index) => Ok(self.ids.get(index).eval(&ctx, &arguments)),
-            RefIdx::Cond(index) => Ok(self.ids.get(index)(ctx.view(), &arguments).into()),
+    ) -> Result<Outcome<Ext, Eff>, EvaluateError> {
+        let index = self.resolve_root(node, arguments.len())?;
+        Ok(self.eval_ref(ctx, index, arguments))
+    }
+
+    fn resolve_root(&self, root: &str, arity: usize) -> Result<RefIdx, EvaluateError> {
+        self.ids.resolve_ref(root, arity).map_err(|source| EvaluateError::new(root, source))
+    }
+
+    fn eval_ref(
+        &self,
+        ctx: EvalContext<Ctx, Ext, Eff>,
+        index: RefIdx,
+        arguments: &[Value<Ext>],
+    ) -> Outcome<Ext, Eff> {
+        match index {
+            RefIdx::Action(index) => self.ids.get(index).eval(&ctx, arguments),
+            RefIdx::Node(index) => self.ids.get(index).eval(&ctx, arguments),
+            RefIdx::Cond(index) => self.ids.get(index)(ctx.view(), arguments).into(),
             RefIdx::Custom(index) => {
                 let seed = index.as_seed();
-                Ok(self.ids.get(index)(ctx.view(), &arguments, self, ctx.is_active(), seed))
+                self.ids.get(index)(ctx.view(), arguments, self, ctx.is_active(), seed)
             },
         }
     }
 
+    pub fn prepare(&self, name: &str, arity: usize) -> Result<NodeHandle, IdError> {
+        let index = self.ids.resolve_ref(name, arity)?;
+        Ok(NodeHandle { index, arity, tree: self.id })
+    }
+
+    pub fn bind(&self, name: &str, prefix: &[Value<Ext>]) -> Result<BoundNode<Ext>, IdError> {
+        match self.ids.resolve_ref(name, prefix.len()) {
+            Ok(_) | Err(IdError::Arity(_)) => Ok(BoundNode {
+                name: name.into(),
+                prefix: prefix.into(),
+            }),
+            Err(error) => Err(error),
+        }
+    }
+
+    pub fn evaluate_bound<A>(
+        &self,
+        view: &Ctx,
+        node: &BoundNode<Ext>,
+        arguments: A,
+    ) -> Result<Outcome<Ext, Eff>, EvaluateError>
+    where
+        A: IntoValues<Ext>,
+    {
+        let ctx = EvalContext::new(view, self);
+        let arguments: SmallVec<[_; 8]> = node.prefix.iter().cloned()
+            .chain(arguments.into_values::<SmallVec<[_; 8]>>())
+            .collect();
+        self.eval_node(ctx, &node.name, &arguments)
+    }
+
+    /// Panics if the handle was prepared by a different tree.
+    #[track_caller]
+    pub fn evaluate_handle<A>(
+        &self,
+        view: &Ctx,
+        handle: NodeHandle,
+        arguments: A,
+    ) -> Result<Outcome<Ext, Eff>, EvaluateError>
+    where
+        A: IntoValues<Ext>,
+    {
+        assert_eq!(handle.tree, self.id, "handle must be prepared by this tree");
+        let ctx = EvalContext::new(view, self);
+        let arguments: SmallVec<[_; 8]> = arguments.into_values();
+        if arguments.len() != handle.arity {
+            let error = ArityError { expected: handle.arity, given: arguments.len() };
+            return Err(EvaluateError::new(self.ids.ref_name(handle.index), IdError::Arity(error)));
+        }
+        Ok(self.eval_ref(ctx, handle.index, &arguments))
+    }
+
     pub fn evaluate<A>(
         &self,
         view: &Ctx,
         root: &str,
         arguments: A,
-    ) -> Result<Outcome<Ext, Eff>, IdError>
+    ) -> Result<Outcome<Ext, Eff>, EvaluateError>
+    where
+        A: IntoValues<Ext>,
+    {
+        let ctx = EvalContext::new(view, self);
+        let arguments: SmallVec<[_; 8]> = arguments.into_values();
+        self.eval_node(ctx, root, &arguments)
+    }
+
+    /// Cached condition outcomes are recomputed on every hit. A condition that doesn't give the
+    /// same result for the same view is logged, and fails a debug assertion.
+    pub fn evaluate_verified<A>(
+        &self,
+        view: &Ctx,
+        root: &str,
+        arguments: A,
+    ) -> Result<Outcome<Ext, Eff>, EvaluateError>
+    where
+        A: IntoValues<Ext>,
+    {
+        let ctx = EvalContext::new(view, self).with_cache(ContextCache::verifying());
+        let arguments: SmallVec<[_; 8]> = arguments.into_values();
+        self.eval_node(ctx, root, &arguments)
+    }
+
+    /// Calls `step_fn` before each ref is evaluated, starting with the root, so a debugger can
+    /// pause between them. Breaking fails the current ref and every ref after it.
+    pub fn evaluate_stepped<A, F>(
+        &self,
+        view: &Ctx,
+        root: &str,
+        arguments: A,
+        step_fn: F,
+    ) -> Result<Outcome<Ext, Eff>, EvaluateError>
+    where
+        A: IntoValues<Ext>,
+        F: FnMut(Step<'_, Ext>) -> ControlFlow<()>,
+    {
+        let arguments: SmallVec<[_; 8]> = arguments.into_values();
+        let index = self.resolve_root(root, arguments.len())?;
+        let stepper = Stepper::new(step_fn);
+        if stepper.enter(self.ids.ref_name(index), &arguments).is_break() {
+            return Ok(Outcome::Failure);
+        }
+        let ctx = EvalContext::new(view, self).with_stepper(&stepper);
+        let outcome = self.eval_ref(ctx, index, &arguments);
+        stepper.leave();
+        Ok(outcome)
+    }
+
+    /// Effects registered as stateful receive `scratch` when they are produced. They fail in
+    /// evaluations without a scratch. The scratch is matched against the type the effects were
+    /// registered with, which is why it has to be `'static`, and a stateful effect reached with
+    /// a different type is an error.
+    pub fn evaluate_with_scratch<A, S>(
+        &self,
+        view: &Ctx,
+        root: &str,
+        arguments: A,
+        scratch: &mut S,
+    ) -> Result<Outcome<Ext, Eff>, EvalError>
+    where
+        A: IntoValues<Ext>,
+        S: Any,
+    {
+        let scratch = Scratch::new(scratch);
+        let ctx = EvalContext::new(view, self).with_scratch(&scratch);
+        let arguments: SmallVec<[_; 8]> = arguments.into_values();
+        let outcome = self.eval_node(ctx, root, &arguments)?;
+        if scratch.is_mismatched() {
+            return Err(EvalError::ScratchType);
+        }
+        Ok(outcome)
+    }
+
+    /// Evaluates against the part of a larger host state that `project` selects, so the hooks
+    /// of this tree only ever see that part. The view is borrowed from `state`, and as `Ctx` is
+    /// fixed by the tree, a view with lifetimes like `Units<'a>` has to be held by the state
+    /// for that same `'a`.
+    pub fn evaluate_projected<S, F, A>(
+        &self,
+        state: &S,
+        project: F,
+        root: &str,
+        arguments: A,
+    ) -> Result<Outcome<Ext, Eff>, EvaluateError>
     where
+        F: FnOnce(&S) -> &Ctx,
         A: IntoValues<Ext>,
     {
+        self.evaluate(project(state), root, arguments)
+    }
+
+    /// Roots are evaluated in order with a shared cache, and the first outcome that isn't a
+    /// failure is returned.
+    pub fn evaluate_priority(
+        &self,
+        view: &Ctx,
+        roots: &[(&str, &[Value<Ext>])],
+    ) -> Result<Outcome<Ext, Eff>, EvaluateError> {
         let ctx = EvalContext::new(view, self);
+        for (root, arguments) in roots {
+            let outcome = self.eval_node(ctx.clone(), root, arguments)?;
+            if outcome.is_non_failure() {
+                return Ok(outcome);
+            }
+        }
+        Ok(Outcome::Failure)
+    }
+
+    /// Arguments are given by parameter name, including the `$` prefix, and are passed in the
+    /// order the node or action declares its parameters.
+    pub fn evaluate_named(
+        &self,
+        view: &Ctx,
+        root: &str,
+        arguments: &[(&str, Value<Ext>)],
+    ) -> Result<Outcome<Ext, Eff>, NamedArgumentsError> {
+        let parameters = self.ids.parameters(root)
+            .map_err(|source| EvaluateError::new(root, source))?;
+        for (index, (name, _)) in arguments.iter().enumerate() {
+            if !parameters.iter().any(|parameter| parameter == name) {
+                return Err(NamedArgumentsError::Unknown { name: (*name).into() });
+            }
+            if arguments[..index].iter().any(|(prev, _)| prev == name) {
+                return Err(NamedArgumentsError::Repeated { name: (*name).into() });
+            }
+        }
+        let arguments = parameters.iter()
+            .map(|parameter| {
+                arguments.iter()
+                    .find(|(name, _)| name == parameter)
+                    .map(|(_, value)| value.clone())
+                    .ok_or_else(|| NamedArgumentsError::Missing { name: parameter.clone() })
+            })
+            .collect::<Result<SmallVec<[_; 8]>, _>>()?;
+        let ctx = EvalContext::new(view, self);
+        Ok(self.eval_node(ctx, root, &arguments)?)
+    }
+
+    /// The cache is reused across calls. Results are only correct as long as the view is
+    /// unchanged since the cache was last cleared.
+    pub fn evaluate_cached<A>(
+        &self,
+        view: &Ctx,
+        root: &str,
+        arguments: A,
+        cache: &PersistentCache<Ext, Eff>,
+    ) -> Result<Outcome<Ext, Eff>, EvaluateError>
+    where
+        A: IntoValues<Ext>,
+    {
+        let ctx = EvalContext::new(view, self).with_cache(cache.context_cache(self.id));
         let arguments: SmallVec<[_; 8]> = arguments.into_values();
         self.eval_node(ctx, root, &arguments)
     }
 
+    pub fn evaluate_dry_run<A>(
+        &self,
+        view: &Ctx,
+        root: &str,
+        arguments: A,
+    ) -> Result<Outcome<Ext, Eff>, EvaluateError>
+    where
+        A: IntoValues<Ext>,
+    {
+        let ctx = EvalContext::new(view, self).with_effect_dry_run();
+        let arguments: SmallVec<[_; 8]> = arguments.into_values();
+        self.eval_node(ctx, root, &arguments)
+    }
+
+    pub fn evaluate_with_budget<A>(
+        &self,
+        view: &Ctx,
+        root: &str,
+        arguments: A,
+        fuel: u64,
+    ) -> Result<Outcome<Ext, Eff>, EvalError>
+    where
+        A: IntoValues<Ext>,
+    {
+        let fuel = Fuel::new(Some(fuel).into());
+        let ctx = EvalContext::new(view, self).with_fuel(fuel.clone());
+        let arguments: SmallVec<[_; 8]> = arguments.into_values();
+        let outcome = self.eval_node(ctx, root, &arguments)?;
+        if fuel.get().is_none() {
+            return Err(EvalError::OutOfFuel);
+        }
+        Ok(outcome)
+    }
+
+    pub fn evaluate_explained<A>(
+        &self,
+        view: &Ctx,
+        root: &str,
+        arguments: A,
+    ) -> Result<ExplainedOutcome<Ext, Eff>, EvaluateError>
+    where
+        A: IntoValues<Ext>,
+    {
+        let arguments: SmallVec<[_; 8]> = arguments.into_values();
+        let index = self.resolve_root(root, arguments.len())?;
+        let explain_stack = ExplainStack::default();
+        explain_stack.borrow_mut().push(Explanation {
+            ref_name: self.ids.ref_name(index).clone(),
+            arguments: arguments[..].into(),
+            outcome: Outcome::Failure,
+            children: Vec::new(),
+        });
+        let ctx = EvalContext::new(view, self).with_explain_stack(explain_stack.clone());
+        let outcome = self.eval_ref(ctx, index, &arguments);
+        let mut explanation = explain_stack.borrow_mut().pop().expect("root explanation");
+        explanation.outcome = outcome.clone();
+        Ok((outcome, explanation))
+    }
+
+    /// Also returns the failures of all `select:` and `for-any` nodes that didn't find a
+    /// successful branch, in the order they finished.
+    pub fn evaluate_diagnosed<A>(
+        &self,
+        view: &Ctx,
+        root: &str,
+        arguments: A,
+    ) -> Result<DiagnosedOutcome<Ext, Eff>, EvaluateError>
+    where
+        A: IntoValues<Ext>,
+    {
+        let arguments: SmallVec<[_; 8]> = arguments.into_values();
+        let index = self.resolve_root(root, arguments.len())?;
+        let diagnosis = Diagnosis::default();
+        let ctx = EvalContext::new(view, self).with_diagnosis(diagnosis.clone());
+        let outcome = self.eval_ref(ctx, index, &arguments);
+        let failures = std::mem::take(&mut diagnosis.borrow_mut().failures);
+        Ok(DiagnosedOutcome { outcome, failures })
+    }
+
+    pub fn evaluate_with_stats<A>(
+        &self,
+        view: &Ctx,
+        root: &str,
+        arguments: A,
+    ) -> Result<(Outcome<Ext, Eff>, CacheStats), EvaluateError>
+    where
+        A: IntoValues<Ext>,
+    {
+        let ctx = EvalContext::new(view, self);
+        let cache = ctx.cache().clone();
+        let arguments: SmallVec<[_; 8]> = arguments.into_values();
+        let outcome = self.eval_node(ctx, root, &arguments)?;
+        Ok((outcome, cache.stats()))
+    }
+
+    pub fn evaluate_with_globals<A>(
+        &self,
+        view: &Ctx,
+        root: &str,
+        arguments: A,
+        overrides: &HashMap<SmolStr, Value<Ext>>,
+    ) -> Result<Outcome<Ext, Eff>, EvalError>
+    where
+        A: IntoValues<Ext>,
+    {
+        let mut global_overrides = GlobalOverrides::with_capacity(overrides.len());
+        for (name, value) in overrides {
+            let index = self.ids.resolve::<GlobalIdx>(name, 0)
+                .map_err(|source| EvalError::Override { name: name.clone(), source })?;
+            global_overrides.insert(index, value.clone());
+        }
+        let ctx = EvalContext::new(view, self).with_global_overrides(&global_overrides);
+        let arguments: SmallVec<[_; 8]> = arguments.into_values();
+        Ok(self.eval_node(ctx, root, &arguments)?)
+    }
+
+    pub fn evaluate_and_apply<A, F>(
+        &self,
+        state: &mut Ctx,
+        root: &str,
+        arguments: A,
+        mut apply_effect: F,
+    ) -> Result<Outcome<Ext, Eff>, EvaluateError>
+    where
+        A: IntoValues<Ext>,
+        F: FnMut(&mut Ctx, &Eff),
+    {
+        let outcome = self.evaluate(state, root, arguments)?;
+        if let Some(effects) = outcome.effects() {
+            for effect in effects {
+                apply_effect(state, effect);
+            }
+        }
+        Ok(outcome)
+    }
+
     pub fn check<A>(
         &self,
         view: &Ctx,
         root: &str,
         arguments: A,
-    ) -> Result<Outcome<Ext, Eff>, IdError>
+    ) -> Result<Outcome<Ext, Eff>, EvaluateError>
     where
         A: IntoValues<Ext>,
     {
@@ -76,6 +448,8 @@ where
         self.eval_node(ctx, root, &arguments[..])
     }
 
+    /// Actions are discovered in declaration order, and per action in the order its
+    /// `discovery` nodes produce them.
     pub fn discover_all<C>(&self, view: &Ctx, collection: &mut C)
     where
         C: Extend<Action<Ext, Eff>>,
@@ -88,24 +462,211 @@ where
         }
     }
 
-    pub fn discover<C>(&self, view: &Ctx, action: &str, collection: &mut C) -> Result<(), IdError>
+    pub fn discover<C>(
+        &self,
+        view: &Ctx,
+        action: &str,
+        collection: &mut C,
+    ) -> Result<(), EvaluateError>
     where
         C: Extend<Action<Ext, Eff>>,
     {
         let collection = RefCell::new(collection);
         let cache = ContextCache::default();
-        let index = self.ids.action(action)?;
+        let index = self.ids.action(action).map_err(|source| EvaluateError::new(action, source))?;
         let ctx = DiscoveryContext::new(view, self, &collection, Some(index), cache);
         self.ids.get(index).eval_discovery_nodes(&ctx);
         Ok(())
     }
 
+    /// Only actions whose arguments are equal to the pinned `Some` arguments are collected. The
+    /// `None` positions are left to discovery.
+    pub fn discover_with<C>(
+        &self,
+        view: &Ctx,
+        action: &str,
+        fixed_arguments: &[Option<Value<Ext>>],
+        collection: &mut C,
+    ) -> Result<(), EvaluateError>
+    where
+        C: Extend<Action<Ext, Eff>>,
+    {
+        let index = self.ids.resolve::<ActionIdx>(action, fixed_arguments.len())
+            .map_err(|source| EvaluateError::new(action, source))?;
+        let mut pinned = PinnedActions { fixed_arguments, collection };
+        let collection = RefCell::new(&mut pinned);
+        let cache = ContextCache::default();
+        let ctx = DiscoveryContext::new(view, self, &collection, Some(index), cache);
+        self.ids.get(index).eval_discovery_nodes(&ctx);
+        Ok(())
+    }
+
+    /// Each call is resolved and run independently, so a failing call doesn't affect the others.
+    /// A successful call can produce multiple effects if it was registered as a multi-effect.
+    /// Stateful effects always fail, as there is no scratch to give them.
+    pub fn effects(
+        &self,
+        view: &Ctx,
+        calls: &[(&str, &[Value<Ext>])],
+    ) -> Vec<Result<Option<Vec<Eff>>, IdError>> {
+        calls.iter()
+            .map(|(name, arguments)| {
+                let index = self.ids.resolve::<EffectIdx>(name, arguments.len())?;
+                let mut effects = Vec::new();
+                let handler = self.ids.get(index);
+                Ok(handler.call(view, arguments, None, None, &mut effects).then_some(effects))
+            })
+            .collect()
+    }
+
+    /// Runs a query and collects `limit` of its values after skipping `offset`. The flag is set
+    /// if the query had more values after the returned page.
+    pub fn query_page(
+        &self,
+        view: &Ctx,
+        name: &str,
+        arguments: &[Value<Ext>],
+        offset: usize,
+        limit: usize,
+    ) -> Result<(Vec<Value<Ext>>, bool), IdError> {
+        let index = self.ids.resolve::<QueryIdx>(name, arguments.len())?;
+        let mut page = Vec::new();
+        let mut has_more = false;
+        let mut skip = offset;
+        self.ids.get(index).query(view, arguments, &mut |value| {
+            let Some(value) = value else {
+                return ControlFlow::Continue(());
+            };
+            if skip > 0 {
+                skip -= 1;
+            } else if page.len() < limit {
+                page.push(value);
+            } else {
+                has_more = true;
+                return ControlFlow::Break(Outcome::Success);
+            }
+            ControlFlow::Continue(())
+        });
+        Ok((page, has_more))
+    }
+
+    pub fn doc(&self, name: &str) -> Option<&str> {
+        if let Some(index) = self.ids.find::<ActionIdx>(name) {
+            self.ids.get(index).doc.as_deref()
+        } else {
+            self.ids.get(self.ids.find::<NodeIdx>(name)?).doc.as_deref()
+        }
+    }
+
+    pub fn structural_eq(&self, other: &Self) -> bool {
+        self.ids.structural_eq(&other.ids)
+    }
+
     #[track_caller]
     pub fn action_name(&self, action: &Action<Ext, Eff>) -> &SmolStr {
         self.ids.action_name(action.index())
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeHandle {
+    index: RefIdx,
+    arity: usize,
+    tree: u64,
+}
+
+impl NodeHandle {
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BoundNode<Ext> {
+    name: SmolStr,
+    prefix: SmallVec<[Value<Ext>; 4]>,
+}
+
+impl<Ext> BoundNode<Ext> {
+    pub fn name(&self) -> &SmolStr {
+        &self.name
+    }
+
+    pub fn prefix(&self) -> &[Value<Ext>] {
+        &self.prefix
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, thiserror::Error)]
+pub enum EvalError {
+    #[error(transparent)]
+    Evaluate(#[from] EvaluateError),
+    #[error("Evaluation ran out of fuel")]
+    OutOfFuel,
+    #[error("Scratch doesn't have the type the stateful effects were registered with")]
+    ScratchType,
+    #[error("Unable to override `{name}`: {source}")]
+    Override { name: SmolStr, source: IdError },
+}
+
+struct PinnedActions<'a, Ext, C> {
+    fixed_arguments: &'a [Option<Value<Ext>>],
+    collection: &'a mut C,
+}
+
+impl<'a, Ext, Eff, C> Extend<Action<Ext, Eff>> for PinnedActions<'a, Ext, C>
+where
+    Ext: PartialEq,
+    C: Extend<Action<Ext, Eff>>,
+{
+    fn extend<I>(&mut self, actions: I)
+    where
+        I: IntoIterator<Item = Action<Ext, Eff>>,
+    {
+        let fixed_arguments = self.fixed_arguments;
+        self.collection.extend(actions.into_iter().filter(|action| {
+            fixed_arguments.iter().zip(action.arguments()).all(|(fixed, argument)| {
+                fixed.as_ref().is_none_or(|fixed| fixed == argument)
+            })
+        }));
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, thiserror::Error)]
+#[error("Unable to evaluate `{node}`: {source}")]
+pub struct EvaluateError {
+    pub node: SmolStr,
+    pub source: IdError,
+}
+
+impl EvaluateError {
+    fn new(node: &str, source: IdError) -> Self {
+        Self { node: node.into(), source }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, thiserror::Error)]
+pub enum NamedArgumentsError {
+    #[error(transparent)]
+    Evaluate(#[from] EvaluateError),
+    #[error("No argument for parameter `{name}`")]
+    Missing { name: SmolStr },
+    #[error("Unknown parameter `{name}`")]
+    Unknown { name: SmolStr },
+    #[error("Repeated argument for parameter `{name}`")]
+    Repeated { name: SmolStr },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, thiserror::Error)]
+pub enum AliasError {
+    #[error("Unknown identifier `{name}`")]
+    Unknown { name: SmolStr },
+    #[error("Identifier `{name}` was already used for {kind}")]
+    Taken { name: SmolStr, kind: Kind },
+    #[error("Alias `{name}` is not a valid identifier for its kind")]
+    InvalidName { name: SmolStr },
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, thiserror::Error)]
 #[error("Expected {expected}, given {given}")]
 pub struct ArityError {
@@ -1,21 +1,48 @@
 
+use std::any::Any;
+use std::collections::HashSet;
+
 use derivative::Derivative;
 use smol_str::SmolStr;
 use treelang::Indent;
 
 use crate::str::{is_variable, is_symbol};
-use crate::tree::{SeedIdx, CustomIdx};
+use crate::tree::{SeedIdx, CustomIdx, External, AliasError, Kind};
 use crate::tree::id_space::{QueryIdx, CondIdx};
 
-use super::{BehaviorTree, GlobalFn, EffectFn, QueryFn, CondFn, SeedFn, CustomFn};
+use super::{
+    BehaviorTree, GlobalFn, GlobalCallFn, GlobalHandler, FallbackFn, EffectFn, ActionEffectFn,
+    MultiEffectFn, StatefulEffectFn, EffectHandler, QueryFn, EagerQueryFn, YieldQueryFn,
+    AsyncQueryFn, BlockOnFn, QueryHandler, CondFn, SeedFn, CustomFn,
+};
 use super::id_space::{IdSpace, GlobalIdx, EffectIdx};
-use super::script::{ScriptSource, Compiler, CompileResult};
+use super::script::{ScriptSource, Compiler, CompileResult, DEFAULT_MAX_NESTING};
+
 
+/// A bundle of host hooks that is registered as a whole.
+pub trait HostModule<Ctx, Ext, Eff> {
+    fn install(self, builder: &mut BehaviorTreeBuilder<Ctx, Ext, Eff>);
+}
+
+impl<Ctx, Ext, Eff, F> HostModule<Ctx, Ext, Eff> for F
+where
+    F: FnOnce(&mut BehaviorTreeBuilder<Ctx, Ext, Eff>),
+{
+    fn install(self, builder: &mut BehaviorTreeBuilder<Ctx, Ext, Eff>) {
+        self(builder);
+    }
+}
 
 #[derive(Derivative)]
 #[derivative(Clone(bound=""), Default(bound=""))]
 pub struct BehaviorTreeBuilder<Ctx, Ext, Eff> {
-    ids: IdSpace<Ctx, Ext, Eff>
+    pub(super) ids: IdSpace<Ctx, Ext, Eff>,
+    fallback: Option<FallbackFn<Ctx, Ext, Eff>>,
+    allow_identical_redefinition: bool,
+    allow_shadowing: bool,
+    #[derivative(Default(value="DEFAULT_MAX_NESTING"))]
+    max_nesting: usize,
+    estimated_decls: usize,
 }
 
 impl<Ctx, Ext, Eff> BehaviorTreeBuilder<Ctx, Ext, Eff> {
@@ -24,9 +51,26 @@ impl<Ctx, Ext, Eff> BehaviorTreeBuilder<Ctx, Ext, Eff> {
     where
         N: Into<SmolStr>,
     {
-        let id = id.into();
+        self.insert_global(id.into(), GlobalHandler::Value(handler), 0);
+    }
+
+    /// Globals with arguments are used as `($name arg...)` in value position.
+    #[track_caller]
+    pub fn register_global_call<N>(
+        &mut self,
+        id: N,
+        (arity, handler): (usize, GlobalCallFn<Ctx, Ext>),
+    )
+    where
+        N: Into<SmolStr>,
+    {
+        self.insert_global(id.into(), GlobalHandler::Call(handler), arity);
+    }
+
+    #[track_caller]
+    fn insert_global(&mut self, id: SmolStr, handler: GlobalHandler<Ctx, Ext>, arity: usize) {
         assert!(is_variable(&id), "global id `{id}` is not a valid variable");
-        let prev = self.ids.set::<GlobalIdx>(id.clone(), handler, 0).err();
+        let prev = self.ids.set::<GlobalIdx>(id.clone(), handler, arity).err();
         if let Some(kind) = prev {
             panic!("global id `{id}` was already used for {kind}");
         }
@@ -51,7 +95,57 @@ impl<Ctx, Ext, Eff> BehaviorTreeBuilder<Ctx, Ext, Eff> {
         N: Into<SmolStr>,
         Ext: Clone,
     {
-        let id = id.into();
+        self.insert_effect(id.into(), EffectHandler::Plain(handler), arity);
+    }
+
+    /// The handler also receives all arguments of the action the effect is produced for, or
+    /// `None` when it is called directly via `BehaviorTree::effects`.
+    #[track_caller]
+    pub fn register_action_effect<N>(
+        &mut self,
+        id: N,
+        (arity, handler): (usize, ActionEffectFn<Ctx, Ext, Eff>),
+    )
+    where
+        N: Into<SmolStr>,
+    {
+        self.insert_effect(id.into(), EffectHandler::Action(handler), arity);
+    }
+
+    /// All effects produced by the handler are added to the action in order.
+    #[track_caller]
+    pub fn register_multi_effect<N>(
+        &mut self,
+        id: N,
+        (arity, handler): (usize, MultiEffectFn<Ctx, Ext, Eff>),
+    )
+    where
+        N: Into<SmolStr>,
+    {
+        self.insert_effect(id.into(), EffectHandler::Multi(handler), arity);
+    }
+
+    /// The handler receives the scratch given to `BehaviorTree::evaluate_with_scratch`. It is
+    /// not called while probing, where it is assumed to succeed as long as a scratch of its type
+    /// was given.
+    #[track_caller]
+    pub fn register_effect_stateful<N, S>(
+        &mut self,
+        id: N,
+        (arity, handler): (usize, StatefulEffectFn<Ctx, Ext, Eff, S>),
+    )
+    where
+        N: Into<SmolStr>,
+        Ctx: 'static,
+        Ext: 'static,
+        Eff: 'static,
+        S: Any,
+    {
+        self.insert_effect(id.into(), EffectHandler::stateful(handler), arity);
+    }
+
+    #[track_caller]
+    fn insert_effect(&mut self, id: SmolStr, handler: EffectHandler<Ctx, Ext, Eff>, arity: usize) {
         assert!(is_symbol(&id), "effect id `{id}` is not a valid symbol");
         let prev = self.ids.set::<EffectIdx>(id.clone(), handler, arity).err();
         if let Some(kind) = prev {
@@ -65,7 +159,54 @@ impl<Ctx, Ext, Eff> BehaviorTreeBuilder<Ctx, Ext, Eff> {
         N: Into<SmolStr>,
         Ext: Clone,
     {
-        let id = id.into();
+        self.insert_query(id.into(), QueryHandler::Visit(handler), arity);
+    }
+
+    /// Unlike `register_query`, the values are always collected before iteration starts.
+    #[track_caller]
+    pub fn register_query_vec<N>(
+        &mut self,
+        id: N,
+        (arity, handler): (usize, EagerQueryFn<Ctx, Ext>),
+    )
+    where
+        N: Into<SmolStr>,
+        Ext: Clone,
+    {
+        self.insert_query(id.into(), QueryHandler::Eager(handler), arity);
+    }
+
+    /// Values are pushed by the handler, which should stop producing them once a break is
+    /// returned.
+    #[track_caller]
+    pub fn register_query_yield<N>(
+        &mut self,
+        id: N,
+        (arity, handler): (usize, YieldQueryFn<Ctx, Ext>),
+    )
+    where
+        N: Into<SmolStr>,
+    {
+        self.insert_query(id.into(), QueryHandler::Yield(handler), arity);
+    }
+
+    /// The future returned by the handler is resolved by `block_on` each time the query is
+    /// used, so evaluation blocks until the values are available.
+    #[track_caller]
+    pub fn register_query_blocking<N>(
+        &mut self,
+        id: N,
+        (arity, handler): (usize, AsyncQueryFn<Ctx, Ext>),
+        block_on: BlockOnFn<Ext>,
+    )
+    where
+        N: Into<SmolStr>,
+    {
+        self.insert_query(id.into(), QueryHandler::Blocking(handler, block_on), arity);
+    }
+
+    #[track_caller]
+    fn insert_query(&mut self, id: SmolStr, handler: QueryHandler<Ctx, Ext, Eff>, arity: usize) {
         assert!(is_symbol(&id), "query id `{id}` is not a valid symbol");
         let prev = self.ids.set::<QueryIdx>(id.clone(), handler, arity).err();
         if let Some(kind) = prev {
@@ -87,6 +228,9 @@ impl<Ctx, Ext, Eff> BehaviorTreeBuilder<Ctx, Ext, Eff> {
         }
     }
 
+    /// Custom handlers receive the view, the arguments, the tree for nested evaluation, whether
+    /// the evaluation is active (`false` for conditions and checks), and a seed that is stable
+    /// for the registered node.
     #[track_caller]
     pub fn register_custom<N>(&mut self, id: N, (arity, handler): (usize, CustomFn<Ctx, Ext, Eff>))
     where
@@ -101,17 +245,111 @@ impl<Ctx, Ext, Eff> BehaviorTreeBuilder<Ctx, Ext, Eff> {
         }
     }
 
+    /// The fallback is consulted for node references to unknown names while compiling. The
+    /// returned handlers are registered as custom nodes.
+    #[track_caller]
+    pub fn register_fallback(&mut self, handler: FallbackFn<Ctx, Ext, Eff>) {
+        assert!(self.fallback.is_none(), "fallback was already registered");
+        self.fallback = Some(handler);
+    }
+
+    pub fn register_module<M>(&mut self, module: M)
+    where
+        M: HostModule<Ctx, Ext, Eff>,
+    {
+        module.install(self);
+    }
+
+    /// Space for the given number of declarations is reserved up front when compiling. This
+    /// only avoids reallocations and doesn't limit the number of declarations.
+    pub fn reserve_declarations(&mut self, estimated_decls: usize) {
+        self.estimated_decls = estimated_decls;
+    }
+
+    /// Makes `id` resolve to the same registered hook as `existing`, with the same kind and
+    /// arity. Aliases of globals have to be variables, all others symbols.
+    pub fn alias<N>(&mut self, id: N, existing: &str) -> Result<(), AliasError>
+    where
+        N: Into<SmolStr>,
+    {
+        let id = id.into();
+        if let Some(kind) = self.ids.kind(&id) {
+            return Err(AliasError::Taken { name: id, kind });
+        }
+        let is_valid = match self.ids.kind(existing) {
+            Some(Kind::Global) => is_variable(&id),
+            Some(_) => is_symbol(&id),
+            None => return Err(AliasError::Unknown { name: existing.into() }),
+        };
+        if !is_valid {
+            return Err(AliasError::InvalidName { name: id });
+        }
+        self.ids.alias(id, existing);
+        Ok(())
+    }
+
+    /// Declarations that are structurally identical to an earlier one with the same name are
+    /// skipped instead of being reported as conflicts.
+    pub fn allow_identical_redefinition(&mut self, allow: bool) {
+        self.allow_identical_redefinition = allow;
+    }
+
+    /// Variables declared by a nested scope may shadow lexicals of an enclosing scope, resolving
+    /// to the innermost declaration. Patterns naming a visible lexical still compare against
+    /// it, and shadowing globals is still an error.
+    pub fn allow_shadowing(&mut self, allow: bool) {
+        self.allow_shadowing = allow;
+    }
+
+    /// Limits how deeply lists and patterns may be nested in scripts. Defaults to
+    /// [`DEFAULT_MAX_NESTING`].
+    pub fn max_nesting(&mut self, limit: usize) {
+        self.max_nesting = limit;
+    }
+
     pub fn compile_str(
         self,
         indent: Indent,
         name: &str,
         content: &str,
-    ) -> CompileResult<BehaviorTree<Ctx, Ext, Eff>> {
+    ) -> CompileResult<BehaviorTree<Ctx, Ext, Eff>>
+    where
+        Ext: External,
+    {
         self.compile(indent, [
             ScriptSource::Str { name: name.into(), content: content.into() },
         ])
     }
 
+    /// Compiles an inline script with two space indentation. Either all lines are prefixed with
+    /// `|` markers, or their common indentation is removed.
+    pub fn compile_str_normalized(
+        self,
+        name: &str,
+        content: &str,
+    ) -> CompileResult<BehaviorTree<Ctx, Ext, Eff>>
+    where
+        Ext: External,
+    {
+        self.compile_str(Indent::spaces(2), name, &normalize_inline(content))
+    }
+
+    /// Parses the sources without compiling them and returns every referenced name that is
+    /// neither registered nor declared by the sources themselves.
+    pub fn required_symbols<T>(&self, indent: Indent, sources: T) -> CompileResult<HashSet<SmolStr>>
+    where
+        T: IntoIterator<Item = ScriptSource>,
+    {
+        let mut compiler = Compiler::new(self.ids.clone(), indent, self.fallback);
+        compiler.allow_identical_redefinition(self.allow_identical_redefinition);
+        compiler.allow_shadowing(self.allow_shadowing);
+        compiler.max_nesting(self.max_nesting);
+        for source in sources {
+            compiler.load(source)?;
+        }
+        Ok(compiler.unknown_refs())
+    }
+
     pub fn compile<'a, T>(
         self,
         indent: Indent,
@@ -119,12 +357,36 @@ impl<Ctx, Ext, Eff> BehaviorTreeBuilder<Ctx, Ext, Eff> {
     ) -> CompileResult<BehaviorTree<Ctx, Ext, Eff>>
     where
         T: IntoIterator<Item = ScriptSource>,
+        Ext: External,
     {
-        let mut compiler = Compiler::new(self.ids, indent);
+        let mut compiler =
+            Compiler::with_capacity(self.ids, indent, self.fallback, self.estimated_decls);
+        compiler.allow_identical_redefinition(self.allow_identical_redefinition);
+        compiler.allow_shadowing(self.allow_shadowing);
+        compiler.max_nesting(self.max_nesting);
         for source in sources {
             compiler.load(source)?;
         }
         let compiled_ids = compiler.compile()?;
-        Ok(BehaviorTree { ids: compiled_ids })
+        Ok(BehaviorTree::new(compiled_ids))
+    }
+}
+
+fn normalize_inline(content: &str) -> String {
+    let lines: Vec<&str> = content.lines().filter(|line| !line.trim().is_empty()).collect();
+    let is_piped = lines.iter().all(|line| line.trim_start().starts_with('|'));
+    let common = lines.iter()
+        .map(|line| line.len() - line.trim_start_matches(' ').len())
+        .min()
+        .unwrap_or(0);
+    let mut normalized = String::new();
+    for line in lines {
+        if is_piped {
+            normalized.push_str(&line.trim_start()[1..]);
+        } else {
+            normalized.push_str(&line[common..]);
+        }
+        normalized.push('\n');
     }
+    normalized
 }
@@ -1,15 +1,124 @@
+use std::any::{Any, TypeId};
 use std::borrow::Cow;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::ops::ControlFlow;
 use std::rc::Rc;
 
+use smol_str::SmolStr;
+
 use crate::Value;
 
-use super::{BehaviorTree, ActionIdx, RefIdx};
-use super::outcome::{Action, Outcome};
+use super::{BehaviorTree, ActionIdx, RefIdx, GlobalIdx};
+use super::outcome::{Action, Outcome, Explanation, FailedCondition, SelectionFailure, Step};
 
 
 const LRU_LEN: usize = 4096;
 
+pub type GlobalOverrides<Ext> = HashMap<GlobalIdx, Value<Ext>>;
+
+// `None` once the fuel was exhausted
+pub type Fuel = Rc<Cell<Option<u64>>>;
+
+// set to stop evaluating the remaining discovery nodes of the current action
+pub type DiscoveryPrune = Rc<Cell<bool>>;
+
+// explanations of the currently evaluating refs, innermost last
+pub type ExplainStack<Ext, Eff> = Rc<RefCell<Vec<Explanation<Ext, Eff>>>>;
+
+// outcomes of `once:` directives by id and activity, along with the lexicals they saw
+type OnceOutcomes<Ext, Eff> =
+    Rc<RefCell<HashMap<(u32, bool), Vec<(Vec<Value<Ext>>, Outcome<Ext, Eff>)>>>>;
+
+/// Host provided state that stateful effects can modify during an evaluation.
+pub trait ScratchCell {
+    /// Remembers the mismatch if the scratch isn't a `scratch_type`.
+    fn accepts(&self, scratch_type: TypeId) -> bool;
+
+    fn with_scratch(&self, scratch_fn: &mut dyn FnMut(&mut dyn Any));
+}
+
+pub struct Scratch<'a> {
+    value: RefCell<&'a mut dyn Any>,
+    is_mismatched: Cell<bool>,
+}
+
+impl<'a> Scratch<'a> {
+    pub fn new(value: &'a mut dyn Any) -> Self {
+        Self { value: RefCell::new(value), is_mismatched: Cell::new(false) }
+    }
+
+    pub fn is_mismatched(&self) -> bool {
+        self.is_mismatched.get()
+    }
+}
+
+impl ScratchCell for Scratch<'_> {
+    fn accepts(&self, scratch_type: TypeId) -> bool {
+        let accepts = (**self.value.borrow()).type_id() == scratch_type;
+        if !accepts {
+            self.is_mismatched.set(true);
+        }
+        accepts
+    }
+
+    fn with_scratch(&self, scratch_fn: &mut dyn FnMut(&mut dyn Any)) {
+        scratch_fn(&mut **self.value.borrow_mut());
+    }
+}
+
+/// Receives every ref before it is evaluated. Once a step breaks, it and every following ref
+/// fail without being passed on.
+pub trait StepCell<Ext> {
+    fn enter(&self, name: &SmolStr, arguments: &[Value<Ext>]) -> ControlFlow<()>;
+
+    fn leave(&self);
+}
+
+pub struct Stepper<F> {
+    step_fn: RefCell<F>,
+    depth: Cell<usize>,
+    is_stopped: Cell<bool>,
+}
+
+impl<F> Stepper<F> {
+    pub fn new(step_fn: F) -> Self {
+        Self { step_fn: RefCell::new(step_fn), depth: Cell::new(0), is_stopped: Cell::new(false) }
+    }
+}
+
+impl<F, Ext> StepCell<Ext> for Stepper<F>
+where
+    F: FnMut(Step<'_, Ext>) -> ControlFlow<()>,
+{
+    fn enter(&self, name: &SmolStr, arguments: &[Value<Ext>]) -> ControlFlow<()> {
+        if self.is_stopped.get() {
+            return ControlFlow::Break(());
+        }
+        let depth = self.depth.get();
+        let flow = (self.step_fn.borrow_mut())(Step { name, arguments, depth });
+        match flow {
+            ControlFlow::Continue(()) => self.depth.set(depth + 1),
+            ControlFlow::Break(()) => self.is_stopped.set(true),
+        }
+        flow
+    }
+
+    fn leave(&self) {
+        self.depth.set(self.depth.get() - 1);
+    }
+}
+
+pub type Diagnosis<Ext> = Rc<RefCell<DiagnosisState<Ext>>>;
+
+#[derive(derivative::Derivative)]
+#[derivative(Default(bound=""))]
+pub struct DiagnosisState<Ext> {
+    // last failed condition of the currently evaluating selection branch
+    pub last_failed: Option<FailedCondition<Ext>>,
+    pub failures: Vec<SelectionFailure<Ext>>,
+}
+
 pub trait Context<Ctx, Ext, Eff>: Sized + Clone {
     fn view(&self) -> &Ctx;
 
@@ -19,10 +128,69 @@ pub trait Context<Ctx, Ext, Eff>: Sized + Clone {
 
     fn to_inactive(&self) -> Self;
 
+    fn to_probe(&self) -> Self;
+
     fn is_active(&self) -> bool;
 
     fn action(&self, action: Action<Ext, Eff>) -> Outcome<Ext, Eff>;
 
+    fn global_overrides(&self) -> Option<&GlobalOverrides<Ext>>;
+
+    fn is_effect_dry_run(&self) -> bool;
+
+    fn fuel(&self) -> Option<&Fuel>;
+
+    fn explain_stack(&self) -> Option<&ExplainStack<Ext, Eff>>;
+
+    fn diagnosis(&self) -> Option<&Diagnosis<Ext>>;
+
+    fn scratch(&self) -> Option<&dyn ScratchCell>;
+
+    fn stepper(&self) -> Option<&dyn StepCell<Ext>>;
+
+    fn discovery_prune(&self) -> Option<&DiscoveryPrune>;
+
+    fn prune_discovery(&self) {
+        if let Some(prune) = self.discovery_prune() {
+            prune.set(true);
+        }
+    }
+
+    fn is_discovery_pruned(&self) -> bool {
+        self.discovery_prune().is_some_and(|prune| prune.get())
+    }
+
+    fn consume_fuel(&self) -> bool {
+        let Some(fuel) = self.fuel() else {
+            return true;
+        };
+        match fuel.get() {
+            Some(0) | None => {
+                fuel.set(None);
+                false
+            },
+            Some(remaining) => {
+                fuel.set(Some(remaining - 1));
+                true
+            },
+        }
+    }
+
+    fn global(&self, index: GlobalIdx) -> Value<Ext>
+    where
+        Ext: Clone,
+    {
+        if let Some(value) = self.global_overrides().and_then(|globals| globals.get(&index)) {
+            value.clone()
+        } else {
+            self.tree().ids.get(index).get(self.view(), &[])
+        }
+    }
+
+    fn global_call(&self, index: GlobalIdx, arguments: &[Value<Ext>]) -> Value<Ext> {
+        self.tree().ids.get(index).get(self.view(), arguments)
+    }
+
     fn to_inactive_if_active(&self) -> Cow<'_, Self> {
         if self.is_active() {
             Cow::Owned(self.to_inactive())
@@ -37,6 +205,13 @@ pub struct EvalContext<'a, Ctx, Ext, Eff> {
     tree: &'a BehaviorTree<Ctx, Ext, Eff>,
     is_active: bool,
     cache: ContextCache<Ext, Eff>,
+    global_overrides: Option<&'a GlobalOverrides<Ext>>,
+    effect_dry_run: bool,
+    fuel: Option<Fuel>,
+    explain_stack: Option<ExplainStack<Ext, Eff>>,
+    diagnosis: Option<Diagnosis<Ext>>,
+    scratch: Option<&'a dyn ScratchCell>,
+    stepper: Option<&'a dyn StepCell<Ext>>,
 }
 
 impl<'a, Ctx, Ext, Eff> Clone for EvalContext<'a, Ctx, Ext, Eff> {
@@ -46,13 +221,64 @@ impl<'a, Ctx, Ext, Eff> Clone for EvalContext<'a, Ctx, Ext, Eff> {
             tree: self.tree,
             is_active: self.is_active,
             cache: self.cache.clone(),
+            global_overrides: self.global_overrides,
+            effect_dry_run: self.effect_dry_run,
+            fuel: self.fuel.clone(),
+            explain_stack: self.explain_stack.clone(),
+            diagnosis: self.diagnosis.clone(),
+            scratch: self.scratch,
+            stepper: self.stepper,
         }
     }
 }
 
 impl<'a, Ctx, Ext, Eff> EvalContext<'a, Ctx, Ext, Eff> {
     pub fn new(view: &'a Ctx, tree: &'a BehaviorTree<Ctx, Ext, Eff>) -> Self {
-        Self { view, tree, is_active: true, cache: ContextCache::default() }
+        Self {
+            view,
+            tree,
+            is_active: true,
+            cache: ContextCache::default(),
+            global_overrides: None,
+            effect_dry_run: false,
+            fuel: None,
+            explain_stack: None,
+            diagnosis: None,
+            scratch: None,
+            stepper: None,
+        }
+    }
+
+    pub fn with_global_overrides(self, global_overrides: &'a GlobalOverrides<Ext>) -> Self {
+        Self { global_overrides: Some(global_overrides), ..self }
+    }
+
+    pub fn with_effect_dry_run(self) -> Self {
+        Self { effect_dry_run: true, ..self }
+    }
+
+    pub fn with_fuel(self, fuel: Fuel) -> Self {
+        Self { fuel: Some(fuel), ..self }
+    }
+
+    pub fn with_explain_stack(self, explain_stack: ExplainStack<Ext, Eff>) -> Self {
+        Self { explain_stack: Some(explain_stack), ..self }
+    }
+
+    pub fn with_diagnosis(self, diagnosis: Diagnosis<Ext>) -> Self {
+        Self { diagnosis: Some(diagnosis), ..self }
+    }
+
+    pub fn with_scratch(self, scratch: &'a dyn ScratchCell) -> Self {
+        Self { scratch: Some(scratch), ..self }
+    }
+
+    pub fn with_stepper(self, stepper: &'a dyn StepCell<Ext>) -> Self {
+        Self { stepper: Some(stepper), ..self }
+    }
+
+    pub fn with_cache(self, cache: ContextCache<Ext, Eff>) -> Self {
+        Self { cache, ..self }
     }
 }
 
@@ -69,6 +295,10 @@ impl<'a, Ctx, Ext, Eff> Context<Ctx, Ext, Eff> for EvalContext<'a, Ctx, Ext, Eff
         &self.cache
     }
 
+    fn to_probe(&self) -> Self {
+        Self { cache: self.cache.to_probe(), effect_dry_run: true, ..self.clone() }
+    }
+
     fn is_active(&self) -> bool {
         self.is_active
     }
@@ -79,6 +309,13 @@ impl<'a, Ctx, Ext, Eff> Context<Ctx, Ext, Eff> for EvalContext<'a, Ctx, Ext, Eff
             tree: self.tree,
             is_active: false,
             cache: self.cache.clone(),
+            global_overrides: self.global_overrides,
+            effect_dry_run: self.effect_dry_run,
+            fuel: self.fuel.clone(),
+            explain_stack: self.explain_stack.clone(),
+            diagnosis: self.diagnosis.clone(),
+            scratch: self.scratch,
+            stepper: self.stepper,
         }
     }
 
@@ -89,6 +326,38 @@ impl<'a, Ctx, Ext, Eff> Context<Ctx, Ext, Eff> for EvalContext<'a, Ctx, Ext, Eff
             Outcome::Failure
         }
     }
+
+    fn global_overrides(&self) -> Option<&GlobalOverrides<Ext>> {
+        self.global_overrides
+    }
+
+    fn is_effect_dry_run(&self) -> bool {
+        self.effect_dry_run
+    }
+
+    fn fuel(&self) -> Option<&Fuel> {
+        self.fuel.as_ref()
+    }
+
+    fn explain_stack(&self) -> Option<&ExplainStack<Ext, Eff>> {
+        self.explain_stack.as_ref()
+    }
+
+    fn diagnosis(&self) -> Option<&Diagnosis<Ext>> {
+        self.diagnosis.as_ref()
+    }
+
+    fn scratch(&self) -> Option<&dyn ScratchCell> {
+        self.scratch
+    }
+
+    fn stepper(&self) -> Option<&dyn StepCell<Ext>> {
+        self.stepper
+    }
+
+    fn discovery_prune(&self) -> Option<&DiscoveryPrune> {
+        None
+    }
 }
 
 pub struct DiscoveryContext<'ctx, 'coll, Ctx, Ext, Eff, C> {
@@ -97,6 +366,16 @@ pub struct DiscoveryContext<'ctx, 'coll, Ctx, Ext, Eff, C> {
     collection: &'ctx RefCell<&'coll mut C>,
     index: Option<ActionIdx>,
     cache: ContextCache<Ext, Eff>,
+    global_overrides: Option<&'ctx GlobalOverrides<Ext>>,
+    effect_dry_run: bool,
+    // probes and inactive evaluations don't collect the actions they reach
+    is_collecting: bool,
+    fuel: Option<Fuel>,
+    explain_stack: Option<ExplainStack<Ext, Eff>>,
+    diagnosis: Option<Diagnosis<Ext>>,
+    scratch: Option<&'ctx dyn ScratchCell>,
+    stepper: Option<&'ctx dyn StepCell<Ext>>,
+    prune: DiscoveryPrune,
 }
 
 impl<'ctx, 'coll, Ctx, Ext, Eff, C> Clone for DiscoveryContext<'ctx, 'coll, Ctx, Ext, Eff, C> {
@@ -107,6 +386,15 @@ impl<'ctx, 'coll, Ctx, Ext, Eff, C> Clone for DiscoveryContext<'ctx, 'coll, Ctx,
             collection: self.collection,
             index: self.index,
             cache: self.cache.clone(),
+            global_overrides: self.global_overrides,
+            effect_dry_run: self.effect_dry_run,
+            fuel: self.fuel.clone(),
+            explain_stack: self.explain_stack.clone(),
+            diagnosis: self.diagnosis.clone(),
+            scratch: self.scratch,
+            stepper: self.stepper,
+            is_collecting: self.is_collecting,
+            prune: self.prune.clone(),
         }
     }
 }
@@ -119,7 +407,22 @@ impl<'ctx, 'coll, Ctx, Ext, Eff, C> DiscoveryContext<'ctx, 'coll, Ctx, Ext, Eff,
         index: Option<ActionIdx>,
         cache: ContextCache<Ext, Eff>,
     ) -> Self {
-        Self { view, tree, collection, index, cache }
+        Self {
+            view,
+            tree,
+            collection,
+            index,
+            cache,
+            global_overrides: None,
+            effect_dry_run: false,
+            fuel: None,
+            explain_stack: None,
+            diagnosis: None,
+            scratch: None,
+            stepper: None,
+            is_collecting: true,
+            prune: DiscoveryPrune::default(),
+        }
     }
 
     pub fn from_context(
@@ -133,6 +436,15 @@ impl<'ctx, 'coll, Ctx, Ext, Eff, C> DiscoveryContext<'ctx, 'coll, Ctx, Ext, Eff,
             collection,
             index,
             cache: ctx.cache().clone(),
+            global_overrides: ctx.global_overrides(),
+            effect_dry_run: ctx.is_effect_dry_run(),
+            fuel: ctx.fuel().cloned(),
+            explain_stack: ctx.explain_stack().cloned(),
+            diagnosis: ctx.diagnosis().cloned(),
+            scratch: ctx.scratch(),
+            stepper: ctx.stepper(),
+            is_collecting: true,
+            prune: DiscoveryPrune::default(),
         }
     }
 }
@@ -155,7 +467,11 @@ where
     }
 
     fn to_inactive(&self) -> Self {
-        self.clone()
+        Self { is_collecting: false, ..self.clone() }
+    }
+
+    fn to_probe(&self) -> Self {
+        Self { cache: self.cache.to_probe(), is_collecting: false, ..self.clone() }
     }
 
     fn is_active(&self) -> bool {
@@ -164,16 +480,107 @@ where
 
     fn action(&self, action: Action<Ext, Eff>) -> Outcome<Ext, Eff> {
         if self.index.map_or(true, |index| index == action.index()) {
-            self.collection.borrow_mut().extend([action]);
+            if self.is_collecting {
+                self.collection.borrow_mut().extend([action]);
+            }
             Outcome::Success
         } else {
             Outcome::Failure
         }
     }
+
+    fn global_overrides(&self) -> Option<&GlobalOverrides<Ext>> {
+        self.global_overrides
+    }
+
+    fn is_effect_dry_run(&self) -> bool {
+        self.effect_dry_run
+    }
+
+    fn fuel(&self) -> Option<&Fuel> {
+        self.fuel.as_ref()
+    }
+
+    fn explain_stack(&self) -> Option<&ExplainStack<Ext, Eff>> {
+        self.explain_stack.as_ref()
+    }
+
+    fn diagnosis(&self) -> Option<&Diagnosis<Ext>> {
+        self.diagnosis.as_ref()
+    }
+
+    fn scratch(&self) -> Option<&dyn ScratchCell> {
+        self.scratch
+    }
+
+    fn stepper(&self) -> Option<&dyn StepCell<Ext>> {
+        self.stepper
+    }
+
+    fn discovery_prune(&self) -> Option<&DiscoveryPrune> {
+        Some(&self.prune)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+    pub evictions: usize,
+}
+
+/// A cache kept across evaluations. It has to be cleared whenever the evaluated state
+/// changes, and must only be used with a single tree, the one it was first used with.
+#[derive(derivative::Derivative)]
+#[derivative(Clone(bound=""), Default(bound=""))]
+pub struct PersistentCache<Ext, Eff> {
+    cache: ContextCache<Ext, Eff>,
+    tree: Rc<Cell<Option<u64>>>,
+}
+
+impl<Ext, Eff> PersistentCache<Ext, Eff> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&self) {
+        self.cache.lru.borrow_mut().clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.lru.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.cache.stats.get()
+    }
+
+    // `once:` outcomes still only last for a single evaluation
+    pub(super) fn context_cache(&self, tree: u64) -> ContextCache<Ext, Eff> {
+        let used_tree = self.tree.get().unwrap_or(tree);
+        assert_eq!(used_tree, tree, "cache must only be used with a single tree");
+        self.tree.set(Some(tree));
+        ContextCache { once: Rc::default(), ..self.cache.clone() }
+    }
 }
 
 pub struct ContextCache<Ext, Eff> {
     lru: Rc<RefCell<Vec<CacheLine<Ext, Eff>>>>,
+    stats: Rc<Cell<CacheStats>>,
+    verification: Option<Rc<Cell<bool>>>,
+    // kept outside of the LRU and its stats
+    once: OnceOutcomes<Ext, Eff>,
+}
+
+impl<Ext, Eff> ContextCache<Ext, Eff> {
+    /// Probes start with an empty cache, but share the `once:` outcomes of the evaluation.
+    pub fn to_probe(&self) -> Self {
+        Self { once: self.once.clone(), ..Self::default() }
+    }
 }
 
 impl<Ext, Eff> ContextCache<Ext, Eff>
@@ -181,6 +588,43 @@ where
     Ext: Clone + PartialEq,
     Eff: Clone,
 {
+    /// Condition hits are recomputed and compared with the cached outcome, to catch host
+    /// functions that aren't deterministic for a single view.
+    pub fn verifying() -> Self {
+        Self { verification: Some(Rc::default()), ..Self::default() }
+    }
+
+    /// Outcomes are kept for the whole evaluation, separately for every set of lexicals. A
+    /// directive reached again while it is still being evaluated fails.
+    pub fn once<F>(
+        &self,
+        id: u32,
+        is_active: bool,
+        lexicals: &[Value<Ext>],
+        calc_outcome: F,
+    ) -> Outcome<Ext, Eff>
+    where
+        F: FnOnce() -> Outcome<Ext, Eff>,
+    {
+        let key = (id, is_active);
+        let find = |lines: &[(Vec<Value<Ext>>, _)]| {
+            lines.iter().position(|(line_lexicals, _)| line_lexicals == lexicals)
+        };
+        if let Some(lines) = self.once.borrow().get(&key) {
+            if let Some(index) = find(lines) {
+                return lines[index].1.clone();
+            }
+        }
+        self.once.borrow_mut().entry(key).or_default().push((lexicals.into(), Outcome::Failure));
+        let outcome = calc_outcome();
+        let mut once = self.once.borrow_mut();
+        let lines = once.entry(key).or_default();
+        if let Some(index) = find(lines) {
+            lines[index].1 = outcome.clone();
+        }
+        outcome
+    }
+
     pub fn get<F>(
         &self,
         ref_index: RefIdx,
@@ -192,25 +636,65 @@ where
         F: FnOnce() -> Outcome<Ext, Eff>,
     {
         if let Some(index) = self.find(ref_index, arguments, is_active) {
+            self.update_stats(|stats| stats.hits += 1);
             let cl = self.lru.borrow_mut().remove(index);
             let outcome = cl.outcome.clone();
+            let is_complete = cl.is_complete;
             self.insert(cl);
+            if is_complete {
+                self.verify_hit(ref_index, &outcome, calc_outcome);
+            }
             outcome
         } else {
+            self.update_stats(|stats| stats.misses += 1);
             let mut cl = CacheLine {
                 index: ref_index,
                 is_active,
+                is_complete: false,
                 arguments: arguments.into(),
                 outcome: Outcome::Failure,
             };
             self.insert(cl.clone());
             let outcome = calc_outcome();
             cl.outcome = outcome.clone();
+            cl.is_complete = true;
             self.replace_or_insert(cl);
             outcome
         }
     }
 
+    pub fn stats(&self) -> CacheStats {
+        self.stats.get()
+    }
+
+    fn verify_hit<F>(&self, index: RefIdx, cached: &Outcome<Ext, Eff>, calc_outcome: F)
+    where
+        F: FnOnce() -> Outcome<Ext, Eff>,
+    {
+        let (Some(is_verifying), RefIdx::Cond(_)) = (&self.verification, index) else {
+            return;
+        };
+        // hits during the recomputation itself are taken as they are
+        if is_verifying.replace(true) {
+            return;
+        }
+        let is_success = calc_outcome().is_success();
+        is_verifying.set(false);
+        if is_success != cached.is_success() {
+            log::error!("Cached outcome of {index:?} diverged from its recomputation");
+            debug_assert!(false, "Cached outcome of {index:?} diverged from its recomputation");
+        }
+    }
+
+    fn update_stats<F>(&self, update: F)
+    where
+        F: FnOnce(&mut CacheStats),
+    {
+        let mut stats = self.stats.get();
+        update(&mut stats);
+        self.stats.set(stats);
+    }
+
     fn find(&self, index: RefIdx, arguments: &[Value<Ext>], is_active: bool) -> Option<usize> {
         self.lru.borrow().iter().position(|cl| {
             cl.index == index
@@ -222,6 +706,10 @@ where
     fn insert(&self, cl: CacheLine<Ext, Eff>) {
         let mut lru = self.lru.borrow_mut();
         lru.insert(0, cl);
+        let evicted = lru.len().saturating_sub(LRU_LEN);
+        if evicted > 0 {
+            self.update_stats(|stats| stats.evictions += evicted);
+        }
         lru.truncate(LRU_LEN);
     }
 
@@ -238,13 +726,23 @@ where
 
 impl<Ext, Eff> Default for ContextCache<Ext, Eff> {
     fn default() -> Self {
-        Self { lru: Rc::new(RefCell::new(Vec::with_capacity(LRU_LEN + 1))) }
+        Self {
+            lru: Rc::new(RefCell::new(Vec::with_capacity(LRU_LEN + 1))),
+            stats: Rc::default(),
+            verification: None,
+            once: Rc::default(),
+        }
     }
 }
 
 impl<Ext, Eff> Clone for ContextCache<Ext, Eff> {
     fn clone(&self) -> Self {
-        Self { lru: self.lru.clone() }
+        Self {
+            lru: self.lru.clone(),
+            stats: self.stats.clone(),
+            verification: self.verification.clone(),
+            once: self.once.clone(),
+        }
     }
 }
 
@@ -252,6 +750,7 @@ impl<Ext, Eff> Clone for ContextCache<Ext, Eff> {
 struct CacheLine<Ext, Eff> {
     index: RefIdx,
     is_active: bool,
+    is_complete: bool,
     arguments: Vec<Value<Ext>>,
     outcome: Outcome<Ext, Eff>,
 }
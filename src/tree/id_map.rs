@@ -1,10 +1,11 @@
 use std::collections::HashMap;
 
 use derivative::Derivative;
+use serde::{Deserialize, Serialize};
 use smol_str::SmolStr;
 
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct Index(usize);
 
 impl Index {
@@ -17,6 +18,7 @@ impl Index {
 #[derivative(Default(bound=""))]
 pub struct IdMap<N, D> {
     indices: HashMap<SmolStr, Index>,
+    names: Vec<SmolStr>,
     nodes: Vec<N>,
     data: Vec<D>,
 }
@@ -29,28 +31,55 @@ impl<N, D> IdMap<N, D> {
             index
         } else {
             let index = Index(self.nodes.len());
-            self.indices.insert(id, index);
+            self.indices.insert(id.clone(), index);
+            self.names.push(id);
             self.nodes.push(node);
             self.data.push(data);
             index
         }
     }
 
+    pub fn reserve(&mut self, additional: usize) {
+        self.indices.reserve(additional);
+        self.names.reserve(additional);
+        self.nodes.reserve(additional);
+        self.data.reserve(additional);
+    }
+
+    // aliases are only reachable by name, entries keep the original name
+    pub fn alias(&mut self, id: SmolStr, index: Index) {
+        self.indices.insert(id, index);
+    }
+
     pub fn indices(&self) -> impl Iterator<Item = Index> {
         (0..self.nodes.len()).into_iter().map(Index)
     }
 
+    pub fn entries(&self) -> impl Iterator<Item = (&SmolStr, &N, &D)> {
+        self.names.iter()
+            .zip(&self.nodes)
+            .zip(&self.data)
+            .map(|((name, node), data)| (name, node, data))
+    }
+
+    pub fn entries_eq<F>(&self, other: &Self, node_eq: F) -> bool
+    where
+        D: PartialEq,
+        F: Fn(&N, &N) -> bool,
+    {
+        self.nodes.len() == other.nodes.len()
+            && self.entries().zip(other.entries()).all(|((a_name, a_node, a_data), b)| {
+                let (b_name, b_node, b_data) = b;
+                a_name == b_name && a_data == b_data && node_eq(a_node, b_node)
+            })
+    }
+
     pub fn find(&self, id: &str) -> Option<Index> {
         self.indices.get(id).copied()
     }
 
     pub fn name(&self, index: Index) -> Option<&SmolStr> {
-        for (name, name_index) in &self.indices {
-            if index == *name_index {
-                return Some(name);
-            }
-        }
-        None
+        self.names.get(index.0)
     }
 
     #[track_caller]
@@ -1,6 +1,12 @@
 
+use std::any::{Any, TypeId};
+use std::future::Future;
+use std::ops::ControlFlow;
+use std::panic::RefUnwindSafe;
+use std::pin::Pin;
 use std::sync::Arc;
 
+use serde::{Deserialize, Serialize};
 use smol_str::SmolStr;
 
 use crate::BehaviorTree;
@@ -8,6 +14,7 @@ use crate::value::Value;
 
 use super::{Index, IdMap, KindError, ArityError};
 use super::outcome::{Outcome};
+use super::context::ScratchCell;
 use super::script::{ActionRoot, NodeRoot};
 
 pub type QueryFn<Ctx, Ext, Eff> = fn(
@@ -15,8 +22,26 @@ pub type QueryFn<Ctx, Ext, Eff> = fn(
     &[Value<Ext>],
     &mut dyn FnMut(&mut dyn Iterator<Item = Value<Ext>>) -> Outcome<Ext, Eff>,
 ) -> Outcome<Ext, Eff>;
+pub type EagerQueryFn<Ctx, Ext> = fn(&Ctx, &[Value<Ext>]) -> Vec<Value<Ext>>;
+pub type YieldQueryFn<Ctx, Ext> =
+    fn(&Ctx, &[Value<Ext>], &mut dyn FnMut(Value<Ext>) -> ControlFlow<()>);
+pub type QueryFuture<'a, Ext> = Pin<Box<dyn Future<Output = Vec<Value<Ext>>> + 'a>>;
+pub type AsyncQueryFn<Ctx, Ext> = for<'a> fn(&'a Ctx, &'a [Value<Ext>]) -> QueryFuture<'a, Ext>;
+pub type BlockOnFn<Ext> = for<'a> fn(QueryFuture<'a, Ext>) -> Vec<Value<Ext>>;
+/// Called with each queried value, then with `None` once the values are exhausted. A query
+/// ends with the first outcome broken out with, or succeeds if there is none.
+pub type QueryVisitFn<'a, Ext, Eff> =
+    dyn FnMut(Option<Value<Ext>>) -> ControlFlow<Outcome<Ext, Eff>> + 'a;
 pub type GlobalFn<Ctx, Ext> = fn(&Ctx) -> Value<Ext>;
+pub type GlobalCallFn<Ctx, Ext> = fn(&Ctx, &[Value<Ext>]) -> Value<Ext>;
 pub type EffectFn<Ctx, Ext, Eff> = fn(&Ctx, &[Value<Ext>]) -> Option<Eff>;
+/// Also receives the arguments of the enclosing action, if there is one.
+pub type ActionEffectFn<Ctx, Ext, Eff> =
+    fn(&Ctx, &[Value<Ext>], Option<&[Value<Ext>]>) -> Option<Eff>;
+pub type MultiEffectFn<Ctx, Ext, Eff> = fn(&Ctx, &[Value<Ext>]) -> Option<Vec<Eff>>;
+pub type StatefulEffectFn<Ctx, Ext, Eff, S> = fn(&Ctx, &[Value<Ext>], &mut S) -> Option<Eff>;
+type ErasedStatefulEffectFn<Ctx, Ext, Eff> =
+    dyn Fn(&Ctx, &[Value<Ext>], &mut dyn Any) -> Option<Eff> + Send + Sync + RefUnwindSafe;
 pub type CondFn<Ctx, Ext> = fn(&Ctx, &[Value<Ext>]) -> bool;
 pub type CustomFn<Ctx, Ext, Eff> = fn(
     &Ctx,
@@ -26,6 +51,146 @@ pub type CustomFn<Ctx, Ext, Eff> = fn(
     u64,
 ) -> Outcome<Ext, Eff>;
 pub type SeedFn<Ctx> = fn(&Ctx) -> u64;
+pub type FallbackFn<Ctx, Ext, Eff> = fn(&str, usize) -> Option<CustomFn<Ctx, Ext, Eff>>;
+
+#[derive(derivative::Derivative)]
+#[derivative(Clone(bound=""), Copy(bound=""))]
+pub enum QueryHandler<Ctx, Ext, Eff> {
+    Visit(QueryFn<Ctx, Ext, Eff>),
+    Eager(EagerQueryFn<Ctx, Ext>),
+    Yield(YieldQueryFn<Ctx, Ext>),
+    Blocking(AsyncQueryFn<Ctx, Ext>, BlockOnFn<Ext>),
+}
+
+impl<Ctx, Ext, Eff> QueryHandler<Ctx, Ext, Eff> {
+    pub fn query(
+        &self,
+        view: &Ctx,
+        arguments: &[Value<Ext>],
+        visit_fn: &mut QueryVisitFn<'_, Ext, Eff>,
+    ) -> Outcome<Ext, Eff> {
+        match self {
+            Self::Visit(query_fn) => {
+                query_fn(view, arguments, &mut |values| visit_query_values(values, visit_fn))
+            },
+            Self::Eager(query_fn) => {
+                visit_query_values(&mut query_fn(view, arguments).into_iter(), visit_fn)
+            },
+            Self::Yield(query_fn) => {
+                let mut outcome = None;
+                query_fn(view, arguments, &mut |value| {
+                    visit_fn(Some(value)).map_break(|result| outcome = Some(result))
+                });
+                outcome.unwrap_or_else(|| finish_query_visit(visit_fn))
+            },
+            Self::Blocking(query_fn, block_on) => {
+                let values = block_on(query_fn(view, arguments));
+                visit_query_values(&mut values.into_iter(), visit_fn)
+            },
+        }
+    }
+}
+
+pub fn visit_query_values<Ext, Eff>(
+    values: &mut dyn Iterator<Item = Value<Ext>>,
+    visit_fn: &mut QueryVisitFn<'_, Ext, Eff>,
+) -> Outcome<Ext, Eff> {
+    for value in values {
+        if let ControlFlow::Break(outcome) = visit_fn(Some(value)) {
+            return outcome;
+        }
+    }
+    finish_query_visit(visit_fn)
+}
+
+fn finish_query_visit<Ext, Eff>(visit_fn: &mut QueryVisitFn<'_, Ext, Eff>) -> Outcome<Ext, Eff> {
+    match visit_fn(None) {
+        ControlFlow::Break(outcome) => outcome,
+        ControlFlow::Continue(()) => Outcome::Success,
+    }
+}
+
+#[derive(derivative::Derivative)]
+#[derivative(Clone(bound=""), Copy(bound=""))]
+pub enum GlobalHandler<Ctx, Ext> {
+    Value(GlobalFn<Ctx, Ext>),
+    Call(GlobalCallFn<Ctx, Ext>),
+}
+
+impl<Ctx, Ext> GlobalHandler<Ctx, Ext> {
+    pub fn get(&self, view: &Ctx, arguments: &[Value<Ext>]) -> Value<Ext> {
+        match self {
+            Self::Value(global_fn) => global_fn(view),
+            Self::Call(global_fn) => global_fn(view, arguments),
+        }
+    }
+}
+
+#[derive(derivative::Derivative)]
+#[derivative(Clone(bound=""))]
+pub enum EffectHandler<Ctx, Ext, Eff> {
+    Plain(EffectFn<Ctx, Ext, Eff>),
+    Action(ActionEffectFn<Ctx, Ext, Eff>),
+    Multi(MultiEffectFn<Ctx, Ext, Eff>),
+    Stateful(TypeId, Arc<ErasedStatefulEffectFn<Ctx, Ext, Eff>>),
+}
+
+impl<Ctx, Ext, Eff> EffectHandler<Ctx, Ext, Eff> {
+    pub fn stateful<S>(effect_fn: StatefulEffectFn<Ctx, Ext, Eff, S>) -> Self
+    where
+        Ctx: 'static,
+        Ext: 'static,
+        Eff: 'static,
+        S: Any,
+    {
+        Self::Stateful(TypeId::of::<S>(), Arc::new(move |view, arguments, scratch| {
+            effect_fn(view, arguments, scratch.downcast_mut()?)
+        }))
+    }
+
+    pub fn scratch_type(&self) -> Option<TypeId> {
+        match self {
+            Self::Stateful(scratch_type, _) => Some(*scratch_type),
+            _ => None,
+        }
+    }
+
+    /// Returns `false` without extending `effects` if the handler failed. Stateful handlers
+    /// always fail without a scratch of their type.
+    pub fn call<E>(
+        &self,
+        view: &Ctx,
+        arguments: &[Value<Ext>],
+        action_arguments: Option<&[Value<Ext>]>,
+        scratch: Option<&dyn ScratchCell>,
+        effects: &mut E,
+    ) -> bool
+    where
+        E: Extend<Eff>,
+    {
+        match self {
+            Self::Plain(effect_fn) => effect_fn(view, arguments).map(|effect| {
+                effects.extend([effect]);
+            }),
+            Self::Action(effect_fn) => effect_fn(view, arguments, action_arguments).map(|effect| {
+                effects.extend([effect]);
+            }),
+            Self::Multi(effect_fn) => effect_fn(view, arguments).map(|multi| {
+                effects.extend(multi);
+            }),
+            Self::Stateful(scratch_type, effect_fn) => {
+                let Some(scratch) = scratch.filter(|scratch| scratch.accepts(*scratch_type)) else {
+                    return false;
+                };
+                let mut effect = None;
+                scratch.with_scratch(&mut |scratch| effect = effect_fn(view, arguments, scratch));
+                effect.map(|effect| {
+                    effects.extend([effect]);
+                })
+            },
+        }.is_some()
+    }
+}
 
 macro_rules! generate {
     {
@@ -35,7 +200,9 @@ macro_rules! generate {
         $(,)?
     } => {
         $(
-            #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+            #[derive(
+                Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+            )]
             pub struct $index(Index);
 
             impl $index {
@@ -81,6 +248,25 @@ macro_rules! generate {
             )*
         }
 
+        impl Kinds {
+            pub fn all() -> Self {
+                [$(Kind::$kind),*].into()
+            }
+        }
+
+        impl FromIterator<Kind> for Kinds {
+            fn from_iter<I>(iter: I) -> Self
+            where
+                I: IntoIterator<Item = Kind>,
+            {
+                let given: Vec<Kind> = iter.into_iter().collect();
+                let Some(&first) = given.first() else {
+                    return Self::default();
+                };
+                [$(if given.contains(&Kind::$kind) { Kind::$kind } else { first }),*].into()
+            }
+        }
+
         impl std::fmt::Display for Kind {
             fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
                 match self {
@@ -108,22 +294,53 @@ macro_rules! generate {
                 )*
                 None
             }
+
+            pub fn reserve(&mut self, additional: usize) {
+                $(
+                    self.$field.reserve(additional);
+                )*
+            }
+
+            pub fn alias(&mut self, id: SmolStr, existing: &str) -> Option<Kind> {
+                $(
+                    if let Some(index) = self.$field.find(existing) {
+                        self.$field.alias(id, index);
+                        return Some(Kind::$kind);
+                    }
+                )*
+                None
+            }
         }
     };
 }
 
 generate! {
-    globals: Global/GlobalIdx (GlobalFn<Ctx, Ext>, usize) => "a global",
-    effects: Effect/EffectIdx (EffectFn<Ctx, Ext, Eff>, usize) => "an effect",
+    globals: Global/GlobalIdx (GlobalHandler<Ctx, Ext>, usize) => "a global",
+    effects: Effect/EffectIdx (EffectHandler<Ctx, Ext, Eff>, usize) => "an effect",
     conditions: Cond/CondIdx (CondFn<Ctx, Ext>, usize) => "a condition",
     customs: Custom/CustomIdx (CustomFn<Ctx, Ext, Eff>, usize) => "a custom node",
     seeds: Seed/SeedIdx (SeedFn<Ctx>, usize) => "an rng seed",
-    queries: Query/QueryIdx (QueryFn<Ctx, Ext, Eff>, usize) => "a query",
+    queries: Query/QueryIdx (QueryHandler<Ctx, Ext, Eff>, usize) => "a query",
     action_roots: Action/ActionIdx (Arc<ActionRoot<Ext>>, usize) => "an action",
     node_roots: Node/NodeIdx (Arc<NodeRoot<Ext>>, usize) => "a node",
 }
 
 impl<Ctx, Ext, Eff> IdSpace<Ctx, Ext, Eff> {
+    // host hooks are compared by name and arity only
+    pub fn structural_eq(&self, other: &Self) -> bool
+    where
+        Ext: PartialEq,
+    {
+        self.globals.entries_eq(&other.globals, |_, _| true)
+            && self.effects.entries_eq(&other.effects, |_, _| true)
+            && self.conditions.entries_eq(&other.conditions, |_, _| true)
+            && self.customs.entries_eq(&other.customs, |_, _| true)
+            && self.seeds.entries_eq(&other.seeds, |_, _| true)
+            && self.queries.entries_eq(&other.queries, |_, _| true)
+            && self.action_roots.entries_eq(&other.action_roots, |a, b| a.structural_eq(b))
+            && self.node_roots.entries_eq(&other.node_roots, |a, b| a.structural_eq(b))
+    }
+
     pub fn contains<Idx>(&self, name: &str) -> bool
     where
         Idx: IdSpaceIndex<Ctx, Ext, Eff>,
@@ -131,6 +348,13 @@ impl<Ctx, Ext, Eff> IdSpace<Ctx, Ext, Eff> {
         Idx::id_map(self).find(name).is_some()
     }
 
+    pub fn find<Idx>(&self, name: &str) -> Option<Idx>
+    where
+        Idx: IdSpaceIndex<Ctx, Ext, Eff>,
+    {
+        Idx::id_map(self).find(name).map(Into::into)
+    }
+
     pub fn resolve_ref(&self, name: &str, given: usize) -> Result<RefIdx, IdError> {
         match self.kind(name) {
             Some(kind) => match kind {
@@ -161,6 +385,18 @@ impl<Ctx, Ext, Eff> IdSpace<Ctx, Ext, Eff> {
         }
     }
 
+    pub fn parameters(&self, name: &str) -> Result<&[SmolStr], IdError> {
+        if let Some(index) = NodeIdx::id_map(self).find(name) {
+            Ok(&NodeIdx::id_map(self).node(index).parameters)
+        } else if let Some(index) = ActionIdx::id_map(self).find(name) {
+            Ok(&ActionIdx::id_map(self).node(index).parameters)
+        } else if let Some(given) = self.kind(name) {
+            Err(IdError::Kind(KindError { expected: [Kind::Node, Kind::Action].into(), given }))
+        } else {
+            Err(IdError::Unknown)
+        }
+    }
+
     #[track_caller]
     pub fn action_name(&self, action: ActionIdx) -> &SmolStr {
         ActionIdx::id_map(self).name(action.into()).expect("action must be valid in this tree")
@@ -220,6 +456,10 @@ impl<Ctx, Ext, Eff> IdSpace<Ctx, Ext, Eff> {
 }
 
 impl Kinds {
+    pub fn contains(&self, kind: Kind) -> bool {
+        self.into_iter().any(|contained| contained == kind)
+    }
+
     pub fn display_connected(&self, connect: &'static str) -> KindsDisplay {
         KindsDisplay { kinds: *self, connect }
     }
@@ -249,7 +489,7 @@ impl std::fmt::Display for KindsDisplay {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum RefIdx {
     Action(ActionIdx),
     Node(NodeIdx),
@@ -257,6 +497,13 @@ pub enum RefIdx {
     Custom(CustomIdx),
 }
 
+impl RefIdx {
+    // conditions can't emit actions, so their outcome doesn't depend on the activity
+    pub fn is_activity_invariant(&self) -> bool {
+        matches!(self, Self::Cond(_))
+    }
+}
+
 pub trait IdSpaceIndex<Ctx, Ext, Eff>: From<Index> + Into<Index> {
     type Node;
 
@@ -272,7 +519,7 @@ pub enum IdError {
     #[error("Unknown identifier")]
     Unknown,
     #[error("Invalid kind: {_0}")]
-    Kind(KindError),
+    Kind(#[from] KindError),
     #[error("Wrong arity: {_0}")]
-    Arity(ArityError),
+    Arity(#[from] ArityError),
 }
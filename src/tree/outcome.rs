@@ -1,6 +1,10 @@
+use std::collections::HashMap;
+use std::hash::Hash;
 use std::sync::Arc;
 
 use derivative::Derivative;
+use ordered_float::OrderedFloat;
+use smol_str::SmolStr;
 
 use crate::value::{Value, Values};
 
@@ -12,6 +16,7 @@ use super::id_space::ActionIdx;
 pub enum Outcome<Ext, Eff> {
     Success,
     Failure,
+    Running,
     Action(Action<Ext, Eff>),
 }
 
@@ -32,6 +37,14 @@ impl<Ext, Eff> Outcome<Ext, Eff> {
         !self.is_failure()
     }
 
+    pub fn is_running(&self) -> bool {
+        matches!(self, Self::Running)
+    }
+
+    pub fn is_non_running(&self) -> bool {
+        !self.is_running()
+    }
+
     pub fn is_action(&self) -> bool {
         matches!(self, Self::Action(_))
     }
@@ -47,6 +60,28 @@ impl<Ext, Eff> Outcome<Ext, Eff> {
             None
         }
     }
+
+    pub fn each_effect<F>(&self, effect_fn: F)
+    where
+        F: FnMut(&Eff),
+    {
+        if let Self::Action(action) = self {
+            action.for_each_effect(effect_fn);
+        }
+    }
+
+    pub fn effect_count(&self) -> usize {
+        self.effects().map_or(0, <[Eff]>::len)
+    }
+
+    /// Successes and actions are `Ok`, failures and running outcomes are returned as errors.
+    pub fn into_result(self) -> Result<Option<Action<Ext, Eff>>, Self> {
+        match self {
+            Self::Success => Ok(None),
+            Self::Action(action) => Ok(Some(action)),
+            other @ (Self::Failure | Self::Running) => Err(other),
+        }
+    }
 }
 
 impl<Ext, Eff> From<bool> for Outcome<Ext, Eff> {
@@ -59,17 +94,33 @@ impl<Ext, Eff> From<bool> for Outcome<Ext, Eff> {
     }
 }
 
+impl<Ext, Eff> From<Result<Option<Action<Ext, Eff>>, ()>> for Outcome<Ext, Eff> {
+    fn from(result: Result<Option<Action<Ext, Eff>>, ()>) -> Self {
+        match result {
+            Ok(None) => Self::Success,
+            Ok(Some(action)) => Self::Action(action),
+            Err(()) => Self::Failure,
+        }
+    }
+}
+
 #[derive(Derivative, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[derivative(Clone(bound=""))]
 pub struct Action<Ext, Eff> {
     index: ActionIdx,
     arguments: Values<Ext>,
     effects: Arc<[Eff]>,
+    score: OrderedFloat<f32>,
 }
 
 impl<Ext, Eff> Action<Ext, Eff> {
-    pub(super) fn new(index: ActionIdx, arguments: Values<Ext>, effects: Arc<[Eff]>) -> Self {
-        Self { index, arguments, effects }
+    pub(super) fn new(
+        index: ActionIdx,
+        arguments: Values<Ext>,
+        effects: Arc<[Eff]>,
+        score: f32,
+    ) -> Self {
+        Self { index, arguments, effects, score: OrderedFloat(score) }
     }
 
     pub(super) fn index(&self) -> ActionIdx {
@@ -83,5 +134,84 @@ impl<Ext, Eff> Action<Ext, Eff> {
     pub fn effects(&self) -> &[Eff] {
         &self.effects
     }
+
+    /// Given by the action's `score:` section, or zero without one.
+    pub fn score(&self) -> f32 {
+        self.score.0
+    }
+
+    pub fn effects_eq_unordered(&self, other: &Self) -> bool
+    where
+        Eff: Eq + Hash,
+    {
+        if self.effects.len() != other.effects.len() {
+            return false;
+        }
+        let mut counts = HashMap::<&Eff, isize>::new();
+        for effect in self.effects.iter() {
+            *counts.entry(effect).or_default() += 1;
+        }
+        for effect in other.effects.iter() {
+            *counts.entry(effect).or_default() -= 1;
+        }
+        counts.values().all(|count| *count == 0)
+    }
+
+    pub fn for_each_effect<F>(&self, effect_fn: F)
+    where
+        F: FnMut(&Eff),
+    {
+        self.effects.iter().for_each(effect_fn);
+    }
+}
+
+pub type ExplainedOutcome<Ext, Eff> = (Outcome<Ext, Eff>, Explanation<Ext, Eff>);
+
+/// One evaluated ref, with the refs it evaluated in turn as children. Inline directives like
+/// `select:` aren't refs, their evaluated refs become children of the enclosing ref.
+#[derive(Derivative, Debug, PartialEq)]
+#[derivative(Clone(bound=""))]
+pub struct Explanation<Ext, Eff> {
+    pub ref_name: SmolStr,
+    pub arguments: Values<Ext>,
+    pub outcome: Outcome<Ext, Eff>,
+    pub children: Vec<Explanation<Ext, Eff>>,
+}
+
+/// A ref that is about to be evaluated. The root has a depth of zero.
+#[derive(Debug)]
+pub struct Step<'a, Ext> {
+    pub name: &'a SmolStr,
+    pub arguments: &'a [Value<Ext>],
+    pub depth: usize,
+}
+
+impl<'a, Ext> Clone for Step<'a, Ext> {
+    fn clone(&self) -> Self {
+        *self
+    }
 }
 
+impl<'a, Ext> Copy for Step<'a, Ext> {}
+
+#[derive(Derivative, Debug, PartialEq)]
+#[derivative(Clone(bound=""))]
+pub struct FailedCondition<Ext> {
+    pub name: SmolStr,
+    pub arguments: Values<Ext>,
+}
+
+/// Recorded for a `select:` or `for-any` that failed, with the last condition that failed in
+/// each of its branches.
+#[derive(Derivative, Debug, PartialEq)]
+#[derivative(Clone(bound=""))]
+pub struct SelectionFailure<Ext> {
+    pub branches: Vec<Option<FailedCondition<Ext>>>,
+}
+
+#[derive(Derivative, Debug, PartialEq)]
+#[derivative(Clone(bound=""))]
+pub struct DiagnosedOutcome<Ext, Eff> {
+    pub outcome: Outcome<Ext, Eff>,
+    pub failures: Vec<SelectionFailure<Ext>>,
+}
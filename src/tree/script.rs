@@ -1,5 +1,6 @@
 
-use std::path::Path;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 pub use runtime::*;
@@ -26,4 +27,100 @@ impl ScriptSource {
     pub fn from_named(name: &str, content: Box<str>) -> Self {
         Self::Str { name: name.into(), content }
     }
+
+    /// The hash is stable across runs. For paths it covers the names and contents of all
+    /// script files in the directory. Files pulled in by `include:` are covered as well,
+    /// wherever they are. Entries that can't be read are hashed as unreadable, so they only
+    /// change the hash when they become readable again, and compiling reports them.
+    pub fn content_hash(&self) -> u64 {
+        let mut hash = ContentHash::default();
+        let mut included = HashSet::new();
+        match self {
+            Self::Path { path } => {
+                for entry in walkdir::WalkDir::new(path).sort_by_file_name() {
+                    let Ok(entry) = entry else {
+                        hash.write_unreadable();
+                        continue;
+                    };
+                    let is_script = entry.path().extension().is_some_and(|ext| ext == "rea");
+                    if entry.file_type().is_file() && is_script {
+                        let name = entry.path().strip_prefix(path).unwrap_or(entry.path());
+                        hash.write(name.to_string_lossy().as_bytes());
+                        match std::fs::read(entry.path()) {
+                            Ok(content) => {
+                                hash.write(&content);
+                                hash.write_includes(&content, entry.path().parent(), &mut included);
+                            },
+                            Err(_) => hash.write_unreadable(),
+                        }
+                    }
+                }
+            },
+            Self::Str { content, .. } => {
+                hash.write(content.as_bytes());
+                hash.write_includes(content.as_bytes(), None, &mut included);
+            },
+        }
+        hash.0
+    }
+
+    /// Whether the source has to be recompiled, given the hash it was last compiled with.
+    pub fn is_changed(&self, prev_hash: u64) -> bool {
+        self.content_hash() != prev_hash
+    }
+}
+
+// FNV-1a, with a separator after every written chunk
+struct ContentHash(u64);
+
+impl Default for ContentHash {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl ContentHash {
+    fn write(&mut self, bytes: &[u8]) {
+        self.write_bytes(bytes.iter().chain([&0xff]));
+    }
+
+    // includes are found like the compiler does, as root level directives relative to `base`,
+    // and every included file is only hashed the first time it is reached
+    fn write_includes(&mut self, content: &[u8], base: Option<&Path>, seen: &mut HashSet<PathBuf>) {
+        for line in String::from_utf8_lossy(content).lines() {
+            let Some(include) = line.strip_prefix("include:").map(str::trim) else {
+                continue;
+            };
+            let include = include.strip_prefix('"')
+                .and_then(|include| include.strip_suffix('"'))
+                .unwrap_or(include);
+            self.write(include.as_bytes());
+            let Ok(path) = base.unwrap_or(Path::new("")).join(include).canonicalize() else {
+                self.write_unreadable();
+                continue;
+            };
+            if !seen.insert(path.clone()) {
+                continue;
+            }
+            match std::fs::read(&path) {
+                Ok(content) => {
+                    self.write(&content);
+                    self.write_includes(&content, path.parent(), seen);
+                },
+                Err(_) => self.write_unreadable(),
+            }
+        }
+    }
+
+    // 0xfe can't follow the separator of a written chunk
+    fn write_unreadable(&mut self) {
+        self.write_bytes([&0xfe]);
+    }
+
+    fn write_bytes<'a>(&mut self, bytes: impl IntoIterator<Item = &'a u8>) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
 }
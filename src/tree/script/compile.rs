@@ -1,15 +1,16 @@
-use std::collections::HashMap;
+use std::collections::HashSet;
+use std::path::Path;
 use std::sync::Arc;
 
 use smol_str::SmolStr;
-use src_ctx::{SourceMap, LoadError, ContextError, SourceError, SourceIndex, Origin};
-use treelang::{Indent, Node as ScriptNode, ParseError, Tree};
+use src_ctx::{SourceMap, LoadError, ContextError, SourceError, SourceIndex, Origin, Span};
+use treelang::{Indent, Node as ScriptNode, ParseError, Tree, Item, ItemKind};
 
 use crate::gen::enum_class;
-use crate::tree::ArityError;
-use crate::tree::id_space::{IdSpace, NodeIdx, ActionIdx, IdError};
+use crate::tree::{ArityError, External};
+use crate::tree::id_space::{IdSpace, NodeIdx, ActionIdx, CustomIdx, IdError, FallbackFn};
 
-use super::{ScriptSource, ActionRoot, NodeRoot};
+use super::{ScriptSource, ActionRoot, NodeRoot, Location};
 
 use parse::*;
 use produce::*;
@@ -30,9 +31,23 @@ pub enum CompileError {
     Conflict(#[from] ContextError<ConflictError>),
     #[error("Multiple definitions of named source `{name}`")]
     NamedSourceConflict { name: Arc<str> },
+    #[error("Unable to read `{}`: {error}", path.display())]
+    Read { path: Arc<Path>, error: Arc<std::io::Error> },
+    #[error("Cyclic include of `{}`", path.display())]
+    IncludeCycle { path: Arc<Path> },
 }
 
 impl CompileError {
+    pub fn phase(&self) -> CompilePhase {
+        match self {
+            Self::Load(_) | Self::Read { .. } | Self::IncludeCycle { .. } => {
+                CompilePhase::Load
+            },
+            Self::Script(error) => error.error().phase(),
+            Self::Conflict(_) | Self::NamedSourceConflict { .. } => CompilePhase::Conflict,
+        }
+    }
+
     pub fn display_with_context(&self) -> impl std::fmt::Display + '_ {
         struct FullDisplay<'a>(&'a CompileError);
         impl<'a> std::fmt::Display for FullDisplay<'a> {
@@ -41,14 +56,108 @@ impl CompileError {
                     CompileError::Load(error) => writeln!(f, "error: {error}"),
                     CompileError::Script(error) => error.display_with_context().fmt(f),
                     CompileError::Conflict(error) => error.display_with_context().fmt(f),
-                    CompileError::NamedSourceConflict { .. } => writeln!(f, "error: {self}"),
+                    CompileError::NamedSourceConflict { name } => {
+                        writeln!(f, "error: Multiple definitions of named source `{name}`")
+                    },
+                    CompileError::Read { path, error } => {
+                        writeln!(f, "error: Unable to read `{}`: {error}", path.display())
+                    },
+                    CompileError::IncludeCycle { path } => {
+                        writeln!(f, "error: Cyclic include of `{}`", path.display())
+                    },
                 }
             }
         }
         FullDisplay(self)
     }
+
+    /// One diagnostic per source location of the error, with the last being the error itself
+    /// and earlier ones notes, like the first of two conflicting definitions. Locations whose
+    /// source text isn't provided are skipped. Errors without any location yield a single
+    /// diagnostic without an origin.
+    pub fn to_diagnostics<F, S>(&self, source_text: F) -> Vec<Diagnostic>
+    where
+        F: Fn(&Origin) -> Option<S>,
+        S: AsRef<str>,
+    {
+        let message = self.to_string();
+        let origins = match self {
+            Self::Script(error) => &error.origins[..],
+            Self::Conflict(error) => &error.origins[..],
+            _ => &[],
+        };
+        if origins.is_empty() {
+            return vec![Diagnostic {
+                message,
+                severity: DiagnosticSeverity::Error,
+                label: None,
+                origin: None,
+                start: (0, 0),
+                end: (0, 0),
+            }];
+        }
+        origins.iter().enumerate()
+            .filter_map(|(index, origin)| {
+                let text = source_text(&origin.origin)?;
+                let text = text.as_ref();
+                Some(Diagnostic {
+                    message: message.clone(),
+                    severity: if index + 1 == origins.len() {
+                        DiagnosticSeverity::Error
+                    } else {
+                        DiagnosticSeverity::Note
+                    },
+                    label: Some(origin.label.clone()),
+                    origin: Some(origin.origin.clone()),
+                    start: line_column(text, origin.span.start),
+                    end: line_column(text, origin.span.end),
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DiagnosticSeverity {
+    Error,
+    Note,
+}
+
+/// Positions are zero-based `(line, column)` pairs, with columns counted in characters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub severity: DiagnosticSeverity,
+    pub label: Option<String>,
+    pub origin: Option<Origin>,
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+}
+
+fn line_column(text: &str, offset: usize) -> (usize, usize) {
+    let before = text.get(..offset).unwrap_or(text);
+    let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+    (before.matches('\n').count(), before[line_start..].chars().count())
+}
+
+fn read_error(path: &Path, error: std::io::Error) -> CompileError {
+    CompileError::Read { path: path.into(), error: Arc::new(error) }
+}
+
+fn canonical_path(path: &Path) -> CompileResult<Arc<Path>> {
+    path.canonicalize().map(Into::into).map_err(|error| read_error(path, error))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CompilePhase {
+    Load,
+    Syntax,
+    Resolution,
+    Conflict,
 }
 
+pub const DEFAULT_MAX_NESTING: usize = 64;
+
 pub type ScriptResult<T = ()> = Result<T, SourceError<ScriptError>>;
 
 #[derive(Debug, Clone, thiserror::Error)]
@@ -65,6 +174,10 @@ pub enum ScriptError {
     InvalidRefDeclaration,
     #[error("Invalid root declaration")]
     InvalidRootDeclaration,
+    #[error("Invalid documentation text")]
+    InvalidDoc,
+    #[error("Expected a single path to include")]
+    InvalidInclude,
     #[error("Invalid query reference")]
     InvalidQueryRef,
     #[error("Invalid effect reference")]
@@ -79,6 +192,12 @@ pub enum ScriptError {
     InvalidCondNode,
     #[error("Invalid condition node after `else` clause")]
     InvalidCondNodeAfterElse,
+    #[error("Expected `try` and `default` fallback nodes")]
+    InvalidFallbackNode,
+    #[error("Values or patterns nested deeper than {limit} levels")]
+    NestingTooDeep { limit: usize },
+    #[error("Empty `{keyword}` block")]
+    EmptyBlock { keyword: &'static str },
     #[error("Variable `{name}` shadows existing lexical")]
     ShadowedLexical { name: SmolStr },
     #[error("Variable `{name}` shadows existing global")]
@@ -95,6 +214,41 @@ pub enum ScriptError {
     UnrecognizedNode,
     #[error("Unrecognized action directive")]
     UnrecognizedActionDirective,
+    #[error("Duplicate `{keyword}` action section")]
+    DuplicateActionSection { keyword: &'static str },
+}
+
+impl ScriptError {
+    pub fn phase(&self) -> CompilePhase {
+        match self {
+            Self::Parse(_)
+            | Self::DirectiveSignatureArity { .. }
+            | Self::DirectiveArgumentArity { .. }
+            | Self::PatternArity { .. }
+            | Self::InvalidRefDeclaration
+            | Self::InvalidRootDeclaration
+            | Self::InvalidDoc
+            | Self::InvalidInclude
+            | Self::InvalidSwitchCase
+            | Self::InvalidCondNode
+            | Self::InvalidCondNodeAfterElse
+            | Self::InvalidFallbackNode
+            | Self::NestingTooDeep { .. }
+            | Self::EmptyBlock { .. }
+            | Self::UnrecognizedPattern
+            | Self::UnrecognizedValue
+            | Self::UnrecognizedNode
+            | Self::UnrecognizedActionDirective
+            | Self::DuplicateActionSection { .. } => CompilePhase::Syntax,
+            Self::InvalidQueryRef
+            | Self::InvalidEffectRef
+            | Self::InvalidActionRef
+            | Self::InvalidSeedRef
+            | Self::UnboundVariable { .. }
+            | Self::Identifier { .. } => CompilePhase::Resolution,
+            Self::ShadowedLexical { .. } | Self::ShadowedGlobal { .. } => CompilePhase::Conflict,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
@@ -113,30 +267,87 @@ impl ConflictError {
 pub struct Compiler<Ctx, Ext, Eff> {
     ids: IdSpace<Ctx, Ext, Eff>,
     indent: Indent,
+    fallback: Option<FallbackFn<Ctx, Ext, Eff>>,
+    allow_identical_redefinition: bool,
+    allow_shadowing: bool,
+    max_nesting: usize,
     sources: SourceMap,
+    included: HashSet<Arc<Path>>,
+    include_stack: Vec<Arc<Path>>,
     action_root_placeholder: Arc<ActionRoot<Ext>>,
     node_root_placeholder: Arc<NodeRoot<Ext>>,
-    declarations: HashMap<SmolStr, Registered>,
+    declarations: Vec<Registered>,
 }
 
 struct Registered {
+    name: SmolStr,
     index: Root<NodeIdx, ActionIdx>,
     decl: Decl,
+    source: SourceText,
+}
+
+// kept with every declaration so compiled nodes can refer to their script locations
+#[derive(Clone)]
+struct SourceText {
+    origin: Arc<str>,
+    text: Arc<str>,
+}
+
+impl SourceText {
+    fn location(&self, span: Span) -> Location {
+        let (line, column) = line_column(&self.text, span.start);
+        Location { origin: self.origin.clone(), line: line + 1, column: column + 1 }
+    }
 }
 
 impl<Ctx, Ext, Eff> Compiler<Ctx, Ext, Eff> {
-    pub fn new(ids: IdSpace<Ctx, Ext, Eff>, indent: Indent) -> Self {
+    pub fn new(
+        ids: IdSpace<Ctx, Ext, Eff>,
+        indent: Indent,
+        fallback: Option<FallbackFn<Ctx, Ext, Eff>>,
+    ) -> Self {
         Self {
             ids,
             indent,
+            fallback,
+            allow_identical_redefinition: false,
+            allow_shadowing: false,
+            max_nesting: DEFAULT_MAX_NESTING,
             sources: SourceMap::new(),
+            included: HashSet::new(),
+            include_stack: Vec::new(),
             action_root_placeholder: Arc::default(),
             node_root_placeholder: Arc::default(),
-            declarations: HashMap::new(),
+            declarations: Vec::new(),
         }
     }
 
-    fn insert_node(&mut self, node: ScriptNode) -> CompileResult {
+    // the estimate is reserved for both node and action declarations
+    pub fn with_capacity(
+        mut ids: IdSpace<Ctx, Ext, Eff>,
+        indent: Indent,
+        fallback: Option<FallbackFn<Ctx, Ext, Eff>>,
+        estimated_decls: usize,
+    ) -> Self {
+        ids.reserve(estimated_decls);
+        let mut compiler = Self::new(ids, indent, fallback);
+        compiler.declarations.reserve(estimated_decls);
+        compiler
+    }
+
+    pub fn allow_identical_redefinition(&mut self, allow: bool) {
+        self.allow_identical_redefinition = allow;
+    }
+
+    pub fn allow_shadowing(&mut self, allow: bool) {
+        self.allow_shadowing = allow;
+    }
+
+    pub fn max_nesting(&mut self, limit: usize) {
+        self.max_nesting = limit;
+    }
+
+    fn insert_node(&mut self, node: ScriptNode, source: &SourceText) -> CompileResult {
         let decl = parse_root_declaration(&node)
             .map_err(|error| error.into_context_error(&self.sources))?;
         let name = decl.name.value.to_smol_str();
@@ -150,18 +361,31 @@ impl<Ctx, Ext, Eff> Compiler<Ctx, Ext, Eff> {
                 let placeholder = self.action_root_placeholder.clone();
                 self.ids.set::<ActionIdx>(name.clone(), placeholder, arity)
             })
-            .lift()
-            .map_err(|_| self.analyze_conflict(&decl))?;
-        self.declarations.insert(name, Registered {
+            .lift();
+        let index = match index {
+            Ok(index) => index,
+            Err(_) if self.is_identical_redefinition(&decl) => return Ok(()),
+            Err(_) => return Err(self.analyze_conflict(&decl)),
+        };
+        self.declarations.push(Registered {
+            name,
             index,
             decl: decl.into_inner(),
+            source: source.clone(),
         });
         Ok(())
     }
 
+    fn is_identical_redefinition(&self, decl: &Root<Decl>) -> bool {
+        let name = decl.name.to_smol_str();
+        self.allow_identical_redefinition && self.declarations.iter().any(|reg_decl| {
+            reg_decl.name == name && is_same_node(&reg_decl.decl.node, &decl.node)
+        })
+    }
+
     fn analyze_conflict(&self, decl: &Root<Decl>) -> CompileError {
         let name = decl.name.to_smol_str();
-        let prev = self.declarations.get(&name);
+        let prev = self.declarations.iter().find(|reg_decl| reg_decl.name == name);
         let error = ConflictError { symbol: name, is_internal: prev.is_none() };
         let mut origins = Vec::new();
         origins.push(self.sources.context_error_origin(
@@ -179,40 +403,122 @@ impl<Ctx, Ext, Eff> Compiler<Ctx, Ext, Eff> {
         CompileError::Conflict(ContextError::with_origins(error, origins))
     }
 
-    fn parse(&mut self, index: SourceIndex) -> CompileResult {
+    // includes are resolved relative to `base`, or the working directory without one
+    fn parse(
+        &mut self,
+        index: SourceIndex,
+        source: &SourceText,
+        base: Option<&Path>,
+    ) -> CompileResult {
         let input = self.sources.input(index);
         let tree = Tree::parse(input, self.indent)
             .map_err(|error| error.map(ScriptError::Parse).into_context_error(&self.sources))?;
         for node in tree.roots {
-            self.insert_node(node)?;
+            let include = try_parse_include_directive(&node)
+                .map_err(|error| error.into_context_error(&self.sources))?;
+            match include {
+                Some(path) => self.include(&base.unwrap_or(Path::new("")).join(path))?,
+                None => self.insert_node(node, source)?,
+            }
         }
         Ok(())
     }
 
+    // every file is only loaded once, including it again while it is loading is a cycle
+    fn include(&mut self, path: &Path) -> CompileResult {
+        let path = canonical_path(path)?;
+        if self.include_stack.contains(&path) {
+            return Err(CompileError::IncludeCycle { path });
+        }
+        if !self.included.insert(path.clone()) {
+            return Ok(());
+        }
+        self.load_file(path)
+    }
+
+    fn load_file(&mut self, path: Arc<Path>) -> CompileResult {
+        let content = std::fs::read_to_string(&path).map_err(|error| read_error(&path, error))?;
+        let name: Arc<str> = path.to_string_lossy().into();
+        let source = SourceText { origin: name.clone(), text: content.into() };
+        let index = self.sources.insert(Origin::Named(name.clone()), source.text.as_ref().into())
+            .try_into_inserted().ok()
+            .ok_or(CompileError::NamedSourceConflict { name })?;
+        self.include_stack.push(path.clone());
+        let parsed = self.parse(index, &source, path.parent());
+        self.include_stack.pop();
+        parsed
+    }
+
     pub fn load(&mut self, source: ScriptSource) -> CompileResult {
         match source {
             ScriptSource::Path { path } => {
-                let inserted = self.sources.load_directory(path, ".rea")?
-                    .into_iter()
-                    .filter_map(|insert| insert.try_into_inserted().ok());
-                for index in inserted {
-                    self.parse(index)?;
+                let mut files = Vec::new();
+                for entry in walkdir::WalkDir::new(&path).sort_by_file_name() {
+                    let entry = entry.map_err(|error| read_error(&path, error.into()))?;
+                    let is_script = entry.path().extension().is_some_and(|ext| ext == "rea");
+                    if entry.file_type().is_file() && is_script {
+                        files.push(canonical_path(entry.path())?);
+                    }
+                }
+                // all files are claimed up front, so includes between them don't load them twice
+                files.retain(|file| self.included.insert(file.clone()));
+                for file in files {
+                    self.load_file(file)?;
                 }
                 Ok(())
             },
             ScriptSource::Str { content, name } => {
-                let index = self.sources.insert(Origin::Named(name.clone()), content)
+                let source = SourceText { origin: name.clone(), text: content.into() };
+                let index = self.sources
+                    .insert(Origin::Named(name.clone()), source.text.as_ref().into())
                     .try_into_inserted().ok()
                     .ok_or_else(|| CompileError::NamedSourceConflict { name })?;
-                self.parse(index)
+                self.parse(index, &source, None)
             },
         }
     }
 
-    pub fn compile(mut self) -> CompileResult<IdSpace<Ctx, Ext, Eff>> {
-        for (_, reg_decl) in std::mem::replace(&mut self.declarations, HashMap::default()) {
-            let compiled = compile_root_declaration(&self.ids, &reg_decl.decl, reg_decl.index)
-                .map_err(|error| error.into_context_error(&self.sources))?;
+    /// Names referenced by the loaded declarations that are neither registered, declared, nor
+    /// provided by the fallback.
+    pub fn unknown_refs(&self) -> HashSet<SmolStr> {
+        let mut unknown = HashSet::new();
+        for reg_decl in &self.declarations {
+            self.collect_unknown_refs(reg_decl.decl.node.children(), &mut unknown);
+        }
+        unknown
+    }
+
+    fn collect_unknown_refs(&self, nodes: &[ScriptNode], unknown: &mut HashSet<SmolStr>) {
+        visit_refs(nodes, true, &mut |name, arity, is_node_ref| {
+            let is_fallback = is_node_ref
+                && self.fallback.is_some_and(|fallback| fallback(name, arity).is_some());
+            if self.ids.kind(name).is_none() && !is_fallback {
+                unknown.insert(name.into());
+            }
+        });
+    }
+
+    pub fn compile(mut self) -> CompileResult<IdSpace<Ctx, Ext, Eff>>
+    where
+        Ext: External,
+    {
+        if let Some(fallback) = self.fallback {
+            for reg_decl in &self.declarations {
+                register_fallbacks(&mut self.ids, fallback, reg_decl.decl.node.children());
+            }
+        }
+        let mut interner = Interner::default();
+        for reg_decl in std::mem::take(&mut self.declarations) {
+            let decl = &reg_decl.decl;
+            let compiled = compile_root_declaration(
+                &self.ids,
+                &mut interner,
+                decl,
+                reg_decl.index,
+                &reg_decl.source,
+                self.allow_shadowing,
+                self.max_nesting,
+            ).map_err(|error| error.into_context_error(&self.sources))?;
             match compiled {
                 Root::Node(root) => self.ids.set_node(root.index.unwrap(), Arc::new(root)),
                 Root::Action(root) => self.ids.set_node(root.index.unwrap(), Arc::new(root)),
@@ -222,6 +528,46 @@ impl<Ctx, Ext, Eff> Compiler<Ctx, Ext, Eff> {
     }
 }
 
+fn register_fallbacks<Ctx, Ext, Eff>(
+    ids: &mut IdSpace<Ctx, Ext, Eff>,
+    fallback: FallbackFn<Ctx, Ext, Eff>,
+    nodes: &[ScriptNode],
+) {
+    visit_refs(nodes, true, &mut |name, arity, is_node_ref| {
+        if is_node_ref && ids.kind(name).is_none() {
+            if let Some(handler) = fallback(name, arity) {
+                ids.set::<CustomIdx>(name.into(), handler, arity).expect("unknown names are free");
+            }
+        }
+    });
+}
+
+// statements, `applicable:` targets and query sources, list literal sources aren't refs. only
+// statements outside of `effects:` sections can refer to nodes
+fn visit_refs(nodes: &[ScriptNode], is_node_ref: bool, visit: &mut dyn FnMut(&str, usize, bool)) {
+    for node in nodes {
+        let referenced = node.statement()
+            .map(|stmt| (&stmt.signature[..], is_node_ref))
+            .or_else(|| try_parse_keyword_directive(node, kw::dir::APPLICABLE).ok().flatten()
+                .map(|arguments| (arguments, false)))
+            .or_else(|| kw::dir::query::ALL.into_iter()
+                .find_map(|keyword| match_directive(node, keyword))
+                .map(|(_, arguments)| (arguments, false))
+                .filter(|(arguments, _)| {
+                    !matches!(arguments, [Item { kind: ItemKind::Brackets(_), .. }])
+                }));
+        if let Some(((ref_name, arguments), is_node_ref)) = referenced
+            .and_then(|(signature, is_node_ref)| Some((match_ref(signature)?, is_node_ref)))
+        {
+            let (RefClass::Raw(name) | RefClass::Query(name)) = ref_name;
+            visit(&name, arguments.len(), is_node_ref);
+        }
+        let is_effects = try_parse_label_directive(node, kw::def::action::EFFECTS)
+            .unwrap_or(false);
+        visit_refs(node.children(), is_node_ref && !is_effects, visit);
+    }
+}
+
 struct Decl {
     name: ItemValue<Sym>,
     parameters: Vec<ItemValue<Var>>,
@@ -253,17 +599,6 @@ impl<Node, Action> Root<Node, Action> {
             Self::Action(action) => Root::Action(mapv(action)),
         }
     }
-
-    fn map_each<FN, RN, FA, RA>(self, mapn: FN, mapa: FA) -> Root<RN, RA>
-    where
-        FN: FnOnce(Node) -> RN,
-        FA: FnOnce(Action) -> RA,
-    {
-        match self {
-            Root::Node(n) => Root::Node(mapn(n)),
-            Root::Action(a) => Root::Action(mapa(a)),
-        }
-    }
 }
 
 enum_class!(RefClass {
@@ -1,6 +1,6 @@
 use smol_str::SmolStr;
 use src_ctx::SourceError;
-use treelang::{Node as ScriptNode, Item, Directive};
+use treelang::{Node as ScriptNode, Item, ItemKind, Directive};
 
 use crate::gen::smol_str_wrapper;
 use crate::str::{is_symbol, is_variable};
@@ -77,6 +77,63 @@ pub(super) fn try_parse_keyword_directive<'a>(
     }
 }
 
+pub(super) fn try_parse_doc_directive(node: &ScriptNode) -> ScriptResult<Option<String>> {
+    let Some(arguments) = try_parse_keyword_directive(node, kw::def::DOC)? else {
+        return Ok(None);
+    };
+    if arguments.is_empty() || !node.children().is_empty() {
+        return Err(SourceError::new(ScriptError::InvalidDoc, node.location, "documentation"));
+    }
+    let mut words = Vec::with_capacity(arguments.len());
+    for item in arguments {
+        match &item.kind {
+            ItemKind::Word(word) => words.push(word.to_string()),
+            ItemKind::Int(value) => words.push(value.to_string()),
+            ItemKind::Float(value) => words.push(value.to_string()),
+            _ => return Err(SourceError::new(
+                ScriptError::InvalidDoc,
+                item.location.start(),
+                "unexpected documentation item",
+            )),
+        }
+    }
+    Ok(Some(words.join(" ")))
+}
+
+pub(super) fn try_parse_score_directive(node: &ScriptNode) -> ScriptResult<Option<&Item>> {
+    let Some(arguments) = try_parse_keyword_directive(node, kw::def::action::SCORE)? else {
+        return Ok(None);
+    };
+    match arguments {
+        [item] if node.children().is_empty() => Ok(Some(item)),
+        _ => Err(SourceError::new(
+            ScriptError::DirectiveArgumentArity {
+                keyword: kw::def::action::SCORE,
+                error: ArityError { expected: 1, given: arguments.len() },
+            },
+            node.location,
+            "score value",
+        )),
+    }
+}
+
+// quotes around the path are optional
+pub(super) fn try_parse_include_directive(node: &ScriptNode) -> ScriptResult<Option<&str>> {
+    let Some(arguments) = try_parse_keyword_directive(node, kw::def::INCLUDE)? else {
+        return Ok(None);
+    };
+    let path = match arguments {
+        [item] if node.children().is_empty() => item.word_str().map(|word| {
+            word.strip_prefix('"').and_then(|word| word.strip_suffix('"')).unwrap_or(word)
+        }),
+        _ => None,
+    };
+    match path {
+        Some(path) if !path.is_empty() => Ok(Some(path)),
+        _ => Err(SourceError::new(ScriptError::InvalidInclude, node.location, "include")),
+    }
+}
+
 pub(super) fn match_ref(items: &[Item]) -> Option<(RefClass<ItemValue<Sym>>, &[Item])> {
     let (first, items) = items.split_first()?;
     if let Some(word) = first.word() {
@@ -143,10 +200,108 @@ pub(super) fn match_var(item: &Item) -> Option<ItemValue<Var>> {
     }
 }
 
+pub(super) fn match_global_call(item: &Item) -> Option<(ItemValue<Var>, &[Item])> {
+    let ItemKind::Parens(items) = &item.kind else {
+        return None;
+    };
+    let (name, arguments) = items.split_first()?;
+    Some((match_var(name)?, arguments))
+}
+
+pub(super) fn match_ext_predicate(item: &Item) -> Option<ItemValue<Sym>> {
+    let name = item.word()?.strip_prefix(kw::EXT_PREDICATE_PREFIX)?;
+    is_symbol(name).then(|| ItemValue { value: Sym(name.into()), item: item.clone() })
+}
+
+pub(super) fn match_tagged_pattern(items: &[Item]) -> Option<(ItemValue<Sym>, &[Item])> {
+    let [tag, Item { kind: ItemKind::Brackets(fields), .. }, ..] = items else {
+        return None;
+    };
+    let name = tag.word()?.strip_prefix(kw::TAG_PREFIX)?;
+    is_symbol(name).then(|| (ItemValue { value: Sym(name.into()), item: tag.clone() }, &fields[..]))
+}
+
+// a tagged pattern spans the tag word and the bracketed fields following it
+pub(super) fn group_patterns(items: &[Item]) -> Vec<&[Item]> {
+    let mut groups = Vec::new();
+    let mut rest = items;
+    while !rest.is_empty() {
+        let len = if match_tagged_pattern(rest).is_some() { 2 } else { 1 };
+        let (group, tail) = rest.split_at(len);
+        groups.push(group);
+        rest = tail;
+    }
+    groups
+}
+
+// renders the first line of a node, for use in diagnostics
+pub(super) fn describe_node(node: &ScriptNode) -> String {
+    if let Some(Directive { signature, arguments, .. }) = node.kind.directive() {
+        let signature = describe_items(signature);
+        if arguments.is_empty() {
+            format!("{signature}:")
+        } else {
+            format!("{signature}: {}", describe_items(arguments))
+        }
+    } else if let Some(stmt) = node.statement() {
+        describe_items(&stmt.signature)
+    } else {
+        String::new()
+    }
+}
+
+fn describe_items(items: &[Item]) -> String {
+    let items: Vec<String> = items.iter()
+        .map(|item| match &item.kind {
+            ItemKind::Word(word) => word.to_string(),
+            ItemKind::Int(value) => value.to_string(),
+            ItemKind::Float(value) => value.to_string(),
+            ItemKind::Brackets(items) => format!("[{}]", describe_items(items)),
+            ItemKind::Parens(items) => format!("({})", describe_items(items)),
+            ItemKind::Braces(items) => format!("{{{}}}", describe_items(items)),
+        })
+        .collect();
+    items.join(" ")
+}
+
+// structural equality that ignores source locations
+pub(super) fn is_same_node(a: &ScriptNode, b: &ScriptNode) -> bool {
+    let is_same_kind = match (a.kind.directive(), b.kind.directive()) {
+        (Some(a), Some(b)) => {
+            is_same_items(&a.signature, &b.signature) && is_same_items(&a.arguments, &b.arguments)
+        },
+        (None, None) => match (a.statement(), b.statement()) {
+            (Some(a), Some(b)) => is_same_items(&a.signature, &b.signature),
+            _ => false,
+        },
+        _ => false,
+    };
+    is_same_kind
+        && a.children().len() == b.children().len()
+        && a.children().iter().zip(b.children()).all(|(a, b)| is_same_node(a, b))
+}
+
+fn is_same_items(a: &[Item], b: &[Item]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| match (&a.kind, &b.kind) {
+        (ItemKind::Word(a), ItemKind::Word(b)) => a == b,
+        (ItemKind::Int(a), ItemKind::Int(b)) => a == b,
+        (ItemKind::Float(a), ItemKind::Float(b)) => a == b,
+        (ItemKind::Brackets(a), ItemKind::Brackets(b))
+        | (ItemKind::Parens(a), ItemKind::Parens(b))
+        | (ItemKind::Braces(a), ItemKind::Braces(b)) => is_same_items(a, b),
+        _ => false,
+    })
+}
+
 pub(super) fn match_wildcard(item: &Item) -> bool {
     item.word_str().map_or(false, |s| s == "$")
 }
 
+// only valid as the last item of a list pattern
+pub(super) fn match_list_rest(item: &Item) -> bool {
+    item.word_str().is_some_and(|s| s == "...")
+}
+
 #[derive(Debug, Clone)]
 pub struct ItemValue<T> {
     pub value: T,
@@ -1,31 +1,56 @@
 
+pub const EXT_PREDICATE_PREFIX: char = '@';
+pub const TAG_PREFIX: char = '#';
+
+// the global `within` reads the current time from
+pub const NOW_GLOBAL: &str = "$now";
+
 pub mod def {
     pub const ACTION: &str = "action";
     pub const NODE: &str = "node";
+    pub const DOC: &str = "doc";
+    pub const INCLUDE: &str = "include";
 
     pub mod action {
         pub const CONDITIONS: &str = "conditions";
         pub const EFFECTS: &str = "effects";
         pub const DISCOVERY: &str = "discovery";
         pub const INHERIT: &str = "inherit";
+        pub const SCORE: &str = "score";
     }
 }
 
 pub mod dir {
     pub const SELECT: &str = "select";
+    pub const SELECT_BEST: &str = "select-best";
     pub const SEQUENCE: &str = "do";
     pub const NONE: &str = "none";
     pub const VISIT: &str = "visit";
     pub const MATCH: &str = "match";
     pub const RANDOM: &str = "random";
     pub const RANDOM_ANY: &str = "any-random";
+    pub const RANDOM_SELECT: &str = "select-any";
+    pub const APPLICABLE: &str = "applicable";
+    pub const ASSERT: &str = "assert";
+    pub const ONCE: &str = "once";
+    pub const PRUNE_DISCOVERY: &str = "prune-discovery";
+    pub const WITHIN: &str = "within";
 
     pub mod query {
         pub const SELECT: &str = "for-any";
         pub const SEQUENCE: &str = "for-every";
         pub const FIRST: &str = "with-first";
+        pub const FIRST_ONLY: &str = "for-first-only";
         pub const LAST: &str = "with-last";
         pub const VISIT: &str = "visit-every";
+        pub const SEQUENCE_INDEXED: &str = "for-each-indexed";
+        pub const SEQUENCE_ACTIONS: &str = "for-every-action";
+        pub const EACH: &str = "with-each";
+
+        pub const ALL: [&str; 9] = [
+            SELECT, SEQUENCE, FIRST, FIRST_ONLY, LAST, VISIT, SEQUENCE_INDEXED, SEQUENCE_ACTIONS,
+            EACH,
+        ];
     }
 
     pub mod switch {
@@ -33,6 +58,12 @@ pub mod dir {
         pub const CASE: &str = "case";
     }
 
+    pub mod fallback {
+        pub const FALLBACK: &str = "fallback";
+        pub const TRY: &str = "try";
+        pub const DEFAULT: &str = "default";
+    }
+
     pub mod cond {
         pub const COND: &str = "cond";
         pub const CASE: &str = "when";
@@ -1,76 +1,156 @@
 use std::sync::Arc;
 
 use ordered_float::OrderedFloat;
+use smol_str::SmolStr;
 use src_ctx::SourceError;
 use treelang::{Node as ScriptNode, Item, ItemKind};
 
-use crate::tree::{ArityError, ActionIdx, NodeIdx, RefIdx};
+use crate::tree::{ArityError, ActionIdx, NodeIdx, RefIdx, External};
 use crate::tree::id_space::{IdSpace, IdError, EffectIdx};
 use crate::tree::script::{
     NodeRoot, ActionRoot, Node, Nodes, Dispatch, RefMode, Patterns, Pattern, ProtoValues,
-    ProtoValue, QueryMode,
+    ProtoValue, QueryMode, QuerySource, RandomMode,
 };
 use crate::value::Value;
 
 use super::parse::{
     Var, ItemValue, kw, try_parse_label_directive, match_ref, Sym, match_var, match_sym,
-    match_directive, try_parse_keyword_directive, match_wildcard,
+    match_directive, try_parse_keyword_directive, match_wildcard, try_parse_doc_directive,
+    match_global_call, match_ext_predicate, match_tagged_pattern, group_patterns, describe_node,
+    match_list_rest, try_parse_score_directive,
 };
-use super::{Root, Decl, ScriptResult, ScriptError, RefClass};
+use super::{Root, Decl, ScriptResult, ScriptError, RefClass, SourceText};
 
 use env::*;
 
+pub(super) use intern::Interner;
+
 
 mod env;
+mod intern;
 
 pub(super) fn compile_root_declaration<Ctx, Ext, Eff>(
     ids: &IdSpace<Ctx, Ext, Eff>,
+    interner: &mut Interner<Ext>,
     decl: &Decl,
     index: Root<NodeIdx, ActionIdx>,
-) -> ScriptResult<Root<NodeRoot<Ext>, ActionRoot<Ext>>> {
-    index.map_each(
-        |index| {
-            compile_node_root(index, ids, &decl.parameters, decl.node.children())
+    source: &SourceText,
+    allow_shadowing: bool,
+    max_nesting: usize,
+) -> ScriptResult<Root<NodeRoot<Ext>, ActionRoot<Ext>>>
+where
+    Ext: External,
+{
+    let root = decl.name.to_smol_str();
+    let env = Env::new(ids, interner, root, source, allow_shadowing, max_nesting);
+    let compiled = match index {
+        Root::Node(index) => {
+            compile_node_root(index, env, &decl.parameters, decl.node.children()).map(Root::Node)
         },
-        |index| {
-            compile_action_root(index, ids, &decl.parameters, decl.node.children())
+        Root::Action(index) => {
+            compile_action_root(index, env, &decl.parameters, decl.node.children())
+                .map(Root::Action)
         },
-    ).lift().map_err(|error| error.with_context(decl.node.location))
+    };
+    compiled.map_err(|error| error.with_context(decl.node.location))
 }
 
 fn compile_node_root<Ctx, Ext, Eff>(
     index: NodeIdx,
-    ids: &IdSpace<Ctx, Ext, Eff>,
+    mut env: Env<'_, Ctx, Ext, Eff>,
     parameters: &[ItemValue<Var>],
     children: &[ScriptNode],
-) -> ScriptResult<NodeRoot<Ext>> {
-    let mut env = Env::new(ids);
+) -> ScriptResult<NodeRoot<Ext>>
+where
+    Ext: External,
+{
+    let mut doc = Doc::default();
+    let mut branches = Vec::new();
+    for child in children {
+        if !doc.try_parse(child)? {
+            branches.push(child.clone());
+        }
+    }
+
     env.scope(parameters.iter(), |env| {
-        let nodes = compile_branches(env, children)?;
+        let nodes = compile_branches(env, &branches)?;
         let lexicals = env.max_vars();
-        Ok(NodeRoot { index: Some(index), node: Node::sequence(nodes), lexicals })
+        Ok(NodeRoot {
+            index: Some(index),
+            node: Node::sequence(nodes),
+            lexicals,
+            doc: doc.finish(),
+            parameters: parameter_names(parameters),
+        })
     })
 }
 
+#[derive(Default)]
+struct Doc {
+    lines: Vec<String>,
+}
+
+impl Doc {
+    fn try_parse(&mut self, node: &ScriptNode) -> ScriptResult<bool> {
+        let Some(line) = try_parse_doc_directive(node)? else {
+            return Ok(false);
+        };
+        self.lines.push(line);
+        Ok(true)
+    }
+
+    fn finish(self) -> Option<SmolStr> {
+        (!self.lines.is_empty()).then(|| self.lines.join("\n").into())
+    }
+}
+
 fn compile_action_root<Ctx, Ext, Eff>(
     index: ActionIdx,
-    ids: &IdSpace<Ctx, Ext, Eff>,
+    mut env: Env<'_, Ctx, Ext, Eff>,
     parameters: &[ItemValue<Var>],
     children: &[ScriptNode],
-) -> ScriptResult<ActionRoot<Ext>> {
+) -> ScriptResult<ActionRoot<Ext>>
+where
+    Ext: External,
+{
     let mut conditions = Vec::new();
     let mut effects = Vec::new();
     let mut discovery = Vec::new();
     let mut inherit = Vec::new();
+    let mut score = None;
+    let mut doc = Doc::default();
+    let mut seen = Vec::new();
 
     'children: for child in children {
-        for (keyword, collection) in [
-            (kw::def::action::CONDITIONS, &mut conditions),
-            (kw::def::action::EFFECTS, &mut effects),
-            (kw::def::action::INHERIT, &mut inherit),
-            (kw::def::action::DISCOVERY, &mut discovery),
+        if doc.try_parse(child)? {
+            continue 'children;
+        }
+        if let Some(item) = try_parse_score_directive(child)? {
+            if score.replace(item).is_some() {
+                return Err(SourceError::new(
+                    ScriptError::DuplicateActionSection { keyword: kw::def::action::SCORE },
+                    child.location,
+                    "repeated action section",
+                ));
+            }
+            continue 'children;
+        }
+        // repeated `effects:` and `inherit:` sections are merged
+        for (keyword, collection, is_unique) in [
+            (kw::def::action::CONDITIONS, &mut conditions, true),
+            (kw::def::action::EFFECTS, &mut effects, false),
+            (kw::def::action::INHERIT, &mut inherit, false),
+            (kw::def::action::DISCOVERY, &mut discovery, true),
         ] {
             if try_parse_label_directive(child, keyword)? {
+                if is_unique && seen.contains(&keyword) {
+                    return Err(SourceError::new(
+                        ScriptError::DuplicateActionSection { keyword },
+                        child.location,
+                        "repeated action section",
+                    ));
+                }
+                seen.push(keyword);
                 collection.extend(child.children().iter().cloned());
                 continue 'children;
             }
@@ -82,13 +162,13 @@ fn compile_action_root<Ctx, Ext, Eff>(
         ));
     }
 
-    let mut env = Env::new(ids);
     let discovery = compile_branches(&mut env, &discovery)?;
 
     env.scope(parameters.iter(), |env| {
         let conditions = compile_branches(env, &conditions)?;
         let effects = compile_effects(env, &effects)?;
         let inherit = compile_branches(env, &inherit)?;
+        let score = score.map(|score| compile_value(env, score)).transpose()?;
         let lexicals = env.max_vars();
         Ok(ActionRoot {
             index: Some(index),
@@ -96,15 +176,25 @@ fn compile_action_root<Ctx, Ext, Eff>(
             inherit,
             conditions,
             discovery,
+            score,
             lexicals,
+            doc: doc.finish(),
+            parameters: parameter_names(parameters),
         })
     })
 }
 
+fn parameter_names(parameters: &[ItemValue<Var>]) -> Arc<[SmolStr]> {
+    parameters.iter().map(|parameter| parameter.to_smol_str()).collect()
+}
+
 fn compile_effects<Ctx, Ext, Eff>(
     env: &mut Env<'_, Ctx, Ext, Eff>,
     nodes: &[ScriptNode],
-) -> ScriptResult<Arc<[(EffectIdx, ProtoValues<Ext>)]>> {
+) -> ScriptResult<Arc<[(EffectIdx, ProtoValues<Ext>)]>>
+where
+    Ext: External,
+{
     let mut compiled = Vec::new();
     for node in nodes {
         compiled.push(compile_effect(env, node)?);
@@ -115,7 +205,10 @@ fn compile_effects<Ctx, Ext, Eff>(
 fn compile_effect<Ctx, Ext, Eff>(
     env: &mut Env<'_, Ctx, Ext, Eff>,
     node: &ScriptNode,
-) -> ScriptResult<(EffectIdx, ProtoValues<Ext>)> {
+) -> ScriptResult<(EffectIdx, ProtoValues<Ext>)>
+where
+    Ext: External,
+{
     let (name, arguments) = node.statement()
         .and_then(|stmt| match_ref(&stmt.signature))
         .filter(|(name, _)| matches!(name, RefClass::Raw(_)))
@@ -136,6 +229,7 @@ fn compile_branches<'i, Ctx, Ext, Eff, I>(
 ) -> ScriptResult<Nodes<Ext>>
 where
     I: IntoIterator<Item = &'i ScriptNode>,
+    Ext: External,
 {
     let mut compiled = Vec::new();
     for node in nodes {
@@ -147,11 +241,16 @@ where
 fn try_compile_branch_random<Ctx, Ext, Eff>(
     env: &mut Env<'_, Ctx, Ext, Eff>,
     node: &ScriptNode,
-) -> ScriptResult<Option<Node<Ext>>> {
-    let (seeds, any) = if let Some(seeds) = try_parse_keyword_directive(node, kw::dir::RANDOM)? {
-        (seeds, false)
+) -> ScriptResult<Option<Node<Ext>>>
+where
+    Ext: External,
+{
+    let (seeds, mode) = if let Some(seeds) = try_parse_keyword_directive(node, kw::dir::RANDOM)? {
+        (seeds, RandomMode::First)
     } else if let Some(seeds) = try_parse_keyword_directive(node, kw::dir::RANDOM_ANY)? {
-        (seeds, true)
+        (seeds, RandomMode::CheckAny)
+    } else if let Some(seeds) = try_parse_keyword_directive(node, kw::dir::RANDOM_SELECT)? {
+        (seeds, RandomMode::Select)
     } else {
         return Ok(None);
     };
@@ -170,26 +269,135 @@ fn try_compile_branch_random<Ctx, Ext, Eff>(
         ctx_seeds.push(index);
     }
     let branches = compile_branches(env, node.children())?;
-    Ok(Some(Node::Random(fastrand::u64(..), ctx_seeds.into(), branches, any)))
+    Ok(Some(Node::Random(fastrand::u64(..), ctx_seeds.into(), branches, mode)))
 }
 
 fn try_compile_branch_dispatch<Ctx, Ext, Eff>(
     env: &mut Env<'_, Ctx, Ext, Eff>,
     node: &ScriptNode,
-) -> ScriptResult<Option<Node<Ext>>> {
+) -> ScriptResult<Option<Node<Ext>>>
+where
+    Ext: External,
+{
     for (keyword, mode) in [
         (kw::dir::SEQUENCE, Dispatch::Sequence),
         (kw::dir::SELECT, Dispatch::Selection),
+        (kw::dir::SELECT_BEST, Dispatch::BestSelection),
         (kw::dir::NONE, Dispatch::None),
         (kw::dir::VISIT, Dispatch::Visit),
     ] {
         if try_parse_label_directive(node, keyword)? {
+            if matches!(mode, Dispatch::Selection | Dispatch::BestSelection) {
+                check_non_empty(node, keyword)?;
+            }
             return Ok(Some(Node::Dispatch(mode, compile_branches(env, node.children())?)));
         }
     }
     Ok(None)
 }
 
+fn try_compile_branch_assert<Ctx, Ext, Eff>(
+    env: &mut Env<'_, Ctx, Ext, Eff>,
+    node: &ScriptNode,
+) -> ScriptResult<Option<Node<Ext>>>
+where
+    Ext: External,
+{
+    if try_parse_label_directive(node, kw::dir::ASSERT)? {
+        let mut asserts = Vec::new();
+        for child in node.children() {
+            let location = env.source().location(child.location);
+            let description = format!("`{}` in `{}`", describe_node(child), env.root());
+            let asserted = Box::new(compile_branch(env, child)?);
+            asserts.push(Node::Assert(asserted, location, description.into()));
+        }
+        return Ok(Some(Node::sequence(asserts.into())));
+    }
+    Ok(None)
+}
+
+fn try_compile_branch_once<Ctx, Ext, Eff>(
+    env: &mut Env<'_, Ctx, Ext, Eff>,
+    node: &ScriptNode,
+) -> ScriptResult<Option<Node<Ext>>>
+where
+    Ext: External,
+{
+    if try_parse_label_directive(node, kw::dir::ONCE)? {
+        let id = env.interner().once_id();
+        return Ok(Some(Node::Once(id, compile_branches(env, node.children())?)));
+    }
+    Ok(None)
+}
+
+fn try_compile_branch_prune_discovery<Ext>(
+    node: &ScriptNode,
+) -> ScriptResult<Option<Node<Ext>>> {
+    if try_parse_label_directive(node, kw::dir::PRUNE_DISCOVERY)? {
+        if let Some(child) = node.children().first() {
+            return Err(SourceError::new(
+                ScriptError::UnrecognizedNode,
+                child.location,
+                "unexpected child node",
+            ));
+        }
+        return Ok(Some(Node::PruneDiscovery));
+    }
+    Ok(None)
+}
+
+fn try_compile_branch_within<Ctx, Ext, Eff>(
+    env: &mut Env<'_, Ctx, Ext, Eff>,
+    node: &ScriptNode,
+) -> ScriptResult<Option<Node<Ext>>>
+where
+    Ext: External,
+{
+    let Some((signature, arguments)) = match_directive(node, kw::dir::WITHIN) else {
+        return Ok(None);
+    };
+    let [limit] = signature else {
+        return Err(SourceError::new(
+            ScriptError::DirectiveSignatureArity {
+                keyword: kw::dir::WITHIN,
+                error: ArityError { expected: 1, given: signature.len() },
+            },
+            node.location,
+            "expected time limit",
+        ));
+    };
+    if !arguments.is_empty() {
+        return Err(SourceError::new(
+            ScriptError::DirectiveArgumentArity {
+                keyword: kw::dir::WITHIN,
+                error: ArityError { expected: 0, given: arguments.len() },
+            },
+            node.location,
+            "unexpected arguments",
+        ));
+    }
+    check_non_empty(node, kw::dir::WITHIN)?;
+    let now = env.ids().resolve(kw::NOW_GLOBAL, 0).map_err(|error| SourceError::new(
+        ScriptError::Identifier { name: kw::NOW_GLOBAL.into(), error },
+        node.location,
+        "time limit requires a global",
+    ))?;
+    let limit = compile_value(env, limit)?;
+    let branches = compile_branches(env, node.children())?;
+    Ok(Some(Node::Within(limit, now, branches)))
+}
+
+fn check_non_empty(node: &ScriptNode, keyword: &'static str) -> ScriptResult {
+    if node.children().is_empty() {
+        return Err(SourceError::new(
+            ScriptError::EmptyBlock { keyword },
+            node.location,
+            "block without children",
+        ));
+    }
+    Ok(())
+}
+
 fn convert_id_error(
     name: &ItemValue<Sym>,
     error: IdError,
@@ -212,7 +420,10 @@ fn resolve_ref_symbol<Ctx, Ext, Eff>(
 fn try_compile_branch_ref<Ctx, Ext, Eff>(
     env: &mut Env<'_, Ctx, Ext, Eff>,
     node: &ScriptNode,
-) -> ScriptResult<Option<Node<Ext>>> {
+) -> ScriptResult<Option<Node<Ext>>>
+where
+    Ext: External,
+{
     if let Some(stmt) = node.statement() {
         if let Some((ref_name, arguments)) = match_ref(&stmt.signature) {
             let (value, mode) = match ref_name {
@@ -227,10 +438,36 @@ fn try_compile_branch_ref<Ctx, Ext, Eff>(
     Ok(None)
 }
 
+fn try_compile_branch_applicable<Ctx, Ext, Eff>(
+    env: &mut Env<'_, Ctx, Ext, Eff>,
+    node: &ScriptNode,
+) -> ScriptResult<Option<Node<Ext>>>
+where
+    Ext: External,
+{
+    let Some(action_ref) = try_parse_keyword_directive(node, kw::dir::APPLICABLE)? else {
+        return Ok(None);
+    };
+    let Some((RefClass::Raw(name), arguments)) = match_ref(action_ref) else {
+        return Err(SourceError::new(
+            ScriptError::InvalidActionRef,
+            node.location,
+            "expected action reference",
+        ));
+    };
+    let index = env.ids().resolve(&name, arguments.len())
+        .map_err(|error| convert_id_error(&name, error))?;
+    let arguments = compile_values(env, arguments)?;
+    Ok(Some(Node::Applicable(index, arguments)))
+}
+
 fn try_compile_branch_cond<Ctx, Ext, Eff>(
     env: &mut Env<'_, Ctx, Ext, Eff>,
     node: &ScriptNode,
-) -> ScriptResult<Option<Node<Ext>>> {
+) -> ScriptResult<Option<Node<Ext>>>
+where
+    Ext: External,
+{
     if try_parse_label_directive(node, kw::dir::cond::COND)? {
         let mut branches = Vec::new();
         let mut else_branch = None;
@@ -271,18 +508,67 @@ fn try_compile_branch_cond<Ctx, Ext, Eff>(
     Ok(None)
 }
 
+fn try_compile_branch_fallback<Ctx, Ext, Eff>(
+    env: &mut Env<'_, Ctx, Ext, Eff>,
+    node: &ScriptNode,
+) -> ScriptResult<Option<Node<Ext>>>
+where
+    Ext: External,
+{
+    if !try_parse_label_directive(node, kw::dir::fallback::FALLBACK)? {
+        return Ok(None);
+    }
+    let invalid = |location| SourceError::new(
+        ScriptError::InvalidFallbackNode,
+        location,
+        "expected `try` followed by `default`",
+    );
+    let [try_node, default_node] = node.children() else {
+        return Err(invalid(node.location));
+    };
+    if !try_parse_label_directive(try_node, kw::dir::fallback::TRY)? {
+        return Err(invalid(try_node.location));
+    }
+    check_non_empty(try_node, kw::dir::fallback::TRY)?;
+    let Some(action_ref) = try_parse_keyword_directive(default_node, kw::dir::fallback::DEFAULT)?
+    else {
+        return Err(invalid(default_node.location));
+    };
+    if !default_node.children().is_empty() {
+        return Err(invalid(default_node.children()[0].location));
+    }
+    let Some((RefClass::Raw(name), arguments)) = match_ref(action_ref) else {
+        return Err(SourceError::new(
+            ScriptError::InvalidActionRef,
+            default_node.location,
+            "expected action reference",
+        ));
+    };
+    let attempt = Node::sequence(compile_branches(env, try_node.children())?);
+    let index = env.ids().resolve::<ActionIdx>(&name, arguments.len())
+        .map_err(|error| convert_id_error(&name, error))?;
+    let arguments = compile_values(env, arguments)?;
+    let default = Node::Ref(RefIdx::Action(index), RefMode::Inherit, arguments);
+    Ok(Some(Node::Dispatch(Dispatch::Selection, [attempt, default].into())))
+}
+
 fn try_compile_branch_switch<Ctx, Ext, Eff>(
     env: &mut Env<'_, Ctx, Ext, Eff>,
     node: &ScriptNode,
-) -> ScriptResult<Option<Node<Ext>>> {
+) -> ScriptResult<Option<Node<Ext>>>
+where
+    Ext: External,
+{
     if let Some(targets) = try_parse_keyword_directive(node, kw::dir::switch::SWITCH)? {
+        check_non_empty(node, kw::dir::switch::SWITCH)?;
         let mut cases = Vec::new();
         for child in node.children() {
             if let Some(patterns) = try_parse_keyword_directive(child, kw::dir::switch::CASE)? {
-                if targets.len() != patterns.len() {
+                let given = group_patterns(patterns).len();
+                if targets.len() != given {
                     return Err(SourceError::new(
                         ScriptError::PatternArity {
-                            error: ArityError { expected: targets.len(), given: patterns.len() },
+                            error: ArityError { expected: targets.len(), given },
                         },
                         child.location,
                         "switch case with arity mismatch",
@@ -292,7 +578,7 @@ fn try_compile_branch_switch<Ctx, Ext, Eff>(
                     let targets = compile_values(env, targets)?;
                     let patterns = compile_pattern_items(env, patterns)?;
                     let branches = compile_branches(env, child.children())?;
-                    cases.push(Node::Match(targets, patterns, branches));
+                    cases.push(Node::Match(targets, patterns, branches, None));
                     Ok(())
                 })?;
             } else {
@@ -311,22 +597,33 @@ fn try_compile_branch_switch<Ctx, Ext, Eff>(
 fn try_compile_branch_match<Ctx, Ext, Eff>(
     env: &mut Env<'_, Ctx, Ext, Eff>,
     node: &ScriptNode,
-) -> ScriptResult<Option<Node<Ext>>> {
+) -> ScriptResult<Option<Node<Ext>>>
+where
+    Ext: External,
+{
     if let Some((patterns, targets)) = match_directive(node, kw::dir::MATCH) {
-        if targets.len() != patterns.len() {
+        let given = group_patterns(patterns).len();
+        if targets.len() != given {
             return Err(SourceError::new(
                 ScriptError::PatternArity {
-                    error: ArityError { expected: targets.len(), given: patterns.len() },
+                    error: ArityError { expected: targets.len(), given },
                 },
                 node.location,
                 "match with arity mismatch",
             ));
         }
+        let (children, else_branch) = match node.children().split_last() {
+            Some((last, children)) if try_parse_label_directive(last, kw::dir::cond::ELSE)? => {
+                let branch = Node::sequence(compile_branches(env, last.children())?);
+                (children, Some(branch.into()))
+            },
+            _ => (node.children(), None),
+        };
         return env.scope([], |env| {
             let targets = compile_values(env, targets)?;
             let patterns = compile_pattern_items(env, patterns)?;
-            let branches = compile_branches(env, node.children())?;
-            Ok(Some(Node::Match(targets, patterns, branches)))
+            let branches = compile_branches(env, children)?;
+            Ok(Some(Node::Match(targets, patterns, branches, else_branch)))
         });
     }
     Ok(None)
@@ -335,16 +632,25 @@ fn try_compile_branch_match<Ctx, Ext, Eff>(
 fn try_compile_branch_query<Ctx, Ext, Eff>(
     env: &mut Env<'_, Ctx, Ext, Eff>,
     node: &ScriptNode,
-) -> ScriptResult<Option<Node<Ext>>> {
+) -> ScriptResult<Option<Node<Ext>>>
+where
+    Ext: External,
+{
     for (keyword, mode) in [
         (kw::dir::query::SELECT, QueryMode::Selection),
         (kw::dir::query::SEQUENCE, QueryMode::Sequence),
         (kw::dir::query::FIRST, QueryMode::First),
+        (kw::dir::query::FIRST_ONLY, QueryMode::FirstOnly),
         (kw::dir::query::LAST, QueryMode::Last),
         (kw::dir::query::VISIT, QueryMode::Visit),
+        (kw::dir::query::SEQUENCE_INDEXED, QueryMode::IndexedSequence),
+        (kw::dir::query::SEQUENCE_ACTIONS, QueryMode::ActionSequence),
+        (kw::dir::query::EACH, QueryMode::Each),
     ] {
         if let Some((signature, arguments)) = match_directive(node, keyword) {
-            let [pattern] = signature else {
+            check_non_empty(node, keyword)?;
+            let signature = group_patterns(signature);
+            let [pattern] = signature[..] else {
                 return Err(SourceError::new(
                     ScriptError::DirectiveSignatureArity {
                         keyword,
@@ -354,20 +660,26 @@ fn try_compile_branch_query<Ctx, Ext, Eff>(
                     "query with invalid signature",
                 ));
             };
-            let Some((RefClass::Raw(name), arguments)) = match_ref(arguments) else {
-                return Err(SourceError::new(
-                    ScriptError::InvalidQueryRef,
-                    node.location,
-                    "expected query reference",
-                ));
+            let is_list = matches!(arguments, [Item { kind: ItemKind::Brackets(_), .. }]);
+            let (source, arguments) = if is_list {
+                (QuerySource::List, arguments)
+            } else {
+                let Some((RefClass::Raw(name), arguments)) = match_ref(arguments) else {
+                    return Err(SourceError::new(
+                        ScriptError::InvalidQueryRef,
+                        node.location,
+                        "expected query reference",
+                    ));
+                };
+                let index = env.ids().resolve(&name, arguments.len())
+                    .map_err(|error| convert_id_error(&name, error))?;
+                (QuerySource::Query(index), arguments)
             };
-            let index = env.ids().resolve(&name, arguments.len())
-                .map_err(|error| convert_id_error(&name, error))?;
             return env.scope([], |env| {
                 let arguments = compile_values(env, arguments)?;
-                let pattern = compile_pattern_item(env, pattern)?;
+                let pattern = compile_pattern_group(env, pattern)?;
                 let branches = compile_branches(env, node.children())?;
-                Ok(Some(Node::Query(pattern, index, arguments, mode, branches)))
+                Ok(Some(Node::Query(pattern, source, arguments, mode, branches)))
             });
         }
     }
@@ -377,9 +689,20 @@ fn try_compile_branch_query<Ctx, Ext, Eff>(
 fn compile_branch<Ctx, Ext, Eff>(
     env: &mut Env<'_, Ctx, Ext, Eff>,
     node: &ScriptNode,
-) -> ScriptResult<Node<Ext>> {
+) -> ScriptResult<Node<Ext>>
+where
+    Ext: External,
+{
     if let Some(compiled) = try_compile_branch_dispatch(env, node)? {
         Ok(compiled)
+    } else if let Some(compiled) = try_compile_branch_assert(env, node)? {
+        Ok(compiled)
+    } else if let Some(compiled) = try_compile_branch_once(env, node)? {
+        Ok(compiled)
+    } else if let Some(compiled) = try_compile_branch_prune_discovery(node)? {
+        Ok(compiled)
+    } else if let Some(compiled) = try_compile_branch_within(env, node)? {
+        Ok(compiled)
     } else if let Some(compiled) = try_compile_branch_ref(env, node)? {
         Ok(compiled)
     } else if let Some(compiled) = try_compile_branch_match(env, node)? {
@@ -392,6 +715,10 @@ fn compile_branch<Ctx, Ext, Eff>(
         Ok(compiled)
     } else if let Some(compiled) = try_compile_branch_cond(env, node)? {
         Ok(compiled)
+    } else if let Some(compiled) = try_compile_branch_fallback(env, node)? {
+        Ok(compiled)
+    } else if let Some(compiled) = try_compile_branch_applicable(env, node)? {
+        Ok(compiled)
     } else {
         Err(SourceError::new(ScriptError::UnrecognizedNode, node.location, "expected logic node"))
     }
@@ -400,7 +727,10 @@ fn compile_branch<Ctx, Ext, Eff>(
 fn compile_value<Ctx, Ext, Eff>(
     env: &mut Env<'_, Ctx, Ext, Eff>,
     item: &Item,
-) -> ScriptResult<ProtoValue<Ext>> {
+) -> ScriptResult<ProtoValue<Ext>>
+where
+    Ext: External,
+{
     if let Some(var) = match_var(item) {
         env.resolve(&var)
     } else if let Some(sym) = match_sym(item) {
@@ -410,7 +740,21 @@ fn compile_value<Ctx, Ext, Eff>(
     } else if let ItemKind::Float(value) = item.kind {
         Ok(ProtoValue::Value(Value::Float(OrderedFloat(value))))
     } else if let ItemKind::Brackets(values) = &item.kind {
-        Ok(ProtoValue::List(compile_values(env, values)?))
+        let values = env.nested(item, |env| compile_values(env, values))?;
+        // lists of literals are turned into shared constants
+        let constants = values.iter()
+            .map(|value| match value {
+                ProtoValue::Value(value) => Some(value.clone()),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>();
+        match constants {
+            Some(constants) => Ok(ProtoValue::Value(Value::List(env.interner().values(constants)))),
+            None => Ok(ProtoValue::List(values)),
+        }
+    } else if let Some((var, arguments)) = match_global_call(item) {
+        let arguments = env.nested(item, |env| compile_values(env, arguments))?;
+        env.resolve_call(&var, arguments)
     } else {
         Err(SourceError::new(
             ScriptError::UnrecognizedValue,
@@ -423,22 +767,31 @@ fn compile_value<Ctx, Ext, Eff>(
 fn compile_values<Ctx, Ext, Eff>(
     env: &mut Env<'_, Ctx, Ext, Eff>,
     values: &[Item],
-) -> ScriptResult<ProtoValues<Ext>> {
+) -> ScriptResult<ProtoValues<Ext>>
+where
+    Ext: External,
+{
     let mut compiled = Vec::new();
     for value in values {
         compiled.push(compile_value(env, value)?);
     }
-    Ok(compiled.into())
+    Ok(env.interner().proto_values(compiled))
 }
 
 fn compile_pattern_item<Ctx, Ext, Eff>(
     env: &mut Env<'_, Ctx, Ext, Eff>,
     item: &Item,
-) -> ScriptResult<Pattern<Ext>> {
+) -> ScriptResult<Pattern<Ext>>
+where
+    Ext: External,
+{
     if match_wildcard(item) {
         Ok(Pattern::Ignore)
     } else if let Some(var) = match_var(item) {
         Ok(env.resolve_pattern(&var))
+    } else if let Some(name) = match_ext_predicate(item) {
+        let index = env.ids().resolve(&name, 1).map_err(|error| convert_id_error(&name, error))?;
+        Ok(Pattern::ExtPredicate(index))
     } else if let Some(sym) = match_sym(item) {
         Ok(Pattern::Exact(sym.to_smol_str().into()))
     } else if let ItemKind::Int(value) = item.kind {
@@ -446,7 +799,12 @@ fn compile_pattern_item<Ctx, Ext, Eff>(
     } else if let ItemKind::Float(value) = item.kind {
         Ok(Pattern::Exact(Value::Float(OrderedFloat(value))))
     } else if let ItemKind::Brackets(items) = &item.kind {
-        Ok(Pattern::List(compile_pattern_items(env, items)?))
+        env.nested(item, |env| match items.split_last() {
+            Some((last, prefix)) if match_list_rest(last) => {
+                Ok(Pattern::ListPrefix(compile_pattern_items(env, prefix)?))
+            },
+            _ => Ok(Pattern::List(compile_pattern_items(env, items)?)),
+        })
     } else {
         Err(SourceError::new(
             ScriptError::UnrecognizedPattern,
@@ -456,13 +814,37 @@ fn compile_pattern_item<Ctx, Ext, Eff>(
     }
 }
 
+fn compile_pattern_group<Ctx, Ext, Eff>(
+    env: &mut Env<'_, Ctx, Ext, Eff>,
+    group: &[Item],
+) -> ScriptResult<Pattern<Ext>>
+where
+    Ext: External,
+{
+    if let Some((tag, fields)) = match_tagged_pattern(group) {
+        let mut compiled = vec![Pattern::Exact(tag.to_smol_str().into())];
+        env.nested(&group[0], |env| {
+            for field in group_patterns(fields) {
+                compiled.push(compile_pattern_group(env, field)?);
+            }
+            Ok(())
+        })?;
+        Ok(Pattern::List(env.interner().patterns(compiled)))
+    } else {
+        compile_pattern_item(env, &group[0])
+    }
+}
+
 fn compile_pattern_items<Ctx, Ext, Eff>(
     env: &mut Env<'_, Ctx, Ext, Eff>,
     items: &[Item],
-) -> ScriptResult<Patterns<Ext>> {
+) -> ScriptResult<Patterns<Ext>>
+where
+    Ext: External,
+{
     let mut compiled = Vec::new();
-    for item in items {
-        compiled.push(compile_pattern_item(env, item)?);
+    for group in group_patterns(items) {
+        compiled.push(compile_pattern_group(env, group)?);
     }
-    Ok(compiled.into())
+    Ok(env.interner().patterns(compiled))
 }
@@ -1,30 +1,74 @@
+use smol_str::SmolStr;
 use src_ctx::SourceError;
+use treelang::Item;
 
 use crate::ScriptError;
 use crate::tree::id_space::{IdSpace, GlobalIdx};
-use crate::tree::script::{Pattern, ProtoValue, ScriptResult};
+use crate::tree::script::{Pattern, ProtoValue, ProtoValues, ScriptResult};
 use crate::tree::script::compile::parse::{Var, ItemValue};
 
+use super::{Interner, SourceText};
+
 
 pub struct Env<'a, Ctx, Ext, Eff> {
     ids: &'a IdSpace<Ctx, Ext, Eff>,
+    interner: &'a mut Interner<Ext>,
+    root: SmolStr,
+    source: &'a SourceText,
     vars: Vec<Var>,
     max_vars: usize,
+    allow_shadowing: bool,
+    scope_start: usize,
+    max_nesting: usize,
+    nesting: usize,
 }
 
 impl<'a, Ctx, Ext, Eff> Env<'a, Ctx, Ext, Eff> {
-    pub fn new(ids: &'a IdSpace<Ctx, Ext, Eff>) -> Self {
+    pub fn new(
+        ids: &'a IdSpace<Ctx, Ext, Eff>,
+        interner: &'a mut Interner<Ext>,
+        root: SmolStr,
+        source: &'a SourceText,
+        allow_shadowing: bool,
+        max_nesting: usize,
+    ) -> Self {
         Self {
             ids,
+            interner,
+            root,
+            source,
             vars: Vec::new(),
             max_vars: 0,
+            allow_shadowing,
+            scope_start: 0,
+            max_nesting,
+            nesting: 0,
+        }
+    }
+
+    /// Tracks the depth of nested values and patterns, failing before it exceeds the limit.
+    pub fn nested<F, R>(&mut self, item: &Item, nested: F) -> ScriptResult<R>
+    where
+        F: FnOnce(&mut Self) -> ScriptResult<R>,
+    {
+        if self.nesting >= self.max_nesting {
+            return Err(SourceError::new(
+                ScriptError::NestingTooDeep { limit: self.max_nesting },
+                item.location.start(),
+                "nested too deeply",
+            ));
         }
+        self.nesting += 1;
+        let mut env = scopeguard::guard(self, |env| {
+            env.nesting -= 1;
+        });
+        nested(&mut env)
     }
 
     pub fn declare(&mut self, var: &ItemValue<Var>) -> ScriptResult<usize> {
         let name = var.as_smol_str();
         let span = var.item.location;
-        if self.vars.contains(&var.value) {
+        if self.vars[self.visible_start()..].contains(&var.value) {
             Err(SourceError::new(
                 ScriptError::ShadowedLexical { name: name.clone() },
                 span.start(),
@@ -50,7 +94,11 @@ impl<'a, Ctx, Ext, Eff> Env<'a, Ctx, Ext, Eff> {
         F: FnOnce(&mut Self) -> ScriptResult<R>,
     {
         let len = self.vars.len();
-        let mut env = scopeguard::guard(self, |env| env.vars.truncate(len));
+        let scope_start = std::mem::replace(&mut self.scope_start, len);
+        let mut env = scopeguard::guard(self, |env| {
+            env.vars.truncate(len);
+            env.scope_start = scope_start;
+        });
         for var in vars {
             env.declare(var)?;
         }
@@ -59,7 +107,7 @@ impl<'a, Ctx, Ext, Eff> Env<'a, Ctx, Ext, Eff> {
 
     pub fn resolve_pattern(&mut self, var: &ItemValue<Var>) -> Pattern<Ext> {
         let name = var.value.as_smol_str().as_str();
-        if let Some(index) = self.vars.iter().position(|prev_var| *prev_var == var.value) {
+        if let Some(index) = self.vars.iter().rposition(|prev_var| *prev_var == var.value) {
             Pattern::Lexical(index)
         } else if let Ok(index) = self.ids.resolve::<GlobalIdx>(name, 0) {
             Pattern::Global(index)
@@ -72,7 +120,7 @@ impl<'a, Ctx, Ext, Eff> Env<'a, Ctx, Ext, Eff> {
     pub fn resolve(&self, var: &ItemValue<Var>) -> ScriptResult<ProtoValue<Ext>> {
         let name = var.value.as_smol_str();
         let span = var.item.location;
-        if let Some(index) = self.vars.iter().position(|prev_var| *prev_var == var.value) {
+        if let Some(index) = self.vars.iter().rposition(|prev_var| *prev_var == var.value) {
             Ok(ProtoValue::Lexical(index))
         } else if let Ok(index) = self.ids.resolve::<GlobalIdx>(name, 0) {
             Ok(ProtoValue::Global(index))
@@ -85,6 +133,26 @@ impl<'a, Ctx, Ext, Eff> Env<'a, Ctx, Ext, Eff> {
         }
     }
 
+    pub fn resolve_call(
+        &self,
+        var: &ItemValue<Var>,
+        arguments: ProtoValues<Ext>,
+    ) -> ScriptResult<ProtoValue<Ext>> {
+        let name = var.value.as_smol_str();
+        match self.ids.resolve::<GlobalIdx>(name, arguments.len()) {
+            Ok(index) => Ok(ProtoValue::GlobalCall(index, arguments)),
+            Err(error) => Err(SourceError::new(
+                ScriptError::Identifier { name: name.clone(), error },
+                var.item.location.start(),
+                "identifier",
+            )),
+        }
+    }
+
+    fn visible_start(&self) -> usize {
+        if self.allow_shadowing { self.scope_start } else { 0 }
+    }
+
     pub fn max_vars(&self) -> usize {
         self.max_vars
     }
@@ -92,4 +160,16 @@ impl<'a, Ctx, Ext, Eff> Env<'a, Ctx, Ext, Eff> {
     pub fn ids(&self) -> &IdSpace<Ctx, Ext, Eff> {
         self.ids
     }
+
+    pub fn interner(&mut self) -> &mut Interner<Ext> {
+        self.interner
+    }
+
+    pub fn root(&self) -> &SmolStr {
+        &self.root
+    }
+
+    pub fn source(&self) -> &SourceText {
+        self.source
+    }
 }
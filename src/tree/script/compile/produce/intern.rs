@@ -0,0 +1,62 @@
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::sync::Arc;
+
+use crate::value::{Value, Values};
+use crate::tree::script::{Pattern, Patterns, ProtoValue, ProtoValues};
+
+
+// equal compiled lists are shared across all roots of a compilation
+pub struct Interner<Ext> {
+    proto_values: HashSet<ProtoValues<Ext>>,
+    patterns: HashSet<Patterns<Ext>>,
+    values: HashSet<Values<Ext>>,
+    // `once:` ids are handed out here as they have to be unique across all roots
+    next_once_id: u32,
+}
+
+impl<Ext> Default for Interner<Ext> {
+    fn default() -> Self {
+        Self {
+            proto_values: HashSet::new(),
+            patterns: HashSet::new(),
+            values: HashSet::new(),
+            next_once_id: 0,
+        }
+    }
+}
+
+impl<Ext> Interner<Ext>
+where
+    Ext: Eq + Hash,
+{
+    pub fn proto_values(&mut self, items: Vec<ProtoValue<Ext>>) -> ProtoValues<Ext> {
+        intern(&mut self.proto_values, items)
+    }
+
+    pub fn patterns(&mut self, items: Vec<Pattern<Ext>>) -> Patterns<Ext> {
+        intern(&mut self.patterns, items)
+    }
+
+    pub fn values(&mut self, items: Vec<Value<Ext>>) -> Values<Ext> {
+        intern(&mut self.values, items)
+    }
+
+    pub fn once_id(&mut self) -> u32 {
+        let id = self.next_once_id;
+        self.next_once_id += 1;
+        id
+    }
+}
+
+fn intern<T>(set: &mut HashSet<Arc<[T]>>, items: Vec<T>) -> Arc<[T]>
+where
+    T: Eq + Hash,
+{
+    if let Some(shared) = set.get(&items[..]) {
+        return shared.clone();
+    }
+    let shared: Arc<[T]> = items.into();
+    set.insert(shared.clone());
+    shared
+}
@@ -1,15 +1,21 @@
 use std::borrow::Cow;
 use std::cell::RefCell;
+use std::ops::ControlFlow;
 use std::sync::Arc;
 
 use fastrand::Rng;
 use log::trace;
+use serde::{Deserialize, Serialize};
 use smallvec::SmallVec;
+use smol_str::SmolStr;
 
 use crate::tree::{RefIdx, SeedIdx, External, Effect};
 use crate::{Outcome, Action};
+use crate::tree::outcome::{Explanation, FailedCondition, SelectionFailure};
 use crate::tree::context::{Context, DiscoveryContext};
-use crate::tree::id_space::{EffectIdx, GlobalIdx, QueryIdx, ActionIdx, NodeIdx};
+use crate::tree::id_space::{
+    EffectIdx, GlobalIdx, QueryIdx, ActionIdx, NodeIdx, CondIdx, QueryVisitFn, visit_query_values,
+};
 use crate::value::Value;
 
 
@@ -26,7 +32,7 @@ type Args<Ext> = SmallVec<[Value<Ext>; 4]>;
 
 type Seeds = Arc<[SeedIdx]>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ActionRoot<Ext> {
     pub index: Option<ActionIdx>,
     pub effects: Arc<[(EffectIdx, ProtoValues<Ext>)]>,
@@ -35,7 +41,10 @@ pub struct ActionRoot<Ext> {
     //pub inherit_optional: Arc<[(ActionIdx, ProtoValues<Ext>)]>,
     pub conditions: Nodes<Ext>,
     pub discovery: Nodes<Ext>,
+    pub score: Option<ProtoValue<Ext>>,
     pub lexicals: usize,
+    pub doc: Option<SmolStr>,
+    pub parameters: Arc<[SmolStr]>,
 }
 
 impl<Ext> ActionRoot<Ext>
@@ -50,6 +59,9 @@ where
         let mut lex = Lex::with_capacity(self.lexicals);
         for node in self.discovery.iter() {
             node.eval(ctx, &mut lex);
+            if ctx.is_discovery_pruned() {
+                break;
+            }
         }
     }
 
@@ -58,54 +70,125 @@ where
         ctx: &C,
         arguments: &[Value<Ext>],
     ) -> Outcome<Ext, Eff>
+    where
+        C: Context<Ctx, Ext, Eff>,
+        Eff: Effect,
+    {
+        match self.try_build(ctx, arguments) {
+            Ok(action) => ctx.action(action),
+            Err(outcome) => outcome,
+        }
+    }
+
+    pub fn eval_applicable<C, Ctx, Eff>(
+        &self,
+        ctx: &C,
+        arguments: &[Value<Ext>],
+    ) -> Outcome<Ext, Eff>
+    where
+        C: Context<Ctx, Ext, Eff>,
+        Eff: Effect,
+    {
+        match self.try_build(&ctx.to_probe(), arguments) {
+            Ok(_) => Outcome::Success,
+            Err(outcome) => outcome,
+        }
+    }
+
+    fn try_build<C, Ctx, Eff>(
+        &self,
+        ctx: &C,
+        arguments: &[Value<Ext>],
+    ) -> Result<Action<Ext, Eff>, Outcome<Ext, Eff>>
     where
         C: Context<Ctx, Ext, Eff>,
         Eff: Effect,
     {
         let mut lex = Lex::with_capacity(self.lexicals);
         lex.extend(arguments.iter().cloned());
-        if !self.conditions_ok(ctx, &mut lex) {
-            return Outcome::Failure;
+        match self.eval_conditions(ctx, &mut lex) {
+            Outcome::Success => {},
+            Outcome::Running => return Err(Outcome::Running),
+            _ => return Err(Outcome::Failure),
         }
+        // non-numeric scores fail the action
+        let score = match self.score.as_ref().map(|score| score.reify(ctx, &mut lex)) {
+            None => 0.0,
+            Some(Value::Int(score)) => score as f32,
+            Some(Value::Float(score)) => score.0,
+            Some(_) => return Err(Outcome::Failure),
+        };
         let mut effects = SmallVec::<[Eff; 32]>::with_capacity(self.effects.len());
-        for (index, arguments) in self.effects.iter() {
-            let arguments: Args<Ext> = reify_values(ctx, &mut lex, arguments.iter());
-            if let Some(effect) = ctx.tree().ids.get(*index)(ctx.view(), &arguments) {
-                effects.push(effect);
-            } else {
-                return Outcome::Failure;
+        let lex_len = lex.len();
+        for (index, effect_arguments) in self.effects.iter() {
+            lex.truncate(lex_len);
+            reify_onto_lex(ctx, &mut lex, effect_arguments);
+            let handler = ctx.tree().ids.get(*index);
+            // stateful effects are assumed to succeed instead of modifying the scratch
+            if let (true, Some(scratch_type)) = (ctx.is_effect_dry_run(), handler.scratch_type()) {
+                if ctx.scratch().is_some_and(|scratch| scratch.accepts(scratch_type)) {
+                    continue;
+                }
+                return Err(Outcome::Failure);
+            }
+            let effects_len = effects.len();
+            let view = ctx.view();
+            if !handler.call(view, &lex[lex_len..], Some(arguments), ctx.scratch(), &mut effects) {
+                return Err(Outcome::Failure);
+            }
+            if ctx.is_effect_dry_run() {
+                effects.truncate(effects_len);
             }
         }
+        lex.truncate(lex_len);
         let mut inherited = Vec::new();
         let collection = RefCell::new(&mut inherited);
         let discovery_ctx = DiscoveryContext::from_context(ctx, &collection, None);
         for node in self.inherit.iter() {
             let result = node.eval(&discovery_ctx, &mut lex);
-            if result.is_failure() {
-                return Outcome::Failure;
+            if result.is_failure() || result.is_running() {
+                return Err(result);
             }
         }
         for action in inherited {
             effects.extend(action.effects().iter().cloned());
         }
-        ctx.action(Action::new(
+        Ok(Action::new(
             self.index.unwrap(),
             arguments.into(),
             effects.into_iter().collect(),
+            score,
         ))
     }
 
-    fn conditions_ok<C, Ctx, Eff>(
+    fn eval_conditions<C, Ctx, Eff>(
         &self,
         ctx: &C,
         lex: &mut Lex<Ext>,
-    ) -> bool
+    ) -> Outcome<Ext, Eff>
     where
         C: Context<Ctx, Ext, Eff>,
         Eff: Effect,
     {
         let ctx = ctx.to_inactive_if_active();
-        eval_sequence(ctx.as_ref(), lex, &self.conditions).is_success()
+        eval_sequence(ctx.as_ref(), lex, &self.conditions)
+    }
+}
+
+impl<Ext> ActionRoot<Ext> {
+    pub fn structural_eq(&self, other: &Self) -> bool
+    where
+        Ext: PartialEq,
+    {
+        self.index == other.index
+            && self.effects == other.effects
+            && nodes_structural_eq(&self.inherit, &other.inherit)
+            && nodes_structural_eq(&self.conditions, &other.conditions)
+            && nodes_structural_eq(&self.discovery, &other.discovery)
+            && self.score == other.score
+            && self.lexicals == other.lexicals
+            && self.doc == other.doc
+            && self.parameters == other.parameters
     }
 }
 
@@ -117,16 +200,21 @@ impl<Ext> Default for ActionRoot<Ext> {
             inherit: Arc::new([]),
             conditions: Arc::new([]),
             discovery: Arc::new([]),
+            score: None,
             lexicals: 0,
+            doc: None,
+            parameters: Arc::new([]),
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NodeRoot<Ext> {
     pub index: Option<NodeIdx>,
     pub node: Node<Ext>,
     pub lexicals: usize,
+    pub doc: Option<SmolStr>,
+    pub parameters: Arc<[SmolStr]>,
 }
 
 impl<Ext> NodeRoot<Ext>
@@ -148,19 +236,35 @@ where
     }
 }
 
+impl<Ext> NodeRoot<Ext> {
+    pub fn structural_eq(&self, other: &Self) -> bool
+    where
+        Ext: PartialEq,
+    {
+        self.index == other.index
+            && self.node.structural_eq(&other.node)
+            && self.lexicals == other.lexicals
+            && self.doc == other.doc
+            && self.parameters == other.parameters
+    }
+}
+
 impl<Ext> Default for NodeRoot<Ext> {
     fn default() -> Self {
         Self {
             index: None,
             node: Node::Failure,
             lexicals: 0,
+            doc: None,
+            parameters: Arc::new([]),
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum ProtoValue<Ext> {
     Global(GlobalIdx),
+    GlobalCall(GlobalIdx, ProtoValues<Ext>),
     Lexical(usize),
     Value(Value<Ext>),
     List(ProtoValues<Ext>),
@@ -173,7 +277,11 @@ impl<Ext> ProtoValue<Ext> {
         Ext: Clone,
     {
         match self {
-            Self::Global(index) => ctx.tree().ids.get(*index)(ctx.view()),
+            Self::Global(index) => ctx.global(*index),
+            Self::GlobalCall(index, arguments) => {
+                let arguments: SmallVec<[_; 4]> = reify_values(ctx, lex, arguments.iter());
+                ctx.global_call(*index, &arguments)
+            },
             Self::Lexical(index) => lex[*index].clone(),
             Self::Value(value) => value.clone(),
             Self::List(values) => Value::List(reify_values(ctx, lex, values.iter())),
@@ -194,25 +302,140 @@ where
     values.into_iter().map(|pv| pv.reify(ctx, lex)).collect()
 }
 
-#[derive(Debug, Clone)]
+// arguments that don't escape the call are reified onto the end of the lexicals so
+// the buffer is reused, callers are responsible for truncating afterwards
+fn reify_onto_lex<C, Ctx, Ext, Eff>(ctx: &C, lex: &mut Lex<Ext>, values: &[ProtoValue<Ext>])
+where
+    C: Context<Ctx, Ext, Eff>,
+    Ext: Clone,
+{
+    for value in values {
+        let value = value.reify(ctx, lex);
+        lex.push(value);
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Node<Ext> {
     Success,
     Failure,
     Dispatch(Dispatch, Nodes<Ext>),
     Ref(RefIdx, RefMode, ProtoValues<Ext>),
-    Query(Pattern<Ext>, QueryIdx, ProtoValues<Ext>, QueryMode, Nodes<Ext>),
-    Match(ProtoValues<Ext>, Patterns<Ext>, Nodes<Ext>),
-    Random(u64, Seeds, Nodes<Ext>, bool),
+    Query(Pattern<Ext>, QuerySource, ProtoValues<Ext>, QueryMode, Nodes<Ext>),
+    // the optional branch runs when the patterns don't match
+    Match(ProtoValues<Ext>, Patterns<Ext>, Nodes<Ext>, Option<CondElseBranch<Ext>>),
+    Random(u64, Seeds, Nodes<Ext>, RandomMode),
+    Applicable(ActionIdx, ProtoValues<Ext>),
     Cond(CondBranches<Ext>, Option<CondElseBranch<Ext>>),
+    // the description names the asserted condition and its declaration
+    Assert(Box<Node<Ext>>, Location, SmolStr),
+    // the id is unique per tree and keys the cached outcome, along with the visible lexicals
+    Once(u32, Nodes<Ext>),
+    // skips the remaining discovery nodes of the action being discovered
+    PruneDiscovery,
+    // the children have to finish before the `$now` global advanced past the limit
+    Within(ProtoValue<Ext>, GlobalIdx, Nodes<Ext>),
+}
+
+// lines and columns start at one
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Location {
+    pub origin: Arc<str>,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", self.origin, self.line, self.column)
+    }
+}
+
+fn nodes_structural_eq<Ext>(a: &[Node<Ext>], b: &[Node<Ext>]) -> bool
+where
+    Ext: PartialEq,
+{
+    a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| a.structural_eq(b))
 }
 
 impl<Ext> Node<Ext> {
+    // compile-time random seeds, once ids and assert locations are ignored
+    pub fn structural_eq(&self, other: &Self) -> bool
+    where
+        Ext: PartialEq,
+    {
+        match (self, other) {
+            (Self::Success, Self::Success)
+            | (Self::Failure, Self::Failure)
+            | (Self::PruneDiscovery, Self::PruneDiscovery) => true,
+            (Self::Dispatch(a, a_nodes), Self::Dispatch(b, b_nodes)) => {
+                a == b && nodes_structural_eq(a_nodes, b_nodes)
+            },
+            (Self::Ref(a, a_mode, a_args), Self::Ref(b, b_mode, b_args)) => {
+                a == b && a_mode == b_mode && a_args == b_args
+            },
+            (
+                Self::Query(a_pattern, a, a_args, a_mode, a_nodes),
+                Self::Query(b_pattern, b, b_args, b_mode, b_nodes),
+            ) => {
+                a_pattern == b_pattern && a == b && a_args == b_args && a_mode == b_mode
+                    && nodes_structural_eq(a_nodes, b_nodes)
+            },
+            (
+                Self::Match(a_values, a_patterns, a_nodes, a_else),
+                Self::Match(b_values, b_patterns, b_nodes, b_else),
+            ) => {
+                a_values == b_values && a_patterns == b_patterns
+                    && nodes_structural_eq(a_nodes, b_nodes)
+                    && match (a_else, b_else) {
+                        (Some(a), Some(b)) => a.structural_eq(b),
+                        (None, None) => true,
+                        _ => false,
+                    }
+            },
+            (
+                Self::Random(_, a_seeds, a_nodes, a_mode),
+                Self::Random(_, b_seeds, b_nodes, b_mode),
+            ) => {
+                a_seeds == b_seeds && a_mode == b_mode && nodes_structural_eq(a_nodes, b_nodes)
+            },
+            (Self::Applicable(a, a_args), Self::Applicable(b, b_args)) => {
+                a == b && a_args == b_args
+            },
+            (Self::Cond(a_branches, a_else), Self::Cond(b_branches, b_else)) => {
+                a_branches.len() == b_branches.len()
+                    && a_branches.iter().zip(b_branches.iter()).all(|((a_cond, a_body), b)| {
+                        let (b_cond, b_body) = b;
+                        a_cond.structural_eq(b_cond) && a_body.structural_eq(b_body)
+                    })
+                    && match (a_else, b_else) {
+                        (Some(a), Some(b)) => a.structural_eq(b),
+                        (None, None) => true,
+                        _ => false,
+                    }
+            },
+            (Self::Assert(a, _, a_description), Self::Assert(b, _, b_description)) => {
+                a_description == b_description && a.structural_eq(b)
+            },
+            (Self::Once(_, a_nodes), Self::Once(_, b_nodes)) => {
+                nodes_structural_eq(a_nodes, b_nodes)
+            },
+            (Self::Within(a_limit, a_now, a_nodes), Self::Within(b_limit, b_now, b_nodes)) => {
+                a_limit == b_limit && a_now == b_now && nodes_structural_eq(a_nodes, b_nodes)
+            },
+            _ => false,
+        }
+    }
+
     fn eval<C, Ctx, Eff>(&self, ctx: &C, lex: &mut Lex<Ext>) -> Outcome<Ext, Eff>
     where
         C: Context<Ctx, Ext, Eff>,
         Ext: External,
         Eff: Effect,
     {
+        if !ctx.consume_fuel() {
+            return Outcome::Failure;
+        }
         match self {
             Self::Failure => Outcome::Failure,
             Self::Success => Outcome::Success,
@@ -220,10 +443,20 @@ impl<Ext> Node<Ext> {
                 dispatch.eval_branches(ctx, lex, branches)
             },
             Self::Ref(ref_kind, mode, arguments) => {
-                let arguments: Args<Ext> = reify_values(ctx, lex, arguments.iter());
-                ref_kind.eval(ctx, *mode, &arguments)
+                let lex_len = lex.len();
+                reify_onto_lex(ctx, lex, arguments);
+                let result = ref_kind.eval(ctx, *mode, &lex[lex_len..]);
+                lex.truncate(lex_len);
+                result
+            },
+            Self::Applicable(index, arguments) => {
+                let lex_len = lex.len();
+                reify_onto_lex(ctx, lex, arguments);
+                let result = ctx.tree().ids.get(*index).eval_applicable(ctx, &lex[lex_len..]);
+                lex.truncate(lex_len);
+                result
             },
-            Self::Match(values, patterns, branches) => {
+            Self::Match(values, patterns, branches, else_branch) => {
                 let values: Args<Ext> = reify_values(ctx, lex, values.iter());
                 let lex_len = lex.len();
                 let mut lex = scopeguard::guard(lex, |lex| lex.truncate(lex_len));
@@ -232,30 +465,46 @@ impl<Ext> Node<Ext> {
                     .all(|(p, v)| p.try_apply(ctx, &mut lex, v));
                 if is_matched {
                     eval_sequence(ctx, &mut lex, branches)
+                } else if let Some(else_branch) = else_branch.as_ref() {
+                    lex.truncate(lex_len);
+                    else_branch.eval(ctx, &mut lex)
                 } else {
                     Outcome::Failure
                 }
             },
-            Self::Query(pattern, index, arguments, mode, branches) => {
+            Self::Query(pattern, source, arguments, mode, branches) => {
                 let arguments: Args<Ext> = reify_values(ctx, lex, arguments.iter());
-                mode.eval_query(ctx, lex, *index, &arguments, pattern, branches)
+                mode.eval_query(ctx, lex, *source, &arguments, pattern, branches)
             },
-            Self::Random(seed, ctx_seeds, branches, check_any) => {
-                let mut branches: SmallVec::<[_; 16]> = branches.iter().cloned().collect();
+            Self::Random(seed, ctx_seeds, branches, mode) => {
                 let mut seed = *seed;
                 for ctx_seed in ctx_seeds.iter() {
                     let ctx_seed = ctx.tree().ids.get(*ctx_seed)(ctx.view());
                     seed = seed.wrapping_add(ctx_seed);
                 }
                 let rng = Rng::with_seed(seed);
+                if *mode == RandomMode::Select {
+                    let probe_ctx = ctx.to_probe();
+                    let mut viable = SmallVec::<[&Node<Ext>; 16]>::new();
+                    for node in branches.iter() {
+                        if node.eval(&probe_ctx, lex).is_non_failure() {
+                            viable.push(node);
+                        }
+                    }
+                    if viable.is_empty() {
+                        return Outcome::Failure;
+                    }
+                    return viable[rng.usize(..viable.len())].eval(ctx, lex);
+                }
+                let mut branches: SmallVec::<[_; 16]> = branches.iter().cloned().collect();
                 rng.shuffle(&mut branches);
                 while let Some(node) = branches.pop() {
                     let result = node.eval(ctx, lex);
-                    if result.is_success() {
+                    if result.is_success() || result.is_running() {
                         return result;
                     }
                     if result.is_action() {
-                        if *check_any {
+                        if *mode == RandomMode::CheckAny {
                             for node in branches {
                                 if node.eval(ctx, lex).is_success() {
                                     return Outcome::Success;
@@ -287,6 +536,32 @@ impl<Ext> Node<Ext> {
                     Outcome::Failure
                 }
             },
+            Self::Assert(node, location, description) => match node.eval(ctx, lex) {
+                Outcome::Failure if cfg!(debug_assertions) => {
+                    panic!("assertion failed at {location}: {description}")
+                },
+                Outcome::Failure => Outcome::Success,
+                other => other,
+            },
+            Self::Once(id, branches) => {
+                let lexicals = lex.to_vec();
+                ctx.cache().once(*id, ctx.is_active(), &lexicals, || {
+                    eval_sequence(ctx, lex, branches)
+                })
+            },
+            Self::PruneDiscovery => {
+                ctx.prune_discovery();
+                Outcome::Success
+            },
+            Self::Within(limit, now, branches) => {
+                let limit = limit.reify(ctx, lex);
+                let start = ctx.global(*now);
+                match eval_sequence(ctx, lex, branches) {
+                    result @ (Outcome::Failure | Outcome::Running) => result,
+                    result if is_within_limit(&start, &ctx.global(*now), &limit) => result,
+                    _ => Outcome::Failure,
+                }
+            },
         }
     }
 
@@ -308,7 +583,22 @@ impl RefIdx {
         Eff: Effect,
     {
         let ctx = mode.apply(ctx);
-        let res = ctx.cache().get(*self, arguments, ctx.is_active(), || {
+        let stepper = ctx.stepper();
+        if stepper.is_some_and(|stepper| {
+            stepper.enter(ctx.tree().ids.ref_name(*self), arguments).is_break()
+        }) {
+            return Outcome::Failure;
+        }
+        if let Some(stack) = ctx.explain_stack() {
+            stack.borrow_mut().push(Explanation {
+                ref_name: ctx.tree().ids.ref_name(*self).clone(),
+                arguments: arguments.into(),
+                outcome: Outcome::Failure,
+                children: Vec::new(),
+            });
+        }
+        let is_active = ctx.is_active() && !self.is_activity_invariant();
+        let res = ctx.cache().get(*self, arguments, is_active, || {
             trace!("eval: {}{:?}", ctx.tree().ids.ref_name(*self), arguments);
             match self {
                 Self::Action(index) => {
@@ -327,11 +617,28 @@ impl RefIdx {
             }
         });
         trace!("outcome: {}{:?} => {:?}", ctx.tree().ids.ref_name(*self), arguments, res);
+        if let (Self::Cond(_), true, Some(diagnosis)) = (self, res.is_failure(), ctx.diagnosis()) {
+            diagnosis.borrow_mut().last_failed = Some(FailedCondition {
+                name: ctx.tree().ids.ref_name(*self).clone(),
+                arguments: arguments.into(),
+            });
+        }
+        if let Some(stack) = ctx.explain_stack() {
+            let mut stack = stack.borrow_mut();
+            let mut explanation = stack.pop().expect("explanation must be on the stack");
+            explanation.outcome = res.clone();
+            if let Some(parent) = stack.last_mut() {
+                parent.children.push(explanation);
+            }
+        }
+        if let Some(stepper) = stepper {
+            stepper.leave();
+        }
         res
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum RefMode {
     Query,
     Inherit,
@@ -362,10 +669,19 @@ where
     Dispatch::Sequence.eval_branches(ctx, lex, nodes)
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RandomMode {
+    First,
+    CheckAny,
+    Select,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Dispatch {
     Sequence,
     Selection,
+    // probes all branches and runs the one with the highest scoring action
+    BestSelection,
     None,
     Visit,
 }
@@ -393,17 +709,43 @@ impl Dispatch {
                 Outcome::Success
             },
             Dispatch::Selection => 'eval: {
+                let mut failures = Vec::new();
                 for node in nodes {
+                    begin_diagnosed_branch(ctx);
                     let result = node.eval(ctx, lex);
                     if result.is_non_failure() {
                         break 'eval result;
                     }
+                    end_diagnosed_branch(ctx, &mut failures);
                 }
+                record_selection_failure(ctx, failures);
                 Outcome::Failure
             },
+            Dispatch::BestSelection => {
+                let probe_ctx = ctx.to_probe();
+                let mut best = None::<(f32, &Node<Ext>)>;
+                for node in nodes {
+                    if let Outcome::Action(action) = node.eval(&probe_ctx, lex) {
+                        // earlier branches win ties
+                        if best.is_none_or(|(score, _)| action.score() > score) {
+                            best = Some((action.score(), node));
+                        }
+                    }
+                }
+                match best {
+                    Some((_, node)) => node.eval(ctx, lex),
+                    None => Dispatch::Selection.eval_branches(ctx, lex, nodes),
+                }
+            },
             Dispatch::None => 'eval: {
+                // the children are evaluated inactively, and discovery doesn't collect the
+                // actions within either
+                let ctx = ctx.to_inactive();
                 for node in nodes {
-                    let result = node.eval(ctx, lex);
+                    let result = node.eval(&ctx, lex);
+                    if result.is_running() {
+                        break 'eval result;
+                    }
                     if result.is_non_failure() {
                         break 'eval Outcome::Failure;
                     }
@@ -420,13 +762,53 @@ impl Dispatch {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+// list sources iterate the items of their single argument
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum QuerySource {
+    Query(QueryIdx),
+    List,
+}
+
+impl QuerySource {
+    fn query<C, Ctx, Ext, Eff>(
+        &self,
+        ctx: &C,
+        arguments: &[Value<Ext>],
+        visit_fn: &mut QueryVisitFn<'_, Ext, Eff>,
+    ) -> Outcome<Ext, Eff>
+    where
+        C: Context<Ctx, Ext, Eff>,
+        Ext: Clone,
+    {
+        match self {
+            Self::Query(index) => ctx.tree().ids.get(*index).query(ctx.view(), arguments, visit_fn),
+            Self::List => {
+                let mut items = arguments.iter()
+                    .filter_map(Value::list)
+                    .flat_map(|items| items.iter())
+                    .cloned();
+                visit_query_values(&mut items, visit_fn)
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum QueryMode {
     Sequence,
     Selection,
+    // skips values not matching the pattern, as does `Last`
     First,
+    // like `First`, but fails when the very first value doesn't match the pattern
+    FirstOnly,
     Last,
     Visit,
+    IndexedSequence,
+    // like `Sequence`, but keeps going on actions and merges their effects into the first one
+    ActionSequence,
+    // like `ActionSequence`, but every child is evaluated independently for each value, only
+    // actions of the first produced kind are merged, and it fails when no child ever passes
+    Each,
 }
 
 impl QueryMode {
@@ -434,7 +816,7 @@ impl QueryMode {
         &self,
         ctx: &C,
         lex: &mut Lex<Ext>,
-        index: QueryIdx,
+        source: QuerySource,
         arguments: &[Value<Ext>],
         pattern: &Pattern<Ext>,
         branches: &Nodes<Ext>,
@@ -446,90 +828,170 @@ impl QueryMode {
     {
         let lex_len = lex.len();
         let mut lex = scopeguard::guard(lex, move |lex| lex.truncate(lex_len));
-        match self {
-            Self::Sequence => {
-                let query_fn = ctx.tree().ids.get(index);
-                query_fn(ctx.view(), arguments, &mut |iter| {
-                    'values: for topic_value in iter {
-                        lex.truncate(lex_len);
-                        if !pattern.try_apply(ctx, &mut lex, &topic_value) {
-                            continue 'values;
-                        }
-                        let result = eval_sequence(ctx, &mut lex, branches);
-                        if result.is_non_success() {
-                            return result;
-                        }
-                    }
-                    Outcome::Success
-                })
-            },
-            Self::Selection => {
-                let query_fn = ctx.tree().ids.get(index);
-                query_fn(ctx.view(), arguments, &mut |iter| {
-                    'values: for topic_value in iter {
-                        lex.truncate(lex_len);
-                        if !pattern.try_apply(ctx, &mut lex, &topic_value) {
-                            continue 'values;
-                        }
-                        let result = eval_sequence(ctx, &mut lex, branches);
-                        if result.is_non_failure() {
-                            return result;
-                        }
-                    }
-                    Outcome::Failure
-                })
-            },
-            Self::First => {
-                let query_fn = ctx.tree().ids.get(index);
-                query_fn(ctx.view(), arguments, &mut |iter| {
-                    'values: for topic_value in iter {
-                        lex.truncate(lex_len);
-                        if !pattern.try_apply(ctx, &mut lex, &topic_value) {
-                            continue 'values;
-                        }
-                        return eval_sequence(ctx, &mut lex, branches);
+        let mut topic_index = 0usize;
+        let mut actions = SmallVec::<[Action<Ext, Eff>; 8]>::new();
+        let mut last = None;
+        let mut failures = Vec::new();
+        // `None` until `Each` binds a value, then whether any of its children passed
+        let mut each_passed = None;
+        source.query(ctx, arguments, &mut |topic_value| {
+            let Some(mut topic_value) = topic_value else {
+                lex.truncate(lex_len);
+                return ControlFlow::Break(match self {
+                    Self::Sequence | Self::IndexedSequence | Self::Visit => Outcome::Success,
+                    Self::Selection => {
+                        record_selection_failure(ctx, std::mem::take(&mut failures));
+                        Outcome::Failure
+                    },
+                    Self::First | Self::FirstOnly => Outcome::Failure,
+                    Self::ActionSequence => merge_actions(&actions),
+                    Self::Each if each_passed == Some(false) => Outcome::Failure,
+                    Self::Each => {
+                        let index = actions.first().map(|action| action.index());
+                        actions.retain(|action| Some(action.index()) == index);
+                        merge_actions(&actions)
+                    },
+                    Self::Last => {
+                        let Some(topic_value) = last.take() else {
+                            return ControlFlow::Break(Outcome::Failure);
+                        };
+                        pattern.try_apply(ctx, &mut lex, &topic_value);
+                        eval_sequence(ctx, &mut lex, branches)
+                    },
+                });
+            };
+            if *self == Self::IndexedSequence {
+                let index = i32::try_from(topic_index).unwrap_or(i32::MAX);
+                topic_value = Value::List([Value::Int(index), topic_value].into());
+            }
+            topic_index += 1;
+            if !ctx.consume_fuel() {
+                return ControlFlow::Break(Outcome::Failure);
+            }
+            lex.truncate(lex_len);
+            if !pattern.try_apply(ctx, &mut lex, &topic_value) {
+                if *self == Self::FirstOnly {
+                    return ControlFlow::Break(Outcome::Failure);
+                }
+                return ControlFlow::Continue(());
+            }
+            match self {
+                Self::Sequence | Self::IndexedSequence => {
+                    let result = eval_sequence(ctx, &mut lex, branches);
+                    if result.is_non_success() {
+                        return ControlFlow::Break(result);
                     }
-                    Outcome::Failure
-                })
-            },
-            Self::Last => {
-                let query_fn = ctx.tree().ids.get(index);
-                query_fn(ctx.view(), arguments, &mut |iter| {
-                    let mut last = Outcome::Failure;
-                    'values: for topic_value in iter {
-                        lex.truncate(lex_len);
-                        if !pattern.try_apply(ctx, &mut lex, &topic_value) {
-                            continue 'values;
+                },
+                Self::ActionSequence => match eval_sequence(ctx, &mut lex, branches) {
+                    Outcome::Success => {},
+                    Outcome::Action(action) => actions.push(action),
+                    other => return ControlFlow::Break(other),
+                },
+                Self::Each => {
+                    each_passed.get_or_insert(false);
+                    for node in branches.iter() {
+                        match node.eval(ctx, &mut lex) {
+                            Outcome::Action(action) => actions.push(action),
+                            Outcome::Running => return ControlFlow::Break(Outcome::Running),
+                            Outcome::Success => {},
+                            Outcome::Failure => continue,
                         }
-                        last = eval_sequence(ctx, &mut lex, branches);
+                        each_passed = Some(true);
                     }
-                    last
-                })
-            },
-            Self::Visit => {
-                let query_fn = ctx.tree().ids.get(index);
-                query_fn(ctx.view(), arguments, &mut |iter| {
-                    'values: for topic_value in iter {
-                        lex.truncate(lex_len);
-                        if !pattern.try_apply(ctx, &mut lex, &topic_value) {
-                            continue 'values;
-                        }
-                        eval_sequence(ctx, &mut lex, branches);
+                },
+                Self::Selection => {
+                    begin_diagnosed_branch(ctx);
+                    let result = eval_sequence(ctx, &mut lex, branches);
+                    if result.is_non_failure() {
+                        return ControlFlow::Break(result);
                     }
-                    Outcome::Success
-                })
-            },
-        }
+                    end_diagnosed_branch(ctx, &mut failures);
+                },
+                Self::First | Self::FirstOnly => {
+                    return ControlFlow::Break(eval_sequence(ctx, &mut lex, branches));
+                },
+                Self::Last => last = Some(topic_value),
+                Self::Visit => {
+                    eval_sequence(ctx, &mut lex, branches);
+                },
+            }
+            ControlFlow::Continue(())
+        })
     }
 }
 
-#[derive(Debug, Clone)]
+fn begin_diagnosed_branch<C, Ctx, Ext, Eff>(ctx: &C)
+where
+    C: Context<Ctx, Ext, Eff>,
+{
+    if let Some(diagnosis) = ctx.diagnosis() {
+        diagnosis.borrow_mut().last_failed = None;
+    }
+}
+
+// the failed condition is kept so an enclosing selection can attribute its branch to it
+fn end_diagnosed_branch<C, Ctx, Ext, Eff>(
+    ctx: &C,
+    failures: &mut Vec<Option<FailedCondition<Ext>>>,
+)
+where
+    C: Context<Ctx, Ext, Eff>,
+{
+    if let Some(diagnosis) = ctx.diagnosis() {
+        failures.push(diagnosis.borrow().last_failed.clone());
+    }
+}
+
+fn record_selection_failure<C, Ctx, Ext, Eff>(
+    ctx: &C,
+    branches: Vec<Option<FailedCondition<Ext>>>,
+)
+where
+    C: Context<Ctx, Ext, Eff>,
+{
+    if let Some(diagnosis) = ctx.diagnosis() {
+        diagnosis.borrow_mut().failures.push(SelectionFailure { branches });
+    }
+}
+
+// times and the limit can be given as integers or floats
+fn is_within_limit<Ext>(start: &Value<Ext>, end: &Value<Ext>, limit: &Value<Ext>) -> bool {
+    let as_f64 = |value: &Value<Ext>| match value {
+        Value::Int(value) => Some(f64::from(*value)),
+        Value::Float(value) => Some(f64::from(value.0)),
+        _ => None,
+    };
+    match (as_f64(start), as_f64(end), as_f64(limit)) {
+        (Some(start), Some(end), Some(limit)) => end - start <= limit,
+        _ => false,
+    }
+}
+
+fn merge_actions<Ext, Eff>(actions: &[Action<Ext, Eff>]) -> Outcome<Ext, Eff>
+where
+    Ext: Clone,
+    Eff: Clone,
+{
+    let Some((first, rest)) = actions.split_first() else {
+        return Outcome::Success;
+    };
+    let effects = first.effects().iter()
+        .chain(rest.iter().flat_map(|action| action.effects()))
+        .cloned()
+        .collect();
+    Outcome::Action(Action::new(first.index(), first.arguments().into(), effects, first.score()))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Pattern<Ext> {
     Exact(Value<Ext>),
     Bind,
     Lexical(usize),
     Global(GlobalIdx),
     List(Patterns<Ext>),
+    // matches lists with at least as many items, ignoring the remaining ones
+    ListPrefix(Patterns<Ext>),
+    ExtPredicate(CondIdx),
     Ignore,
 }
 
@@ -552,7 +1014,11 @@ impl<Ext> Pattern<Ext> {
             },
             Self::Exact(exact) => value == exact,
             Self::Lexical(index) => *value == lex[*index],
-            Self::Global(index) => *value == ctx.tree().ids.get(*index)(ctx.view()),
+            Self::Global(index) => *value == ctx.global(*index),
+            Self::ExtPredicate(index) => {
+                matches!(value, Value::Ext(_))
+                    && ctx.tree().ids.get(*index)(ctx.view(), std::slice::from_ref(value))
+            },
             Self::List(patterns) => {
                 if let Value::List(values) = value {
                     patterns.len() == values.len() && patterns
@@ -563,6 +1029,16 @@ impl<Ext> Pattern<Ext> {
                     false
                 }
             },
+            Self::ListPrefix(patterns) => {
+                if let Value::List(values) = value {
+                    patterns.len() <= values.len() && patterns
+                        .iter()
+                        .zip(values.iter())
+                        .all(|(p, v)| p.try_apply(ctx, lex, v))
+                } else {
+                    false
+                }
+            },
         }
     }
 }
\ No newline at end of file
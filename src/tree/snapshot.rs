@@ -0,0 +1,139 @@
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use smol_str::SmolStr;
+
+use super::{BehaviorTree, Kind};
+use super::builder::BehaviorTreeBuilder;
+use super::id_space::{
+    IdSpace, IdSpaceIndex, GlobalIdx, EffectIdx, CondIdx, CustomIdx, SeedIdx, QueryIdx, NodeIdx,
+    ActionIdx,
+};
+use super::script::{NodeRoot, ActionRoot};
+
+
+#[derive(Debug, thiserror::Error)]
+pub enum SnapshotError {
+    #[error("Invalid snapshot data: {_0}")]
+    Data(#[from] bincode::Error),
+    #[error("Snapshot requires {kind} `{name}` with arity {arity}")]
+    MissingHost { kind: Kind, name: SmolStr, arity: usize },
+    #[error("Snapshot does not know about {kind} `{name}`")]
+    UnexpectedHost { kind: Kind, name: SmolStr },
+    #[error("Conflict with {kind} definition of `{name}`")]
+    Conflict { kind: Kind, name: SmolStr },
+}
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot<Ext> {
+    globals: Vec<Hook>,
+    effects: Vec<Hook>,
+    conditions: Vec<Hook>,
+    customs: Vec<Hook>,
+    seeds: Vec<Hook>,
+    queries: Vec<Hook>,
+    node_roots: Vec<Entry<NodeRoot<Ext>>>,
+    action_roots: Vec<Entry<ActionRoot<Ext>>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Hook {
+    name: SmolStr,
+    arity: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry<T> {
+    name: SmolStr,
+    arity: usize,
+    root: T,
+}
+
+impl<Ctx, Ext, Eff> BehaviorTree<Ctx, Ext, Eff> {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, SnapshotError>
+    where
+        Ext: Serialize + Clone,
+    {
+        let snapshot = Snapshot {
+            globals: hooks::<GlobalIdx, _, _, _>(&self.ids),
+            effects: hooks::<EffectIdx, _, _, _>(&self.ids),
+            conditions: hooks::<CondIdx, _, _, _>(&self.ids),
+            customs: hooks::<CustomIdx, _, _, _>(&self.ids),
+            seeds: hooks::<SeedIdx, _, _, _>(&self.ids),
+            queries: hooks::<QueryIdx, _, _, _>(&self.ids),
+            node_roots: entries::<NodeIdx, _, _, _, _>(&self.ids),
+            action_roots: entries::<ActionIdx, _, _, _, _>(&self.ids),
+        };
+        Ok(bincode::serialize(&snapshot)?)
+    }
+
+    pub fn from_bytes(
+        builder: BehaviorTreeBuilder<Ctx, Ext, Eff>,
+        bytes: &[u8],
+    ) -> Result<Self, SnapshotError>
+    where
+        Ext: DeserializeOwned,
+    {
+        let snapshot: Snapshot<Ext> = bincode::deserialize(bytes)?;
+        let mut ids = builder.ids;
+        check_hooks::<GlobalIdx, _, _, _>(&ids, &snapshot.globals)?;
+        check_hooks::<EffectIdx, _, _, _>(&ids, &snapshot.effects)?;
+        check_hooks::<CondIdx, _, _, _>(&ids, &snapshot.conditions)?;
+        check_hooks::<CustomIdx, _, _, _>(&ids, &snapshot.customs)?;
+        check_hooks::<SeedIdx, _, _, _>(&ids, &snapshot.seeds)?;
+        check_hooks::<QueryIdx, _, _, _>(&ids, &snapshot.queries)?;
+        for Entry { name, arity, root } in snapshot.node_roots {
+            ids.set::<NodeIdx>(name.clone(), Arc::new(root), arity)
+                .map_err(|kind| SnapshotError::Conflict { kind, name })?;
+        }
+        for Entry { name, arity, root } in snapshot.action_roots {
+            ids.set::<ActionIdx>(name.clone(), Arc::new(root), arity)
+                .map_err(|kind| SnapshotError::Conflict { kind, name })?;
+        }
+        Ok(Self::new(ids))
+    }
+}
+
+fn hooks<Idx, Ctx, Ext, Eff>(ids: &IdSpace<Ctx, Ext, Eff>) -> Vec<Hook>
+where
+    Idx: IdSpaceIndex<Ctx, Ext, Eff>,
+{
+    Idx::id_map(ids).entries()
+        .map(|(name, _, arity)| Hook { name: name.clone(), arity: *arity })
+        .collect()
+}
+
+fn entries<Idx, Ctx, Ext, Eff, T>(ids: &IdSpace<Ctx, Ext, Eff>) -> Vec<Entry<T>>
+where
+    Idx: IdSpaceIndex<Ctx, Ext, Eff, Node = Arc<T>>,
+    T: Clone,
+{
+    Idx::id_map(ids).entries()
+        .map(|(name, root, arity)| Entry {
+            name: name.clone(),
+            arity: *arity,
+            root: T::clone(root),
+        })
+        .collect()
+}
+
+fn check_hooks<Idx, Ctx, Ext, Eff>(
+    ids: &IdSpace<Ctx, Ext, Eff>,
+    expected: &[Hook],
+) -> Result<(), SnapshotError>
+where
+    Idx: IdSpaceIndex<Ctx, Ext, Eff>,
+{
+    let mut registered = hooks::<Idx, _, _, _>(ids).into_iter();
+    for hook in expected {
+        if registered.next().as_ref() != Some(hook) {
+            let Hook { name, arity } = hook.clone();
+            return Err(SnapshotError::MissingHost { kind: Idx::KIND, name, arity });
+        }
+    }
+    if let Some(Hook { name, .. }) = registered.next() {
+        return Err(SnapshotError::UnexpectedHost { kind: Idx::KIND, name });
+    }
+    Ok(())
+}
@@ -1,3 +1,4 @@
+use std::cmp::Ordering;
 use std::sync::Arc;
 
 use ordered_float::OrderedFloat;
@@ -5,10 +6,17 @@ use smol_str::SmolStr;
 use serde::{Deserialize, Serialize};
 
 use crate::gen::{fn_enum_is_variant, fn_enum_variant_access, fn_enum_variant_try_into};
+use crate::str::is_symbol;
+use crate::tree::ArityError;
+use crate::tree::script::DEFAULT_MAX_NESTING;
 
 
 pub type Values<Ext> = Arc<[Value<Ext>]>;
 
+const MAX_FLATTEN_DEPTH: usize = 64;
+
+const NAMESPACE_SEPARATOR: char = '/';
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
 pub struct ExtValue<T>(pub T);
 
@@ -38,8 +46,66 @@ impl<Ext> Value<Ext> {
         Self::Symbol(SmolStr::new_inline(s))
     }
 
+    pub fn variant_name(&self) -> &'static str {
+        match self {
+            Self::Symbol(_) => "symbol",
+            Self::Int(_) => "int",
+            Self::Float(_) => "float",
+            Self::List(_) => "list",
+            Self::Ext(_) => "external value",
+        }
+    }
+
     pub fn is_str(&self, s: &str) -> bool {
-        self.symbol().map_or(false, |sym| sym == s)
+        self.as_symbol_str() == Some(s)
+    }
+
+    pub fn as_symbol_str(&self) -> Option<&str> {
+        self.symbol().map(SmolStr::as_str)
+    }
+
+    /// Parses a symbol, number, or bracketed list using the script value syntax. Variables,
+    /// global calls, and external values have no literal form and are rejected.
+    pub fn parse(input: &str) -> Result<Self, ValueParseError> {
+        let mut tokens = value_tokens(input).into_iter().peekable();
+        let value = parse_value(&mut tokens, input.len(), 0)?;
+        match tokens.next() {
+            Some((offset, _)) => Err(ValueParseError::Trailing { offset }),
+            None => Ok(value),
+        }
+    }
+
+    pub fn qualified(namespace: &str, name: &str) -> Self {
+        Self::Symbol(format!("{namespace}{NAMESPACE_SEPARATOR}{name}").into())
+    }
+
+    /// Only symbols and integers can be concatenated.
+    pub fn symbol_concat(parts: &[Self]) -> Option<Self> {
+        let mut symbol = String::new();
+        for part in parts {
+            match part {
+                Self::Symbol(value) => symbol.push_str(value),
+                Self::Int(value) => symbol.push_str(&value.to_string()),
+                _ => return None,
+            }
+        }
+        Some(Self::Symbol(symbol.into()))
+    }
+
+    pub fn symbol_namespace(&self) -> Option<(&str, &str)> {
+        self.as_symbol_str()?.split_once(NAMESPACE_SEPARATOR)
+    }
+
+    pub fn tagged<T>(tag: &str, fields: impl IntoIterator<Item = T>) -> Self
+    where
+        T: Into<Self>,
+    {
+        std::iter::once(Self::from(tag)).chain(fields.into_iter().map(Into::into)).collect()
+    }
+
+    pub fn tag_fields(&self, tag: &str) -> Option<&[Self]> {
+        let (first, fields) = self.list()?.split_first()?;
+        first.is_str(tag).then_some(fields)
     }
 
     pub fn matches_prefix(&self, prefix: &Self) -> bool
@@ -65,6 +131,152 @@ impl<Ext> Value<Ext> {
         self == other || self.list().map_or(false, |items| items.iter().any(|it| it.contains(other)))
     }
 
+    pub fn list_flatten(&self) -> Option<Self>
+    where
+        Ext: Clone,
+    {
+        fn flatten_into<Ext: Clone>(items: &[Value<Ext>], depth: usize, flat: &mut Vec<Value<Ext>>) {
+            for item in items {
+                match item.list() {
+                    Some(nested) if depth < MAX_FLATTEN_DEPTH => {
+                        flatten_into(nested, depth + 1, flat);
+                    },
+                    _ => flat.push(item.clone()),
+                }
+            }
+        }
+        let mut flat = Vec::new();
+        flatten_into(self.list()?, 0, &mut flat);
+        Some(Self::List(flat.into()))
+    }
+
+    pub fn list_path(&self, path: &Self) -> Option<&Self> {
+        path.list()?.iter().try_fold(self, |current, index| {
+            let index = usize::try_from(index.int()?).ok()?;
+            current.list()?.get(index)
+        })
+    }
+
+    /// Values of different variants are ordered by variant first, so integers always sort
+    /// before floats.
+    pub fn list_sort(&self) -> Option<Self>
+    where
+        Ext: Ord + Clone,
+    {
+        let mut items = self.list()?.to_vec();
+        items.sort();
+        Some(Self::List(items.into()))
+    }
+
+    /// Keeps the first occurrence of each item.
+    pub fn list_unique(&self) -> Option<Self>
+    where
+        Ext: PartialEq + Clone,
+    {
+        let mut unique: Vec<Self> = Vec::new();
+        for item in self.list()?.iter() {
+            if !unique.contains(item) {
+                unique.push(item.clone());
+            }
+        }
+        Some(Self::List(unique.into()))
+    }
+
+    pub fn vec2(x: f32, y: f32) -> Self {
+        Self::List([x.into(), y.into()].into())
+    }
+
+    pub fn vec3(x: f32, y: f32, z: f32) -> Self {
+        Self::List([x.into(), y.into(), z.into()].into())
+    }
+
+    pub fn as_vec2(&self) -> Option<(f32, f32)> {
+        let [x, y] = self.vec_components()?;
+        Some((x, y))
+    }
+
+    pub fn as_vec3(&self) -> Option<(f32, f32, f32)> {
+        let [x, y, z] = self.vec_components()?;
+        Some((x, y, z))
+    }
+
+    pub fn vec_add(&self, other: &Self) -> Option<Self> {
+        if let (Some((ax, ay)), Some((bx, by))) = (self.as_vec2(), other.as_vec2()) {
+            Some(Self::vec2(ax + bx, ay + by))
+        } else {
+            let ((ax, ay, az), (bx, by, bz)) = (self.as_vec3()?, other.as_vec3()?);
+            Some(Self::vec3(ax + bx, ay + by, az + bz))
+        }
+    }
+
+    pub fn vec_dist(&self, other: &Self) -> Option<f32> {
+        if let (Some((ax, ay)), Some((bx, by))) = (self.as_vec2(), other.as_vec2()) {
+            Some((ax - bx).hypot(ay - by))
+        } else {
+            let ((ax, ay, az), (bx, by, bz)) = (self.as_vec3()?, other.as_vec3()?);
+            Some(((ax - bx).powi(2) + (ay - by).powi(2) + (az - bz).powi(2)).sqrt())
+        }
+    }
+
+    // integer components are accepted, and vectors only combine with the same dimension
+    fn vec_components<const N: usize>(&self) -> Option<[f32; N]> {
+        let items: &[Self; N] = self.list()?.as_ref().try_into().ok()?;
+        let mut components = [0.0; N];
+        for (component, item) in components.iter_mut().zip(items) {
+            *component = match item {
+                Self::Float(value) => value.0,
+                Self::Int(value) => *value as f32,
+                _ => return None,
+            };
+        }
+        Some(components)
+    }
+
+    /// Integers and floats are compared numerically with each other, mixed pairs as `f64` and
+    /// NaN ordered like `OrderedFloat` does. Other values are only ordered against structurally
+    /// equal values, so the result is either equal or `None`.
+    pub fn compare(&self, other: &Self) -> Option<Ordering>
+    where
+        Ext: PartialEq,
+    {
+        let wide = |value: f32| OrderedFloat(f64::from(value));
+        match (self, other) {
+            (Self::Int(a), Self::Int(b)) => Some(a.cmp(b)),
+            (Self::Int(a), Self::Float(b)) => Some(OrderedFloat(f64::from(*a)).cmp(&wide(b.0))),
+            (Self::Float(a), Self::Int(b)) => Some(wide(a.0).cmp(&OrderedFloat(f64::from(*b)))),
+            (Self::Float(a), Self::Float(b)) => Some(a.cmp(b)),
+            _ => (self == other).then_some(Ordering::Equal),
+        }
+    }
+
+    pub fn checked_add(&self, other: &Self) -> Option<Self> {
+        self.int_op(other, i32::checked_add)
+    }
+
+    pub fn checked_sub(&self, other: &Self) -> Option<Self> {
+        self.int_op(other, i32::checked_sub)
+    }
+
+    pub fn checked_mul(&self, other: &Self) -> Option<Self> {
+        self.int_op(other, i32::checked_mul)
+    }
+
+    pub fn saturating_add(&self, other: &Self) -> Option<Self> {
+        self.int_op(other, |a, b| Some(a.saturating_add(b)))
+    }
+
+    pub fn saturating_sub(&self, other: &Self) -> Option<Self> {
+        self.int_op(other, |a, b| Some(a.saturating_sub(b)))
+    }
+
+    pub fn saturating_mul(&self, other: &Self) -> Option<Self> {
+        self.int_op(other, |a, b| Some(a.saturating_mul(b)))
+    }
+
+    fn int_op(&self, other: &Self, op: fn(i32, i32) -> Option<i32>) -> Option<Self> {
+        op(self.int()?, other.int()?).map(Self::Int)
+    }
+
     fn_enum_is_variant!(pub is_symbol, Symbol);
     fn_enum_is_variant!(pub is_int, Int);
     fn_enum_is_variant!(pub is_float, Float);
@@ -84,6 +296,51 @@ impl<Ext> Value<Ext> {
     fn_enum_variant_try_into!(pub try_into_ext -> Ext, Self::Ext(ext) => ext);
 }
 
+/// Objects become lists of `[key value]` pairs, and strings as well as booleans become
+/// symbols. Numbers are integers when they fit, and floats otherwise. `null` is an empty list.
+/// Strings and keys that aren't valid symbols are rejected.
+///
+/// Converting back turns symbols into strings, and external values into `null`.
+#[cfg(feature = "json")]
+impl<Ext> Value<Ext> {
+    pub fn from_json(json: &serde_json::Value) -> Result<Self, JsonError> {
+        use serde_json::Value as Json;
+
+        let symbol = |value: &str| if is_symbol(value) {
+            Ok(Self::Symbol(value.into()))
+        } else {
+            Err(JsonError::InvalidSymbol { symbol: value.into() })
+        };
+        Ok(match json {
+            Json::Null => Self::List(Arc::new([])),
+            Json::Bool(value) => Self::Symbol(if *value { "true" } else { "false" }.into()),
+            Json::Number(number) => match number.as_i64().and_then(|value| value.try_into().ok()) {
+                Some(value) => Self::Int(value),
+                None => Self::Float(OrderedFloat(number.as_f64().unwrap_or(f64::NAN) as f32)),
+            },
+            Json::String(value) => symbol(value)?,
+            Json::Array(items) => {
+                Self::List(items.iter().map(Self::from_json).collect::<Result<_, _>>()?)
+            },
+            Json::Object(fields) => Self::List(fields.iter()
+                .map(|(key, value)| Ok(Self::from([symbol(key)?, Self::from_json(value)?])))
+                .collect::<Result<_, _>>()?),
+        })
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        use serde_json::Value as Json;
+
+        match self {
+            Self::Symbol(value) => Json::String(value.to_string()),
+            Self::Int(value) => Json::from(*value),
+            Self::Float(value) => Json::from(value.0),
+            Self::List(items) => Json::Array(items.iter().map(Self::to_json).collect()),
+            Self::Ext(_) => Json::Null,
+        }
+    }
+}
+
 impl<Ext, T> FromIterator<T> for Value<Ext>
 where
     T: Into<Self>,
@@ -116,6 +373,11 @@ impl_value_from!(&str, |value| Self::Symbol(value.into()));
 impl_value_from!(i32, Self::Int);
 impl_value_from!(f32, |value| Self::Float(OrderedFloat(value)));
 impl_value_from!(OrderedFloat<f32>, |value| Self::Float(value));
+impl_value_from!(char, |value| Self::Symbol(std::iter::once(value).collect()));
+
+// unsigned integers saturate at `i32::MAX` when they don't fit
+impl_value_from!(u32, |value| Self::Int(value.try_into().unwrap_or(i32::MAX)));
+impl_value_from!(u64, |value| Self::Int(value.try_into().unwrap_or(i32::MAX)));
 
 impl<Ext> From<ExtValue<Ext>> for Value<Ext> {
     fn from(value: ExtValue<Ext>) -> Self {
@@ -263,17 +525,150 @@ macro_rules! impl_tuple_into_values {
 
 impl_tuple_into_values!(T15 T14 T13 T12 T11 T10 T9 T8 T7 T6 T5 T4 T3 T2 T1 T0);
 
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ConversionError {
+    #[error("Wrong number of values: {_0}")]
+    Arity(ArityError),
+    #[error("Expected `{expected}` at index {index}, given {given}")]
+    Kind { index: usize, expected: &'static str, given: &'static str },
+}
+
+#[cfg(feature = "json")]
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum JsonError {
+    #[error("`{symbol}` is not a valid symbol")]
+    InvalidSymbol { symbol: SmolStr },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ValueParseError {
+    #[error("Unexpected end of input at offset {offset}")]
+    UnexpectedEnd { offset: usize },
+    #[error("Unclosed list starting at offset {offset}")]
+    Unclosed { offset: usize },
+    #[error("Unexpected `]` at offset {offset}")]
+    UnexpectedClose { offset: usize },
+    #[error("Unrecognized value `{token}` at offset {offset}")]
+    Unrecognized { token: SmolStr, offset: usize },
+    #[error("Unexpected trailing input at offset {offset}")]
+    Trailing { offset: usize },
+    #[error("List at offset {offset} is nested deeper than {limit} levels")]
+    NestingTooDeep { offset: usize, limit: usize },
+}
+
+type ValueTokens<'a> = std::iter::Peekable<std::vec::IntoIter<(usize, &'a str)>>;
+
+fn value_tokens(input: &str) -> Vec<(usize, &str)> {
+    let mut tokens = Vec::new();
+    let mut word_start = None;
+    for (offset, c) in input.char_indices() {
+        if c.is_whitespace() || c == '[' || c == ']' {
+            if let Some(start) = word_start.take() {
+                tokens.push((start, &input[start..offset]));
+            }
+            if !c.is_whitespace() {
+                tokens.push((offset, &input[offset..(offset + 1)]));
+            }
+        } else if word_start.is_none() {
+            word_start = Some(offset);
+        }
+    }
+    if let Some(start) = word_start {
+        tokens.push((start, &input[start..]));
+    }
+    tokens
+}
+
+fn parse_value<Ext>(
+    tokens: &mut ValueTokens<'_>,
+    end: usize,
+    depth: usize,
+) -> Result<Value<Ext>, ValueParseError> {
+    match tokens.next() {
+        None => Err(ValueParseError::UnexpectedEnd { offset: end }),
+        Some((offset, "[")) if depth >= DEFAULT_MAX_NESTING => {
+            Err(ValueParseError::NestingTooDeep { offset, limit: DEFAULT_MAX_NESTING })
+        },
+        Some((offset, "[")) => {
+            let mut items = Vec::new();
+            loop {
+                match tokens.peek() {
+                    None => return Err(ValueParseError::Unclosed { offset }),
+                    Some((_, "]")) => {
+                        tokens.next();
+                        return Ok(Value::List(items.into()));
+                    },
+                    Some(_) => items.push(parse_value(tokens, end, depth + 1)?),
+                }
+            }
+        },
+        Some((offset, "]")) => Err(ValueParseError::UnexpectedClose { offset }),
+        Some((offset, token)) => {
+            if let Ok(value) = token.parse::<i32>() {
+                Ok(Value::Int(value))
+            } else if let Some(value) = parse_float(token) {
+                Ok(Value::Float(OrderedFloat(value)))
+            } else if is_symbol(token) {
+                Ok(Value::Symbol(token.into()))
+            } else {
+                Err(ValueParseError::Unrecognized { token: token.into(), offset })
+            }
+        },
+    }
+}
+
+fn parse_float(token: &str) -> Option<f32> {
+    let is_numeric = token.starts_with(|c: char| c.is_ascii_digit() || c == '-');
+    token.parse::<f32>().ok().filter(|value| is_numeric && value.is_finite())
+}
+
 pub trait TryFromValues<Ext>: Sized {
     const ARITY: usize;
 
     fn try_from_values<I>(values: I) -> Option<Self>
     where
         I: IntoIterator<Item = Value<Ext>>;
+
+    fn try_from_values_detailed<I>(values: I) -> Result<Self, ConversionError>
+    where
+        I: IntoIterator<Item = Value<Ext>>;
+}
+
+/// Names the kind of value a conversion target expects, for use in [`ConversionError::Kind`].
+pub trait ValueKind {
+    const KIND_NAME: &'static str;
+}
+
+macro_rules! impl_value_kind {
+    ($name:literal, $([$($generic:ident),*] $target:ty),*) => {
+        $(
+            impl<$($generic),*> ValueKind for $target {
+                const KIND_NAME: &'static str = $name;
+            }
+        )*
+    };
+}
+
+impl_value_kind!("symbol", [] SmolStr);
+impl_value_kind!("int", [] i32);
+impl_value_kind!("float", [] f32, [] OrderedFloat<f32>);
+impl_value_kind!("list", [T] Vec<T>);
+impl_value_kind!("external value", [Ext] ExtValue<Ext>);
+impl_value_kind!("value", [Ext] Value<Ext>);
+
+fn try_convert_value<Ext, T>(index: usize, value: Value<Ext>) -> Result<T, ConversionError>
+where
+    Value<Ext>: TryInto<T>,
+    T: ValueKind,
+{
+    let given = value.variant_name();
+    value.try_into().map_err(|_| ConversionError::Kind { index, expected: T::KIND_NAME, given })
 }
 
 impl<Ext, T, const N: usize> TryFromValues<Ext> for [T; N]
 where
     Value<Ext>: TryInto<T>,
+    T: ValueKind,
     //T: TryFrom<Value<Ext>>,
 {
     const ARITY: usize = N;
@@ -291,6 +686,24 @@ where
         }
         Some(values.map(|value| value.unwrap()))
     }
+
+    fn try_from_values_detailed<I>(values: I) -> Result<Self, ConversionError>
+    where
+        I: IntoIterator<Item = Value<Ext>>,
+    {
+        let values: Vec<_> = values.into_iter().take(N).collect();
+        if values.len() < N {
+            return Err(ConversionError::Arity(ArityError { expected: N, given: values.len() }));
+        }
+        let converted = values.into_iter()
+            .enumerate()
+            .map(|(index, value)| try_convert_value(index, value))
+            .collect::<Result<Vec<T>, _>>()?;
+        match converted.try_into() {
+            Ok(converted) => Ok(converted),
+            Err(_) => unreachable!("all values were converted"),
+        }
+    }
 }
 
 macro_rules! impl_tuple_try_from_values_next {
@@ -313,6 +726,7 @@ macro_rules! impl_tuple_try_from_values {
         where
             $(
                 Value<Ext>: TryInto<$param>,
+                $param: ValueKind,
             )*
         {
             const ARITY: usize = const_arity!($($param)*);
@@ -332,6 +746,29 @@ macro_rules! impl_tuple_try_from_values {
                     None
                 }
             }
+
+            fn try_from_values_detailed<I>(values: I) -> Result<Self, ConversionError>
+            where
+                I: IntoIterator<Item = Value<Ext>>,
+            {
+                let arity = <Self as TryFromValues<Ext>>::ARITY;
+                let values: Vec<_> = values.into_iter().collect();
+                if values.len() != arity {
+                    return Err(ConversionError::Arity(ArityError {
+                        expected: arity,
+                        given: values.len(),
+                    }));
+                }
+                #[allow(unused)]
+                let mut iter = values.into_iter().enumerate();
+                Ok(($(
+                    {
+                        let _: $param;
+                        let (index, value) = iter.next().unwrap();
+                        try_convert_value(index, value)?
+                    },
+                )*))
+            }
         }
         impl_tuple_try_from_values_next!($($param)*);
     };
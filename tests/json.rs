@@ -0,0 +1,58 @@
+#![cfg(feature = "json")]
+
+use reagenz::{JsonError, Value};
+use serde_json::json;
+
+
+type TestValue = Value<()>;
+
+fn list<const N: usize>(items: [TestValue; N]) -> TestValue {
+    items.into()
+}
+
+#[test]
+fn from_json() {
+    assert_eq!(TestValue::from_json(&json!(23)), Ok(Value::Int(23)));
+    assert_eq!(TestValue::from_json(&json!(1.5)), Ok(Value::from(1.5)));
+    assert_eq!(TestValue::from_json(&json!(i64::MAX)), Ok(Value::from(i64::MAX as f32)));
+    assert_eq!(TestValue::from_json(&json!("foo")), Ok(Value::from("foo")));
+    assert_eq!(TestValue::from_json(&json!(true)), Ok(Value::from("true")));
+    assert_eq!(TestValue::from_json(&json!(null)), Ok(list([])));
+    assert_eq!(
+        TestValue::from_json(&json!({ "name": "orc", "stats": { "hp": 10 }, "tags": [1, "a"] })),
+        Ok(list([
+            list(["name".into(), "orc".into()]),
+            list(["stats".into(), list([list(["hp".into(), 10.into()])])]),
+            list(["tags".into(), list([1.into(), "a".into()])]),
+        ]))
+    );
+}
+
+#[test]
+fn invalid_json_symbols() {
+    let invalid = |symbol: &str| Err(JsonError::InvalidSymbol { symbol: symbol.into() });
+    assert_eq!(TestValue::from_json(&json!("")), invalid(""));
+    assert_eq!(TestValue::from_json(&json!("hello world")), invalid("hello world"));
+    assert_eq!(TestValue::from_json(&json!([1, ["ok", "$var"]])), invalid("$var"));
+    assert_eq!(TestValue::from_json(&json!({ "bad key": 1 })), invalid("bad key"));
+}
+
+#[test]
+fn to_json() {
+    assert_eq!(TestValue::from(23).to_json(), json!(23));
+    assert_eq!(TestValue::from(1.5).to_json(), json!(1.5));
+    assert_eq!(Value::Ext(()).to_json(), json!(null));
+    assert_eq!(list([1.into(), list(["a".into(), 2.5.into()])]).to_json(), json!([1, ["a", 2.5]]));
+}
+
+#[test]
+fn round_trips() {
+    let round_trip = |json| TestValue::from_json(&json).unwrap().to_json();
+    let nested = json!([1, [2.5, ["deep", []]], "end"]);
+    assert_eq!(round_trip(nested.clone()), nested);
+    assert_eq!(round_trip(json!({ "a": [1, 2], "b": { "c": "d" } })), json!([
+        ["a", [1, 2]],
+        ["b", [["c", "d"]]],
+    ]));
+    assert_eq!(round_trip(json!(false)), json!("false"));
+}
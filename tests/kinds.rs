@@ -0,0 +1,37 @@
+use reagenz::{Kind, Kinds};
+
+
+#[test]
+fn membership() {
+    let kinds = Kinds::from_iter([Kind::Node, Kind::Action, Kind::Node]);
+    assert_eq!(kinds.len(), 2);
+    assert!(kinds.contains(Kind::Action));
+    assert!(kinds.contains(Kind::Node));
+    assert!(!kinds.contains(Kind::Cond));
+    assert_eq!(kinds, Kinds::from([Kind::Action, Kind::Node]));
+
+    let empty = Kinds::from_iter([]);
+    assert!(empty.is_empty());
+    assert!(!empty.contains(Kind::Action));
+
+    let all = Kinds::all();
+    assert_eq!(all.len(), 8);
+    assert!(all.into_iter().all(|kind| all.contains(kind)));
+    assert_eq!(Kinds::from_iter(all), all);
+}
+
+#[test]
+fn display() {
+    assert_eq!(Kinds::from_iter([]).display_connected("or").to_string(), "none");
+    assert_eq!(Kinds::from_iter([Kind::Cond]).display_connected("or").to_string(), "a condition");
+    assert_eq!(
+        Kinds::from_iter([Kind::Action, Kind::Node]).display_connected("or").to_string(),
+        "an action or a node",
+    );
+    assert_eq!(
+        Kinds::from_iter([Kind::Action, Kind::Node, Kind::Cond])
+            .display_connected("and")
+            .to_string(),
+        "a condition, an action and a node",
+    );
+}
@@ -1,4 +1,13 @@
-use reagenz::{BehaviorTreeBuilder, Outcome, effect_fn, cond_fn, query_fn, custom_fn};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use reagenz::{
+    BehaviorTreeBuilder, Outcome, Value, ExtValue, IdError, ArityError, KindError, Kind, CacheStats,
+    PersistentCache, AliasError, NamedArgumentsError, FailedCondition, HostModule,
+    CompileError, CompilePhase, EvalError, EvaluateError, ScriptError, ScriptSource,
+    DEFAULT_MAX_NESTING, DiagnosticSeverity, effect_fn, effects_fn, cond_fn, query_fn, custom_fn,
+};
+use smol_str::SmolStr;
 use src_ctx::normalize;
 use treelang::{Indent};
 use assert_matches::assert_matches;
@@ -6,6 +15,36 @@ use assert_matches::assert_matches;
 
 const INDENT: Indent = Indent::spaces(2);
 
+#[test]
+fn global_calls() {
+    let mut tree = BehaviorTreeBuilder::<i32, (), i32>::default();
+    tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+    tree.register_global_call("$offset", (1, |ctx, args| match args {
+        [Value::Int(value)] => (ctx + value).into(),
+        _ => Value::Int(0),
+    }));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: test $value
+        |  effects:
+        |    emit-value ($offset $value)
+        |    emit-value ($offset ($offset 1))
+    ")).unwrap();
+    assert_matches!(tree.evaluate(&10, "test", [3]), Ok(Outcome::Action(action)) => {
+        assert_matches!(action.effects(), [13, 21]);
+    });
+
+    for source in ["action: test\n  effects:\n    emit-value ($offset)", "node: test $offset"] {
+        let mut tree = BehaviorTreeBuilder::<i32, (), i32>::default();
+        tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+        tree.register_global_call("$offset", (1, |_, _| Value::Int(0)));
+        assert_matches!(
+            tree.compile_str(INDENT, "test", source).err(),
+            Some(CompileError::Script(_)),
+            "source: {source:?}"
+        );
+    }
+}
+
 #[test]
 fn globals() {
     let mut tree = BehaviorTreeBuilder::<i32, (), i32>::default();
@@ -21,6 +60,45 @@ fn globals() {
     });
 }
 
+#[test]
+fn global_overrides() {
+    let mut tree = BehaviorTreeBuilder::<i32, (), i32>::default();
+    tree.register_condition("eq", cond_fn!(_, a: i32, b: i32 => a == b));
+    tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+    tree.register_global("$goal", |ctx| (*ctx).into());
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |node: test $value
+        |  eq $goal $value
+        |node: test-match $value
+        |  match $goal: $value
+        |action: test-action
+        |  effects:
+        |    emit-value $goal
+    ")).unwrap();
+    let overrides = HashMap::from([("$goal".into(), 42.into())]);
+    assert_eq!(tree.evaluate(&23, "test", [42]), Ok(Outcome::Failure));
+    assert_eq!(tree.evaluate_with_globals(&23, "test", [42], &overrides), Ok(Outcome::Success));
+    assert_eq!(tree.evaluate(&23, "test-match", [42]), Ok(Outcome::Failure));
+    assert_eq!(
+        tree.evaluate_with_globals(&23, "test-match", [42], &overrides),
+        Ok(Outcome::Success)
+    );
+    assert_matches!(
+        tree.evaluate_with_globals(&23, "test-action", (), &overrides),
+        Ok(Outcome::Action(action)) => {
+            assert_matches!(action.effects(), [42]);
+        }
+    );
+    assert_eq!(
+        tree.evaluate_with_globals(&23, "test", [42], &HashMap::from([("$unknown".into(), 42.into())])),
+        Err(EvalError::Override { name: "$unknown".into(), source: IdError::Unknown })
+    );
+    assert_eq!(
+        tree.evaluate_with_globals(&23, "unknown", [42], &overrides),
+        Err(EvalError::Evaluate(EvaluateError { node: "unknown".into(), source: IdError::Unknown }))
+    );
+}
+
 #[test]
 fn action_inheritance() {
     let mut tree = BehaviorTreeBuilder::<(), (), i32>::default();
@@ -100,6 +178,33 @@ fn effects() {
     assert_matches!(tree.evaluate(&23, "test", [23]), Ok(Outcome::Failure));
 }
 
+#[test]
+fn effect_dry_run() {
+    let mut tree = BehaviorTreeBuilder::<i32, (), i32>::default();
+    tree.register_effect("spend", effect_fn!(ctx, value: i32 => {
+        (*ctx >= value).then_some(-value)
+    }));
+    tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: gain $value
+        |  effects:
+        |    emit-value $value
+        |action: buy $value
+        |  inherit:
+        |    gain 1
+        |  effects:
+        |    spend $value
+    ")).unwrap();
+    assert_matches!(tree.evaluate(&10, "buy", [5]), Ok(Outcome::Action(action)) => {
+        assert_matches!(action.effects(), [-5, 1]);
+    });
+    assert_matches!(tree.evaluate_dry_run(&10, "buy", [5]), Ok(Outcome::Action(action)) => {
+        assert_matches!(action.effects(), []);
+        assert_eq!(tree.action_name(&action), "buy");
+    });
+    assert_eq!(tree.evaluate_dry_run(&10, "buy", [20]), Ok(Outcome::Failure));
+}
+
 #[test]
 fn conditions() {
     let mut tree = BehaviorTreeBuilder::<(), (), ()>::default();
@@ -121,6 +226,492 @@ fn custom_nodes() {
     assert_eq!(tree.evaluate(&(), "test", [42]), Ok(Outcome::Failure));
 }
 
+#[test]
+fn custom_node_contract() {
+    let mut tree = BehaviorTreeBuilder::<i32, (), ()>::default();
+    tree.register_condition("eq", cond_fn!(ctx, value: i32 => *ctx == value));
+    tree.register_custom("delegate", custom_fn!(ctx, tree, _, _, name: SmolStr, value: i32 => {
+        tree.evaluate(ctx, &name, [value]).unwrap_or(Outcome::Failure)
+    }));
+    tree.register_custom("active", custom_fn!(_, _, is_active, _ => is_active.into()));
+    tree.register_custom("seed-a", custom_fn!(_, _, _, seed, value: i32 => {
+        (seed == value as u64).into()
+    }));
+    tree.register_custom("seed-b", custom_fn!(_, _, _, seed, value: i32 => {
+        (seed == value as u64).into()
+    }));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |node: test-delegate $value
+        |  delegate eq $value
+    ")).unwrap();
+    assert_eq!(tree.evaluate(&23, "test-delegate", [23]), Ok(Outcome::Success));
+    assert_eq!(tree.evaluate(&23, "test-delegate", [42]), Ok(Outcome::Failure));
+    assert_eq!(tree.evaluate(&23, "active", ()), Ok(Outcome::Success));
+    assert_eq!(tree.check(&23, "active", ()), Ok(Outcome::Failure));
+
+    let seed = |name| (0..4).find(|&seed| tree.evaluate(&0, name, [seed]) == Ok(Outcome::Success));
+    let (seed_a, seed_b) = (seed("seed-a"), seed("seed-b"));
+    assert!(seed_a.is_some() && seed_b.is_some());
+    assert_ne!(seed_a, seed_b);
+    assert_eq!(seed("seed-a"), seed_a);
+}
+
+#[test]
+fn select_any() {
+    let mut tree = BehaviorTreeBuilder::<u64, (), i32>::default();
+    tree.register_seed("seed", |ctx| *ctx);
+    tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+    tree.register_condition("fail", cond_fn!(_ => false));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: emit $value
+        |  effects:
+        |    emit-value $value
+        |node: test
+        |  select-any: seed
+        |    emit 1
+        |    fail
+        |    emit 2
+        |    emit 3
+        |node: test-none
+        |  select-any: seed
+        |    fail
+        |    fail
+    ")).unwrap();
+    let mut counts = [0; 3];
+    for seed in 0..300 {
+        let value = assert_matches!(
+            tree.evaluate(&seed, "test", ()),
+            Ok(Outcome::Action(action)) => action.effects()[0]
+        );
+        assert_eq!(
+            tree.evaluate(&seed, "test", ()).ok().and_then(|outcome| outcome.effects()?.first().copied()),
+            Some(value),
+        );
+        counts[value as usize - 1] += 1;
+        // actions aren't viable when evaluated inactively
+        assert_eq!(tree.check(&seed, "test", ()), Ok(Outcome::Failure));
+        assert_eq!(tree.evaluate(&seed, "test-none", ()), Ok(Outcome::Failure));
+    }
+    assert!(counts.iter().all(|count| *count > 50), "uneven distribution: {counts:?}");
+}
+
+#[test]
+fn applicable_actions() {
+    let mut tree = BehaviorTreeBuilder::<i32, (), i32>::default();
+    tree.register_effect("spend", effect_fn!(ctx, value: i32 => (*ctx >= value).then_some(-value)));
+    tree.register_condition("below", cond_fn!(_, a: i32, b: i32 => a < b));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: buy $value
+        |  conditions:
+        |    below $value 100
+        |  effects:
+        |    spend $value
+        |action: plan $value
+        |  conditions:
+        |    applicable: buy $value
+        |  effects:
+        |    spend 0
+        |node: test $value
+        |  applicable: buy $value
+    ")).unwrap();
+    assert_eq!(tree.evaluate(&50, "test", [20]), Ok(Outcome::Success));
+    assert_eq!(tree.check(&50, "test", [20]), Ok(Outcome::Success));
+    assert_eq!(tree.evaluate(&50, "test", [70]), Ok(Outcome::Failure));
+    assert_eq!(tree.evaluate(&500, "test", [200]), Ok(Outcome::Failure));
+    assert_matches!(tree.evaluate(&50, "plan", [20]), Ok(Outcome::Action(action)) => {
+        assert_eq!(tree.action_name(&action), "plan");
+        assert_matches!(action.effects(), [0]);
+    });
+    assert_eq!(tree.evaluate(&50, "plan", [70]), Ok(Outcome::Failure));
+
+    for source in ["node: test\n  applicable: below 1 2", "node: test\n  applicable: buy"] {
+        let mut tree = BehaviorTreeBuilder::<(), (), ()>::default();
+        tree.register_condition("below", cond_fn!(_, a: i32, b: i32 => a < b));
+        let source = format!("action: buy $value\n{source}");
+        assert_matches!(
+            tree.compile_str(INDENT, "test", &source).err(),
+            Some(CompileError::Script(_)),
+            "source: {source:?}"
+        );
+    }
+}
+
+#[test]
+fn compile_phases() {
+    for (source, phase) in [
+        ("node: 23\n  below 1 2", CompilePhase::Syntax),
+        ("node: test\n  below $value 2", CompilePhase::Resolution),
+        ("node: test\n  below 1 2\nnode: test\n  below 1 2", CompilePhase::Conflict),
+    ] {
+        let mut tree = BehaviorTreeBuilder::<(), (), ()>::default();
+        tree.register_condition("below", cond_fn!(_, a: i32, b: i32 => a < b));
+        let error = tree.compile_str(INDENT, "test", source).err();
+        assert_eq!(error.map(|error| error.phase()), Some(phase), "source: {source:?}");
+    }
+}
+
+#[test]
+fn evaluation_budget() {
+    let mut tree = BehaviorTreeBuilder::<(), (), ()>::default();
+    tree.register_condition("check", cond_fn!(_, value: i32 => value >= 0));
+    tree.register_query("endless", query_fn!(_ => (0..).map(Into::into)));
+    tree.register_query("values", query_fn!(_ => (0..10).map(Into::into)));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |node: test-endless
+        |  for-every $value: endless
+        |    check $value
+        |node: test-values
+        |  for-every $value: values
+        |    check $value
+    ")).unwrap();
+    assert_eq!(tree.evaluate_with_budget(&(), "test-endless", (), 1000), Err(EvalError::OutOfFuel));
+    assert_eq!(tree.evaluate_with_budget(&(), "test-values", (), 1000), Ok(Outcome::Success));
+    assert_eq!(tree.evaluate_with_budget(&(), "test-values", (), 5), Err(EvalError::OutOfFuel));
+    assert_eq!(
+        tree.evaluate_with_budget(&(), "unknown", (), 1000),
+        Err(EvalError::Evaluate(EvaluateError { node: "unknown".into(), source: IdError::Unknown }))
+    );
+    assert_eq!(
+        tree.evaluate_with_budget(&(), "test-values", [1], 1000).unwrap_err().to_string(),
+        "Unable to evaluate `test-values`: Wrong arity: Expected 0, given 1"
+    );
+}
+
+#[test]
+fn lazy_queries() {
+    use std::cell::Cell;
+
+    let mut tree = BehaviorTreeBuilder::<Cell<usize>, (), i32>::default();
+    tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+    tree.register_condition("eq", cond_fn!(_, a: i32, b: i32 => a == b));
+    tree.register_query("values", query_fn!(ctx => (0..10).map(|value| {
+        ctx.set(ctx.get() + 1);
+        Value::from(value)
+    })));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: emit $value
+        |  effects:
+        |    emit-value $value
+        |node: test-any $target
+        |  for-any $value: values
+        |    eq $value $target
+        |node: test-every $target
+        |  for-every $value: values
+        |    eq $value $target
+        |node: test-first
+        |  with-first $value: values
+        |    emit $value
+    ")).unwrap();
+    for (node, target, outcome, advanced) in [
+        ("test-any", 2, Outcome::Success, 3),
+        ("test-any", 23, Outcome::Failure, 10),
+        ("test-every", 0, Outcome::Failure, 2),
+    ] {
+        let counter = Cell::new(0);
+        assert_eq!(tree.evaluate(&counter, node, [target]), Ok(outcome));
+        assert_eq!(counter.get(), advanced, "{node} {target}");
+    }
+    let counter = Cell::new(0);
+    assert_matches!(tree.evaluate(&counter, "test-first", ()), Ok(Outcome::Action(_)));
+    assert_eq!(counter.get(), 1);
+}
+
+#[test]
+fn yielding_queries() {
+    use std::cell::Cell;
+
+    let mut tree = BehaviorTreeBuilder::<Cell<usize>, (), i32>::default();
+    tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+    tree.register_condition("eq", cond_fn!(_, a: i32, b: i32 => a == b));
+    tree.register_query_yield("values", (1, |ctx, args, yield_fn| {
+        let Some(limit) = args[0].int() else {
+            return;
+        };
+        for value in 0..limit {
+            ctx.set(ctx.get() + 1);
+            if yield_fn(value.into()).is_break() {
+                break;
+            }
+        }
+    }));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: emit $value
+        |  effects:
+        |    emit-value $value
+        |node: test-any $target
+        |  for-any $value: values 10
+        |    eq $value $target
+        |node: test-every $target
+        |  for-every $value: values 10
+        |    eq $value $target
+        |node: test-first
+        |  with-first $value: values 10
+        |    emit $value
+        |node: test-last
+        |  with-last $value: values 10
+        |    emit $value
+        |node: test-empty
+        |  with-first $value: values 0
+        |    emit $value
+    ")).unwrap();
+    for (node, target, outcome, advanced) in [
+        ("test-any", 2, Outcome::Success, 3),
+        ("test-any", 23, Outcome::Failure, 10),
+        ("test-every", 0, Outcome::Failure, 2),
+    ] {
+        let counter = Cell::new(0);
+        assert_eq!(tree.evaluate(&counter, node, [target]), Ok(outcome));
+        assert_eq!(counter.get(), advanced, "{node} {target}");
+    }
+    let counter = Cell::new(0);
+    assert_matches!(tree.evaluate(&counter, "test-first", ()), Ok(Outcome::Action(action)) => {
+        assert_matches!(action.effects(), [0]);
+    });
+    assert_eq!(counter.get(), 1);
+    let counter = Cell::new(0);
+    assert_matches!(tree.evaluate(&counter, "test-last", ()), Ok(Outcome::Action(action)) => {
+        assert_matches!(action.effects(), [9]);
+    });
+    assert_eq!(counter.get(), 10);
+    assert_eq!(tree.evaluate(&Cell::new(0), "test-empty", ()), Ok(Outcome::Failure));
+}
+
+#[test]
+fn explained_evaluation() {
+    let mut tree = BehaviorTreeBuilder::<i32, (), ()>::default();
+    tree.register_condition("eq", cond_fn!(ctx, value: i32 => *ctx == value));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |node: test
+        |  select:
+        |    eq 1
+        |    check 2
+        |    eq 3
+        |node: check $value
+        |  eq $value
+    ")).unwrap();
+    let (outcome, explanation) = tree.evaluate_explained(&2, "test", ()).unwrap();
+    assert_eq!(outcome, Outcome::Success);
+    assert_eq!(explanation.ref_name, "test");
+    assert_eq!(explanation.outcome, Outcome::Success);
+    let path: Vec<_> = explanation.children.iter()
+        .map(|child| (child.ref_name.as_str(), child.arguments.to_vec(), child.outcome.clone()))
+        .collect();
+    assert_eq!(path, [
+        ("eq", vec![Value::from(1)], Outcome::Failure),
+        ("check", vec![Value::from(2)], Outcome::Success),
+    ]);
+    assert!(explanation.children[0].children.is_empty());
+    assert_matches!(&explanation.children[1].children[..], [nested] => {
+        assert_eq!(nested.ref_name, "eq");
+        assert_eq!(nested.outcome, Outcome::Success);
+    });
+
+    let (outcome, explanation) = tree.evaluate_explained(&4, "test", ()).unwrap();
+    assert_eq!(outcome, Outcome::Failure);
+    assert_eq!(explanation.outcome, Outcome::Failure);
+    assert_eq!(explanation.children.len(), 3);
+    assert_matches!(
+        tree.evaluate_explained(&4, "unknown", ()),
+        Err(EvaluateError { source: IdError::Unknown, .. })
+    );
+}
+
+#[test]
+fn structural_equality() {
+    let build = |source: &str| {
+        let mut tree = BehaviorTreeBuilder::<(), (), ()>::default();
+        tree.register_seed("seed", |_| 23);
+        tree.register_condition("eq", cond_fn!(_, a: i32, b: i32 => a == b));
+        tree.register_query("values", query_fn!(_ => [1, 2, 3].map(Into::into)));
+        tree.compile_str(INDENT, "test", &normalize(source)).unwrap()
+    };
+    let source = "
+        |node: test $value
+        |  random: seed
+        |    eq $value 1
+        |    for-any [$a $b]: values
+        |      eq $a $value
+        |  cond:
+        |    when:
+        |      eq $value 2
+        |    do:
+        |      eq $value 3
+        |    else:
+        |      eq $value [4 $value]
+    ";
+    let tree = build(source);
+    assert!(tree.structural_eq(&tree.clone()));
+    assert!(tree.structural_eq(&build(source)));
+    assert!(!tree.structural_eq(&build(&source.replace("eq $value 3", "eq $value 4"))));
+    assert!(!tree.structural_eq(&build(&source.replace("for-any", "for-every"))));
+    assert!(!tree.structural_eq(&build(&source.replace("random:", "any-random:"))));
+    let extended = format!("{source}\n        |node: other\n        |  eq 1 1");
+    assert!(!tree.structural_eq(&build(&extended)));
+}
+
+#[test]
+fn indexed_queries() {
+    let mut tree = BehaviorTreeBuilder::<&[i32], (), (i32, i32)>::default();
+    tree.register_effect("emit-pair", effect_fn!(_, index: i32, value: i32 => Some((index, value))));
+    tree.register_condition("below", cond_fn!(_, a: i32, b: i32 => a < b));
+    tree.register_query("values", query_fn!(ctx => ctx.iter().copied().map(Into::into)));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: emit-all
+        |  inherit:
+        |    for-each-indexed [$index $value]: values
+        |      emit-one $index $value
+        |action: emit-one $index $value
+        |  effects:
+        |    emit-pair $index $value
+        |node: test-below $limit
+        |  for-each-indexed [$index $]: values
+        |    below $index $limit
+        |node: test-skip
+        |  for-each-indexed [0 $value]: values
+        |    below $value 10
+    ")).unwrap();
+    assert_matches!(
+        tree.evaluate(&&[7, 5, 9][..], "emit-all", ()),
+        Ok(Outcome::Action(action)) => {
+            assert_matches!(action.effects(), [(0, 7), (1, 5), (2, 9)]);
+        }
+    );
+    assert_eq!(tree.evaluate(&&[7, 5, 9][..], "test-below", [3]), Ok(Outcome::Success));
+    assert_eq!(tree.evaluate(&&[7, 5, 9][..], "test-below", [2]), Ok(Outcome::Failure));
+    assert_eq!(tree.evaluate(&&[7, 50, 90][..], "test-skip", ()), Ok(Outcome::Success));
+    assert_eq!(tree.evaluate(&&[70, 5][..], "test-skip", ()), Ok(Outcome::Failure));
+}
+
+#[test]
+fn action_sequence_queries() {
+    let mut tree = BehaviorTreeBuilder::<&[i32], (), i32>::default();
+    tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+    tree.register_condition("below", cond_fn!(_, a: i32, b: i32 => a < b));
+    tree.register_query("values", query_fn!(ctx => ctx.iter().copied().map(Into::into)));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: emit $value
+        |  conditions:
+        |    below $value 100
+        |  effects:
+        |    emit-value $value
+        |node: test-every
+        |  for-every $value: values
+        |    emit $value
+        |node: test-every-action
+        |  for-every-action $value: values
+        |    emit $value
+        |node: test-mixed
+        |  for-every-action $value: values
+        |    select:
+        |      below $value 10
+        |      emit $value
+    ")).unwrap();
+    let view: &[i32] = &[1, 2, 3];
+    assert_matches!(tree.evaluate(&view, "test-every", ()), Ok(Outcome::Action(action)) => {
+        assert_matches!(action.effects(), [1]);
+    });
+    assert_matches!(tree.evaluate(&view, "test-every-action", ()), Ok(Outcome::Action(action)) => {
+        assert_eq!(tree.action_name(&action), "emit");
+        assert_eq!(action.arguments(), [Value::from(1)]);
+        assert_matches!(action.effects(), [1, 2, 3]);
+    });
+    assert_eq!(tree.evaluate(&&[][..], "test-every-action", ()), Ok(Outcome::Success));
+    assert_eq!(tree.evaluate(&&[1, 200][..], "test-every-action", ()), Ok(Outcome::Failure));
+    assert_matches!(tree.evaluate(&&[1, 20, 2, 30][..], "test-mixed", ()), Ok(Outcome::Action(action)) => {
+        assert_matches!(action.effects(), [20, 30]);
+    });
+    assert_eq!(tree.evaluate(&&[1, 2][..], "test-mixed", ()), Ok(Outcome::Success));
+}
+
+#[test]
+fn each_queries() {
+    let mut tree = BehaviorTreeBuilder::<&[i32], (), (&'static str, i32)>::default();
+    tree.register_effect("emit-attack", effect_fn!(_, value: i32 => Some(("attack", value))));
+    tree.register_effect("emit-taunt", effect_fn!(_, value: i32 => Some(("taunt", value))));
+    tree.register_condition("below", cond_fn!(_, a: i32, b: i32 => a < b));
+    tree.register_query("targets", query_fn!(ctx => ctx.iter().copied().map(Into::into)));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: attack $target
+        |  conditions:
+        |    below $target 10
+        |  effects:
+        |    emit-attack $target
+        |action: taunt $target
+        |  effects:
+        |    emit-taunt $target
+        |action: engage $target $mode
+        |  discovery:
+        |    with-each $target: targets
+        |      engage $target near
+        |      engage $target far
+        |node: test
+        |  with-each $target: targets
+        |    attack $target
+        |    taunt $target
+        |node: test-checked
+        |  with-each $target: targets
+        |    attack $target
+        |    below $target 5
+    ")).unwrap();
+    let view: &[i32] = &[1, 20, 3];
+    assert_matches!(tree.evaluate(&view, "test", ()), Ok(Outcome::Action(action)) => {
+        assert_eq!(tree.action_name(&action), "attack");
+        assert_eq!(action.arguments(), [Value::from(1)]);
+        assert_matches!(action.effects(), [("attack", 1), ("attack", 3)]);
+    });
+    assert_matches!(tree.evaluate(&&[20][..], "test", ()), Ok(Outcome::Action(action)) => {
+        assert_eq!(tree.action_name(&action), "taunt");
+        assert_matches!(action.effects(), [("taunt", 20)]);
+    });
+    assert_eq!(tree.evaluate(&&[][..], "test-checked", ()), Ok(Outcome::Success));
+    assert_eq!(tree.evaluate(&&[20, 30][..], "test-checked", ()), Ok(Outcome::Failure));
+    assert_matches!(tree.evaluate(&&[20, 3][..], "test-checked", ()), Ok(Outcome::Action(action)) => {
+        assert_matches!(action.effects(), [("attack", 3)]);
+    });
+    let mut actions = Vec::new();
+    tree.discover(&view, "engage", &mut actions).unwrap();
+    let arguments: Vec<_> = actions.iter().map(|action| action.arguments().to_vec()).collect();
+    assert_eq!(arguments.len(), 6);
+    assert_eq!(arguments[2], [Value::from(20), Value::from("near")]);
+    assert_eq!(arguments[3], [Value::from(20), Value::from("far")]);
+}
+
+#[test]
+fn list_literal_queries() {
+    let mut tree = BehaviorTreeBuilder::<(), (), i32>::default();
+    tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+    tree.register_condition("below", cond_fn!(_, a: i32, b: i32 => a < b));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: emit $value
+        |  effects:
+        |    emit-value $value
+        |node: test-every
+        |  for-every-action $value: [1 2 3]
+        |    emit $value
+        |node: test-first $limit
+        |  with-first $value: [1 $limit 3]
+        |    below $value 5
+        |    emit $value
+        |node: test-pattern
+        |  for-any [$a $b]: [[1 2] [3 4]]
+        |    below 2 $a
+        |    emit $b
+        |node: test-empty
+        |  for-any $value: []
+        |    emit $value
+    ")).unwrap();
+    assert_matches!(tree.evaluate(&(), "test-every", ()), Ok(Outcome::Action(action)) => {
+        assert_matches!(action.effects(), [1, 2, 3]);
+    });
+    assert_matches!(tree.evaluate(&(), "test-first", [0]), Ok(Outcome::Action(action)) => {
+        assert_matches!(action.effects(), [1]);
+    });
+    assert_matches!(tree.evaluate(&(), "test-pattern", ()), Ok(Outcome::Action(action)) => {
+        assert_matches!(action.effects(), [4]);
+    });
+    assert_eq!(tree.evaluate(&(), "test-empty", ()), Ok(Outcome::Failure));
+}
+
 #[test]
 fn queries() {
     let mut tree = BehaviorTreeBuilder::<&[i32], (), ()>::default();
@@ -160,6 +751,39 @@ fn queries() {
     assert!(! eval("test-last", &[1, 1, 0]).unwrap());
 }
 
+#[test]
+fn eager_queries() {
+    let mut tree = BehaviorTreeBuilder::<&[i32], (), i32>::default();
+    tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+    tree.register_condition("check", cond_fn!(_, value: i32 => value != 0));
+    tree.register_query_vec("values", (0, |ctx, _| ctx.iter().copied().map(Into::into).collect()));
+    tree.register_query_vec("scaled", (1, |ctx, args| {
+        let Some(factor) = args[0].int() else {
+            return Vec::new();
+        };
+        ctx.iter().map(|value| (value * factor).into()).collect()
+    }));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: emit $value
+        |  effects:
+        |    emit-value $value
+        |node: test-every
+        |  for-every $value: values
+        |    check $value
+        |node: test-scaled
+        |  with-last $value: scaled 10
+        |    emit $value
+    ")).unwrap();
+    assert_eq!(tree.evaluate(&&[1, 2, 3][..], "test-every", ()), Ok(Outcome::Success));
+    assert_eq!(tree.evaluate(&&[1, 0, 3][..], "test-every", ()), Ok(Outcome::Failure));
+    assert_matches!(
+        tree.evaluate(&&[1, 2, 3][..], "test-scaled", ()),
+        Ok(Outcome::Action(action)) => {
+            assert_matches!(action.effects(), [30]);
+        }
+    );
+}
+
 #[test]
 fn cond_cases() {
     let mut tree = BehaviorTreeBuilder::<(), (), i32>::default();
@@ -258,46 +882,143 @@ fn switch_cases() {
 }
 
 #[test]
-fn patterns() {
-    let mut tree = BehaviorTreeBuilder::<&[[i32; 2]], (), (i32, i32)>::default();
-    tree.register_global("$global", |_| 123.into());
+fn switch_computed_subjects() {
+    let mut tree = BehaviorTreeBuilder::<i32, (), i32>::default();
+    tree.register_global("$global", |ctx| (*ctx).into());
+    tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: emit $value
+        |  effects:
+        |    emit-value $value
+        |node: test-list $v
+        |  switch: [$v 2]
+        |    case: [23 $x]
+        |      emit $x
+        |    case: [$x 2]
+        |      emit 0
+        |node: test-global
+        |  switch: $global
+        |    case: 23
+        |      emit 1
+        |    case: $
+        |      emit 2
+        |node: test-literal
+        |  switch: 3 [4 5]
+        |    case: 3 [4 $x]
+        |      emit $x
+        |node: test-match-list $v
+        |  match [$a $b]: [$v 7]
+        |    emit $b
+    ")).unwrap();
+    let effects = |name, arguments: &[i32]| match tree.evaluate(&23, name, arguments) {
+        Ok(Outcome::Action(action)) => action.effects().to_vec(),
+        other => panic!("unexpected outcome {other:?}"),
+    };
+    assert_eq!(effects("test-list", &[23]), [2]);
+    assert_eq!(effects("test-list", &[42]), [0]);
+    assert_eq!(effects("test-global", &[]), [1]);
+    assert_eq!(effects("test-literal", &[]), [5]);
+    assert_eq!(effects("test-match-list", &[1]), [7]);
+}
+
+#[test]
+fn first_last_mixed_shapes() {
+    let mut tree = BehaviorTreeBuilder::<Vec<Value<()>>, (), (i32, i32)>::default();
     tree.register_effect("emit-value", effect_fn!(_, a: i32, b: i32 => Some((a, b))));
-    tree.register_query("values", query_fn!(ctx => ctx.iter().copied().map(Into::into)));
+    tree.register_query_vec("values", (0, |ctx, _| ctx.clone()));
     let tree = tree.compile_str(INDENT, "test", &normalize("
         |action: emit $a $b
         |  effects:
         |    emit-value $a $b
-        |node: test-for-every
-        |  for-every [$a $b]: values
-        |    emit $a $b
-        |node: test-for-any
-        |  for-any [$a $b]: values
-        |    emit $a $b
-        |node: test-visit-every
-        |  visit-every [$a $b]: values
-        |    emit $a $b
         |node: test-with-first
         |  with-first [$a $b]: values
         |    emit $a $b
         |node: test-with-last
         |  with-last [$a $b]: values
         |    emit $a $b
-        |node: test-match
-        |  with-first $value: values
-        |    match [$a $b]: $value
-        |      emit $a $b
-        |node: test-match-symbol $value
-        |  match abc: $value
-        |node: test-match-int $value
-        |  match 23: $value
-        |node: test-match-multi $value
-        |  match [$x $x]: $value
-        |node: test-match-global $value
-        |  match $global: $value
+        |node: test-first-only
+        |  for-first-only [$a $b]: values
+        |    emit $a $b
     ")).unwrap();
 
+    let mixed = vec![
+        Value::from(1),
+        Value::from_iter([2]),
+        Value::from_iter([3, 4]),
+        Value::from("x"),
+        Value::from_iter([5, 6]),
+        Value::from_iter([7, 8, 9]),
+        Value::from(10),
+    ];
     assert_matches!(
-        tree.evaluate(&&[[2, 3]][..], "test-for-every", ()),
+        tree.evaluate(&mixed, "test-with-first", ()),
+        Ok(Outcome::Action(action)) => {
+            assert_matches!(action.effects(), [(3, 4)]);
+        }
+    );
+    assert_matches!(
+        tree.evaluate(&mixed, "test-with-last", ()),
+        Ok(Outcome::Action(action)) => {
+            assert_matches!(action.effects(), [(5, 6)]);
+        }
+    );
+
+    assert_eq!(tree.evaluate(&mixed, "test-first-only", ()), Ok(Outcome::Failure));
+    assert_matches!(
+        tree.evaluate(&mixed[2..].to_vec(), "test-first-only", ()),
+        Ok(Outcome::Action(action)) => {
+            assert_matches!(action.effects(), [(3, 4)]);
+        }
+    );
+
+    let no_pairs = vec![Value::from(1), Value::from_iter([2, 3, 4]), Value::from("x")];
+    assert_eq!(tree.evaluate(&no_pairs, "test-with-first", ()), Ok(Outcome::Failure));
+    assert_eq!(tree.evaluate(&no_pairs, "test-with-last", ()), Ok(Outcome::Failure));
+    assert_eq!(tree.evaluate(&no_pairs, "test-first-only", ()), Ok(Outcome::Failure));
+    assert_eq!(tree.evaluate(&vec![], "test-first-only", ()), Ok(Outcome::Failure));
+}
+
+#[test]
+fn patterns() {
+    let mut tree = BehaviorTreeBuilder::<&[[i32; 2]], (), (i32, i32)>::default();
+    tree.register_global("$global", |_| 123.into());
+    tree.register_effect("emit-value", effect_fn!(_, a: i32, b: i32 => Some((a, b))));
+    tree.register_query("values", query_fn!(ctx => ctx.iter().copied().map(Into::into)));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: emit $a $b
+        |  effects:
+        |    emit-value $a $b
+        |node: test-for-every
+        |  for-every [$a $b]: values
+        |    emit $a $b
+        |node: test-for-any
+        |  for-any [$a $b]: values
+        |    emit $a $b
+        |node: test-visit-every
+        |  visit-every [$a $b]: values
+        |    emit $a $b
+        |node: test-with-first
+        |  with-first [$a $b]: values
+        |    emit $a $b
+        |node: test-with-last
+        |  with-last [$a $b]: values
+        |    emit $a $b
+        |node: test-match
+        |  with-first $value: values
+        |    match [$a $b]: $value
+        |      emit $a $b
+        |node: test-match-symbol $value
+        |  match abc: $value
+        |node: test-match-int $value
+        |  match 23: $value
+        |node: test-match-multi $value
+        |  match [$x $x]: $value
+        |node: test-match-global $value
+        |  match $global: $value
+    ")).unwrap();
+
+    assert_matches!(
+        tree.evaluate(&&[[2, 3]][..], "test-for-every", ()),
         Ok(Outcome::Action(action)) => {
             assert_matches!(action.effects(), [(2, 3)]);
         }
@@ -366,4 +1087,2236 @@ fn patterns() {
         tree.evaluate(&&[][..], "test-match-multi", ([23, 42],)),
         Ok(Outcome::Failure)
     );
-}
\ No newline at end of file
+}
+
+#[test]
+fn evaluate_and_apply() {
+    let mut tree = BehaviorTreeBuilder::<i32, (), i32>::default();
+    tree.register_effect("add", effect_fn!(_, value: i32 => Some(value)));
+    tree.register_condition("below", cond_fn!(ctx, value: i32 => *ctx < value));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: increment $value
+        |  conditions:
+        |    below 3
+        |  effects:
+        |    add $value
+    ")).unwrap();
+    let mut counter = 0;
+    let apply = |counter: &mut i32, value: &i32| *counter += value;
+    for _ in 0..5 {
+        tree.evaluate_and_apply(&mut counter, "increment", [1], apply).unwrap();
+    }
+    assert_eq!(counter, 3);
+    assert_matches!(
+        tree.evaluate_and_apply(&mut counter, "increment", [1], apply),
+        Ok(Outcome::Failure)
+    );
+    assert_eq!(counter, 3);
+}
+
+#[test]
+fn nested_queries() {
+    let mut tree = BehaviorTreeBuilder::<(), (), (i32, i32)>::default();
+    tree.register_condition("eq", cond_fn!(_, a: i32, b: i32 => a == b));
+    tree.register_effect("emit-value", effect_fn!(_, a: i32, b: i32 => Some((a, b))));
+    tree.register_query("nums", query_fn!(_ => [1, 2, 3].into_iter().map(Into::into)));
+    tree.register_query("scaled", query_fn!(_, n: i32 => [n, n * 10].into_iter().map(Into::into)));
+    tree.register_query("pair", query_fn!(_, n: i32 => [[n, n + 1]].into_iter().map(Into::into)));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: emit $a $b
+        |  effects:
+        |    emit-value $a $b
+        |node: test-every-any $value
+        |  for-every $outer: nums
+        |    for-any $inner: scaled $outer
+        |      eq $inner $value
+        |node: test-any-any $value
+        |  for-any $outer: nums
+        |    for-any $inner: scaled $outer
+        |      eq $inner $value
+        |node: test-inner-binding $value
+        |  with-last $outer: nums
+        |    with-last $inner: scaled $outer
+        |      emit $value $inner
+        |node: test-pattern-binding $value
+        |  with-first [$x $y]: pair $value
+        |    with-last $z: scaled $y
+        |      emit $x $z
+        |node: test-three-levels
+        |  for-any $a: nums
+        |    for-any $b: scaled $a
+        |      for-any [$c $d]: pair $b
+        |        eq $d 21
+        |        emit $a $c
+    ")).unwrap();
+
+    assert_eq!(tree.evaluate(&(), "test-every-any", [20]), Ok(Outcome::Failure));
+    assert_eq!(tree.evaluate(&(), "test-any-any", [20]), Ok(Outcome::Success));
+    assert_eq!(tree.evaluate(&(), "test-any-any", [4]), Ok(Outcome::Failure));
+    assert_matches!(
+        tree.evaluate(&(), "test-inner-binding", [5]),
+        Ok(Outcome::Action(action)) => {
+            assert_matches!(action.effects(), [(5, 30)]);
+        }
+    );
+    assert_matches!(
+        tree.evaluate(&(), "test-pattern-binding", [2]),
+        Ok(Outcome::Action(action)) => {
+            assert_matches!(action.effects(), [(2, 30)]);
+        }
+    );
+    assert_matches!(
+        tree.evaluate(&(), "test-three-levels", ()),
+        Ok(Outcome::Action(action)) => {
+            assert_matches!(action.effects(), [(2, 20)]);
+        }
+    );
+}
+
+#[test]
+fn running_outcomes() {
+    let mut tree = BehaviorTreeBuilder::<(), (), i32>::default();
+    tree.register_condition("ok", cond_fn!(_ => true));
+    tree.register_condition("fail", cond_fn!(_ => false));
+    tree.register_custom("pending", custom_fn!(_, _, _, _ => Outcome::Running));
+    tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: emit $value
+        |  effects:
+        |    emit-value $value
+        |action: emit-when-ready $value
+        |  conditions:
+        |    pending
+        |  effects:
+        |    emit-value $value
+        |node: test-sequence
+        |  do:
+        |    ok
+        |    pending
+        |    emit 23
+        |node: test-selection
+        |  select:
+        |    fail
+        |    pending
+        |    emit 23
+        |node: test-none
+        |  none:
+        |    fail
+        |    pending
+        |node: test-nested
+        |  select:
+        |    fail
+        |    test-sequence
+        |node: test-action-conditions
+        |  emit-when-ready 23
+    ")).unwrap();
+    assert_eq!(tree.evaluate(&(), "test-sequence", ()), Ok(Outcome::Running));
+    assert_eq!(tree.evaluate(&(), "test-selection", ()), Ok(Outcome::Running));
+    assert_eq!(tree.evaluate(&(), "test-none", ()), Ok(Outcome::Running));
+    assert_eq!(tree.evaluate(&(), "test-nested", ()), Ok(Outcome::Running));
+    assert_eq!(tree.evaluate(&(), "test-action-conditions", ()), Ok(Outcome::Running));
+}
+
+#[test]
+fn cache_stats() {
+    let mut tree = BehaviorTreeBuilder::<(), (), ()>::default();
+    tree.register_condition("ok", cond_fn!(_ => true));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |node: sub
+        |  ok
+        |node: test
+        |  sub
+        |  sub
+    ")).unwrap();
+    assert_eq!(
+        tree.evaluate_with_stats(&(), "test", ()),
+        Ok((Outcome::Success, CacheStats { hits: 1, misses: 2, evictions: 0 }))
+    );
+}
+
+#[test]
+fn persistent_cache() {
+    let build = || {
+        let mut tree = BehaviorTreeBuilder::<i32, (), ()>::default();
+        tree.register_condition("positive", cond_fn!(ctx => *ctx > 0));
+        tree.compile_str(INDENT, "test", &normalize("
+            |node: test
+            |  positive
+        ")).unwrap()
+    };
+    let tree = build();
+    let cache = PersistentCache::new();
+    assert_eq!(tree.evaluate_cached(&1, "test", (), &cache), Ok(Outcome::Success));
+    assert_eq!(cache.stats(), CacheStats { hits: 0, misses: 1, evictions: 0 });
+    assert_eq!(cache.len(), 1);
+    assert_eq!(tree.evaluate_cached(&1, "test", (), &cache), Ok(Outcome::Success));
+    assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 1, evictions: 0 });
+
+    assert_eq!(tree.evaluate_cached(&-1, "test", (), &cache), Ok(Outcome::Success));
+    cache.clear();
+    assert!(cache.is_empty());
+    assert_eq!(tree.evaluate_cached(&-1, "test", (), &cache), Ok(Outcome::Failure));
+    assert_eq!(tree.evaluate_cached(&-1, "test", (), &cache), Ok(Outcome::Failure));
+    assert_eq!(cache.stats(), CacheStats { hits: 3, misses: 2, evictions: 0 });
+    assert_matches!(tree.evaluate_cached(&1, "unknown", (), &cache), Err(_));
+
+    assert_eq!(tree.clone().evaluate_cached(&-1, "test", (), &cache), Ok(Outcome::Failure));
+    let other = build();
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        other.evaluate_cached(&-1, "test", (), &cache)
+    }));
+    assert!(result.is_err());
+}
+
+#[test]
+fn prepared_handles() {
+    let build = || {
+        let mut tree = BehaviorTreeBuilder::<(), (), ()>::default();
+        tree.register_condition("eq", cond_fn!(_, a: i32, b: i32 => a == b));
+        tree.compile_str(INDENT, "test", &normalize("
+            |node: test $value
+            |  eq $value 23
+        ")).unwrap()
+    };
+    let tree = build();
+    let handle = tree.prepare("test", 1).unwrap();
+    assert_eq!(handle.arity(), 1);
+    for value in [23, 42] {
+        assert_eq!(
+            tree.evaluate_handle(&(), handle, [value]),
+            tree.evaluate(&(), "test", [value])
+        );
+    }
+    let error = tree.evaluate_handle(&(), handle, [23, 42]).unwrap_err();
+    assert_eq!(error.source, IdError::Arity(ArityError { expected: 1, given: 2 }));
+    assert_eq!(error.to_string(), "Unable to evaluate `test`: Wrong arity: Expected 1, given 2");
+    assert_eq!(tree.clone().evaluate_handle(&(), handle, [23]), Ok(Outcome::Success));
+    assert_eq!(tree.prepare("test", 2), Err(IdError::Arity(ArityError { expected: 1, given: 2 })));
+    assert_eq!(tree.prepare("unknown", 0), Err(IdError::Unknown));
+
+    let other = build();
+    let result = std::panic::catch_unwind(|| other.evaluate_handle(&(), handle, [23]));
+    assert!(result.is_err());
+}
+
+#[test]
+fn bound_nodes() {
+    let mut tree = BehaviorTreeBuilder::<(), (), ()>::default();
+    tree.register_condition("eq", cond_fn!(_, a: i32, b: i32 => a == b));
+    tree.register_effect("noop", effect_fn!(_ => Some(())));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |node: test $a $b
+        |  eq $a $b
+    ")).unwrap();
+    let bound = tree.bind("test", &[23.into()]).unwrap();
+    assert_eq!(bound.name(), "test");
+    assert_eq!(bound.prefix(), [Value::from(23)]);
+    assert_eq!(tree.evaluate_bound(&(), &bound, [23]), Ok(Outcome::Success));
+    assert_eq!(tree.evaluate_bound(&(), &bound, [42]), Ok(Outcome::Failure));
+    assert_eq!(
+        tree.evaluate_bound(&(), &bound, ()).unwrap_err().to_string(),
+        "Unable to evaluate `test`: Wrong arity: Expected 2, given 1"
+    );
+
+    let bound = tree.bind("eq", &[23.into(), 23.into()]).unwrap();
+    assert_eq!(tree.evaluate_bound(&(), &bound, ()), Ok(Outcome::Success));
+    let bound = tree.bind("test", &[1.into(), 2.into(), 3.into()]).unwrap();
+    assert_eq!(
+        tree.evaluate_bound(&(), &bound, ()),
+        Err(EvaluateError {
+            node: "test".into(),
+            source: IdError::Arity(ArityError { expected: 2, given: 3 }),
+        })
+    );
+
+    assert_eq!(tree.bind("unknown", &[]).err(), Some(IdError::Unknown));
+    assert_matches!(tree.bind("noop", &[]).err(), Some(IdError::Kind(_)));
+}
+
+#[test]
+fn discovery_order() {
+    let build = || {
+        let mut tree = BehaviorTreeBuilder::<&'static [i32], (), i32>::default();
+        tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+        tree.register_condition("below", cond_fn!(_, a: i32, b: i32 => a < b));
+        tree.register_query("values", query_fn!(ctx => ctx.iter().copied().map(Into::into)));
+        tree.compile_str(INDENT, "test", &normalize("
+            |action: emit-large $value
+            |  conditions:
+            |    below 10 $value
+            |  effects:
+            |    emit-value $value
+            |  discovery:
+            |    visit-every $value: values
+            |      emit-large $value
+            |action: emit-small $value
+            |  conditions:
+            |    below $value 10
+            |  effects:
+            |    emit-value $value
+            |  discovery:
+            |    visit-every $value: values
+            |      emit-small $value
+            |action: emit-both $a $b
+            |  effects:
+            |    emit-value $a
+            |    emit-value $b
+            |  discovery:
+            |    visit-every $a: values
+            |      visit-every $b: values
+            |        below $a $b
+            |        emit-both $a $b
+        ")).unwrap()
+    };
+    let view: &[i32] = &[30, 3, 20, 2];
+    let expected = [
+        ("emit-large", vec![30]),
+        ("emit-large", vec![20]),
+        ("emit-small", vec![3]),
+        ("emit-small", vec![2]),
+        ("emit-both", vec![3, 30]),
+        ("emit-both", vec![3, 20]),
+        ("emit-both", vec![20, 30]),
+        ("emit-both", vec![2, 30]),
+        ("emit-both", vec![2, 3]),
+        ("emit-both", vec![2, 20]),
+    ];
+    for _ in 0..5 {
+        let tree = build();
+        for _ in 0..2 {
+            let mut actions = Vec::new();
+            tree.discover_all(&view, &mut actions);
+            let discovered: Vec<_> = actions.iter()
+                .map(|action| (tree.action_name(action).as_str(), action.effects().to_vec()))
+                .collect();
+            assert_eq!(discovered, expected);
+        }
+    }
+}
+
+#[test]
+fn none_is_inactive() {
+    let mut tree = BehaviorTreeBuilder::<(), (), i32>::default();
+    tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+    tree.register_condition("below", cond_fn!(_, a: i32, b: i32 => a < b));
+    tree.register_custom("active", custom_fn!(_, _, is_active, _ => is_active.into()));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: emit $value
+        |  conditions:
+        |    below $value 10
+        |  effects:
+        |    emit-value $value
+        |  discovery:
+        |    select:
+        |      none:
+        |        emit 1
+        |      emit 2
+        |node: test $value
+        |  none:
+        |    emit $value
+        |node: test-active
+        |  none:
+        |    active
+    ")).unwrap();
+    let mut actions = Vec::new();
+    tree.discover_all(&(), &mut actions);
+    assert_matches!(&actions[..], [action] => {
+        assert_matches!(action.effects(), [2]);
+    });
+    assert_eq!(tree.evaluate(&(), "test", [1]), Ok(Outcome::Success));
+    assert_eq!(tree.evaluate(&(), "test", [20]), Ok(Outcome::Success));
+    assert_eq!(tree.evaluate(&(), "test-active", ()), Ok(Outcome::Success));
+}
+
+#[test]
+fn evaluate_errors() {
+    let mut tree = BehaviorTreeBuilder::<(), (), ()>::default();
+    tree.register_query("values", query_fn!(_ => [1, 2, 3].map(Value::from)));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: act $value
+        |node: test $value
+        |  act $value
+    ")).unwrap();
+    let error = |node: &str, source| Err(EvaluateError { node: node.into(), source });
+    assert_eq!(tree.evaluate(&(), "unknown", ()), error("unknown", IdError::Unknown));
+    assert_eq!(
+        tree.evaluate(&(), "test", ()),
+        error("test", IdError::Arity(ArityError { expected: 1, given: 0 }))
+    );
+    assert_matches!(
+        tree.check(&(), "values", ()),
+        Err(EvaluateError { node, source: IdError::Kind(KindError { given: Kind::Query, .. }) }) => {
+            assert_eq!(node, "values");
+        }
+    );
+    assert_eq!(
+        tree.discover(&(), "test", &mut Vec::new()).unwrap_err(),
+        EvaluateError {
+            node: "test".into(),
+            source: IdError::Kind(KindError { expected: Kind::Action.into(), given: Kind::Node }),
+        }
+    );
+    assert_eq!(
+        tree.evaluate(&(), "unknown", ()).unwrap_err().to_string(),
+        "Unable to evaluate `unknown`: Unknown identifier"
+    );
+}
+
+#[test]
+fn shared_list_literals() {
+    let tree = BehaviorTreeBuilder::<(), (), ()>::default();
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: act $list
+        |node: test-a
+        |  act [1 2 [3]]
+        |node: test-b
+        |  act [1 2 [3]]
+        |node: test-c $value
+        |  act [1 2 [$value]]
+    ")).unwrap();
+    let list = |node: &str, arguments: &[i32]| {
+        let arguments: Vec<Value<()>> = arguments.iter().copied().map(Into::into).collect();
+        match tree.evaluate(&(), node, arguments) {
+            Ok(Outcome::Action(action)) => match &action.arguments()[0] {
+                Value::List(list) => list.clone(),
+                other => panic!("unexpected argument {other:?}"),
+            },
+            other => panic!("unexpected outcome {other:?}"),
+        }
+    };
+    assert!(Arc::ptr_eq(&list("test-a", &[]), &list("test-b", &[])));
+    assert!(!Arc::ptr_eq(&list("test-c", &[3]), &list("test-c", &[3])));
+    assert_eq!(list("test-a", &[]), list("test-c", &[3]));
+}
+
+#[test]
+fn repeated_bindings() {
+    let tree = BehaviorTreeBuilder::<(), (), ()>::default();
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |node: test-list $value
+        |  match [$x $x]: $value
+        |node: test-nested $a $b
+        |  match [$x $]: $a
+        |    match [$ $x]: $b
+        |node: test-parameter $a $b
+        |  match [$a $x]: $b
+    ")).unwrap();
+    let list = |a: i32, b: i32| Value::<()>::List([a.into(), b.into()].into());
+    assert_eq!(tree.evaluate(&(), "test-list", [list(2, 2)]), Ok(Outcome::Success));
+    assert_eq!(tree.evaluate(&(), "test-list", [list(2, 3)]), Ok(Outcome::Failure));
+    assert_eq!(tree.evaluate(&(), "test-nested", [list(2, 0), list(0, 2)]), Ok(Outcome::Success));
+    assert_eq!(tree.evaluate(&(), "test-nested", [list(2, 0), list(2, 0)]), Ok(Outcome::Failure));
+    assert_eq!(tree.evaluate(&(), "test-parameter", [2.into(), list(2, 3)]), Ok(Outcome::Success));
+    assert_eq!(tree.evaluate(&(), "test-parameter", [3.into(), list(2, 3)]), Ok(Outcome::Failure));
+}
+
+#[test]
+fn source_hashes() {
+    let hash = |content: &str| ScriptSource::from_named("test", content.into()).content_hash();
+    assert_eq!(hash("node: test\n  ok"), hash("node: test\n  ok"));
+    assert_ne!(hash("node: test\n  ok"), hash("node: test\n  fail"));
+    assert_eq!(
+        ScriptSource::from_named("other", "node: test\n  ok".into()).content_hash(),
+        hash("node: test\n  ok")
+    );
+    let missing = ScriptSource::from_path("does/not/exist").content_hash();
+    assert_eq!(missing, ScriptSource::from_path("does/not/exist").content_hash());
+    assert_ne!(missing, hash(""));
+
+    let source = ScriptSource::from_named("test", "node: test\n  ok".into());
+    assert!(!source.is_changed(hash("node: test\n  ok")));
+    assert!(source.is_changed(hash("node: test\n  fail")));
+}
+
+#[test]
+fn ext_predicates() {
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    enum Unit {
+        Enemy(i32),
+        Ally(i32),
+    }
+    let mut tree = BehaviorTreeBuilder::<Vec<Value<Unit>>, Unit, i32>::default();
+    tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+    tree.register_condition("is-enemy", cond_fn!(_, unit: ExtValue<Unit> => {
+        matches!(unit.0, Unit::Enemy(_))
+    }));
+    tree.register_query("units", query_fn!(ctx => ctx.clone()));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: emit $value
+        |  effects:
+        |    emit-value $value
+        |node: test-match $value
+        |  match @is-enemy: $value
+        |node: test-list $value
+        |  match [@is-enemy $id]: $value
+        |    emit $id
+        |node: test-query
+        |  for-every [@is-enemy $id]: units
+        |    emit $id
+    ")).unwrap();
+    let view = vec![
+        Value::List([Value::Ext(Unit::Enemy(1)), 1.into()].into()),
+        Value::List([Value::Ext(Unit::Ally(2)), 2.into()].into()),
+        Value::List([Value::Ext(Unit::Enemy(3)), 3.into()].into()),
+    ];
+    let enemy = Value::Ext(Unit::Enemy(1));
+    let ally = Value::Ext(Unit::Ally(1));
+    assert_eq!(tree.evaluate(&view, "test-match", [enemy]), Ok(Outcome::Success));
+    assert_eq!(tree.evaluate(&view, "test-match", [ally]), Ok(Outcome::Failure));
+    assert_eq!(tree.evaluate(&view, "test-match", [Value::from(1)]), Ok(Outcome::Failure));
+    assert_matches!(
+        tree.evaluate(&view, "test-list", [view[0].clone()]),
+        Ok(Outcome::Action(action)) => {
+            assert_matches!(action.effects(), [1]);
+        }
+    );
+    assert_eq!(tree.evaluate(&view, "test-list", [view[1].clone()]), Ok(Outcome::Failure));
+    assert_matches!(tree.evaluate(&view, "test-query", ()), Ok(Outcome::Action(action)) => {
+        assert_matches!(action.effects(), [1]);
+    });
+
+    for name in ["unknown", "emit-value"] {
+        let source = format!("node: test $v\n  match @{name}: $v");
+        let mut tree = BehaviorTreeBuilder::<(), (), i32>::default();
+        tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+        assert_matches!(
+            tree.compile_str(INDENT, "test", &source).err(),
+            Some(CompileError::Script(_)),
+            "source: {source:?}"
+        );
+    }
+}
+
+#[test]
+fn fallback_nodes() {
+    let build = |with_fallback: bool| {
+        let mut tree = BehaviorTreeBuilder::<(), (), ()>::default();
+        tree.register_condition("ok", cond_fn!(_ => true));
+        if with_fallback {
+            tree.register_fallback(|name, arity| match (name, arity) {
+                ("optional-stub", 1) => Some(|_, _, _, _, _| Outcome::Success),
+                _ => None,
+            });
+        }
+        tree.compile_str(INDENT, "test", &normalize("
+            |node: test
+            |  ok
+            |  optional-stub 23
+            |node: test-query
+            |  optional-stub? 23
+        "))
+    };
+    let tree = build(true).unwrap();
+    assert_eq!(tree.evaluate(&(), "test", ()), Ok(Outcome::Success));
+    assert_eq!(tree.evaluate(&(), "test-query", ()), Ok(Outcome::Success));
+    assert_matches!(build(false).err(), Some(CompileError::Script(_)));
+
+    let mut tree = BehaviorTreeBuilder::<(), (), ()>::default();
+    tree.register_fallback(|_, _| None);
+    assert_matches!(
+        tree.compile_str(INDENT, "test", "node: test\n  missing").err(),
+        Some(CompileError::Script(_))
+    );
+
+    // only node statements consult the fallback, other refs stay unknown
+    for source in [
+        "node: test\n  applicable: optional-stub 23",
+        "node: test\n  for-any $value: optional-stub 23\n    ok",
+        "action: test\n  effects:\n    optional-stub 23",
+    ] {
+        let builder = || {
+            let mut tree = BehaviorTreeBuilder::<(), (), ()>::default();
+            tree.register_condition("ok", cond_fn!(_ => true));
+            tree.register_fallback(|name, arity| match (name, arity) {
+                ("optional-stub", 1) => Some(|_, _, _, _, _| Outcome::Success),
+                _ => None,
+            });
+            tree
+        };
+        let required =
+            builder().required_symbols(INDENT, [ScriptSource::from_named("test", source.into())]);
+        assert_eq!(required.ok(), Some(["optional-stub".into()].into()), "source: {source:?}");
+        let error = builder().compile_str(INDENT, "test", source).err();
+        assert_eq!(
+            error.map(|error| error.to_string()),
+            Some("for `optional-stub`: Unknown identifier".into()),
+            "source: {source:?}"
+        );
+    }
+}
+
+#[test]
+fn effect_calls() {
+    let mut tree = BehaviorTreeBuilder::<i32, (), i32>::default();
+    tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+    tree.register_effect("emit-positive", effect_fn!(_, value: i32 => {
+        (value > 0).then_some(value)
+    }));
+    tree.register_condition("ok", cond_fn!(_ => true));
+    let tree = tree.compile_str(INDENT, "test", "").unwrap();
+    let results = tree.effects(&0, &[
+        ("emit-value", &[23.into()]),
+        ("emit-positive", &[(-1).into()]),
+        ("emit-value", &[]),
+        ("ok", &[]),
+        ("emit-positive", &[42.into()]),
+    ]);
+    assert_eq!(results, [
+        Ok(Some(vec![23])),
+        Ok(None),
+        Err(IdError::Arity(ArityError { expected: 1, given: 0 })),
+        Err(IdError::Kind(KindError { expected: Kind::Effect.into(), given: Kind::Cond })),
+        Ok(Some(vec![42])),
+    ]);
+}
+
+#[test]
+fn action_names() {
+    let mut tree = BehaviorTreeBuilder::<(), (), ()>::default();
+    tree.register_condition("ok", cond_fn!(_ => true));
+    let source: String = (0..200)
+        .map(|n| format!("action: act-{n}\n  conditions:\n    ok\n"))
+        .collect();
+    let tree = tree.compile_str(INDENT, "test", &source).unwrap();
+    for n in (0..200).rev() {
+        let name = format!("act-{n}");
+        assert_matches!(tree.evaluate(&(), &name, ()), Ok(Outcome::Action(action)) => {
+            assert_eq!(tree.action_name(&action), &name);
+        });
+    }
+}
+
+#[test]
+fn shared_across_threads() {
+    fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+    let mut tree = BehaviorTreeBuilder::<i32, (), i32>::default();
+    tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+    tree.register_condition("below", cond_fn!(_, a: i32, b: i32 => a < b));
+    tree.register_global("$limit", |ctx| (*ctx).into());
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: emit $value
+        |  conditions:
+        |    below $value $limit
+        |  effects:
+        |    emit-value $value
+        |node: test $value
+        |  emit $value
+    ")).unwrap();
+    assert_send_sync(&tree);
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..4)
+            .map(|worker| {
+                let tree = &tree;
+                scope.spawn(move || {
+                    (0..100)
+                        .map(|value| match tree.evaluate(&50, "test", [worker * 100 + value]) {
+                            Ok(Outcome::Action(action)) => action.effects().len(),
+                            Ok(_) => 0,
+                            Err(error) => panic!("unexpected error: {error}"),
+                        })
+                        .sum::<usize>()
+                })
+            })
+            .collect();
+        let counts: Vec<_> = workers.into_iter().map(|worker| worker.join().unwrap()).collect();
+        assert_eq!(counts, [50, 0, 0, 0]);
+    });
+}
+
+#[test]
+fn id_error_conversions() {
+    let arity = ArityError { expected: 2, given: 3 };
+    assert_eq!(IdError::from(arity), IdError::Arity(arity));
+    let kind = KindError { expected: Kind::Node.into(), given: Kind::Query };
+    assert_eq!(IdError::from(kind), IdError::Kind(kind));
+
+    fn resolve(given: usize) -> Result<(), IdError> {
+        if given != 2 {
+            Err(ArityError { expected: 2, given })?;
+        }
+        Ok(())
+    }
+    assert_eq!(resolve(2), Ok(()));
+    assert_eq!(resolve(1), Err(IdError::Arity(ArityError { expected: 2, given: 1 })));
+}
+
+#[test]
+fn docs() {
+    let mut tree = BehaviorTreeBuilder::<(), (), ()>::default();
+    tree.register_effect("noop", effect_fn!(_ => Some(())));
+    tree.register_condition("cond", cond_fn!(_ => true));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: move
+        |  doc: Moves toward the target
+        |  effects:
+        |    noop
+        |node: test
+        |  doc: Checks the condition
+        |  cond
+        |  doc: 2 times
+        |node: undocumented
+        |  cond
+    ")).unwrap();
+    assert_eq!(tree.doc("move"), Some("Moves toward the target"));
+    assert_eq!(tree.doc("test"), Some("Checks the condition\n2 times"));
+    assert_eq!(tree.evaluate(&(), "test", ()), Ok(Outcome::Success));
+    assert_eq!(tree.doc("undocumented"), None);
+    assert_eq!(tree.doc("cond"), None);
+    assert_eq!(tree.doc("unknown"), None);
+
+    for source in ["node: test\n  doc:", "node: test\n  doc: [a]", "node: test\n  doc: a\n    b"] {
+        let tree = BehaviorTreeBuilder::<(), (), ()>::default();
+        assert_matches!(
+            tree.compile_str(INDENT, "test", source).err(),
+            Some(CompileError::Script(_)),
+            "source: {source:?}"
+        );
+    }
+}
+
+#[test]
+fn argument_reification() {
+    let mut tree = BehaviorTreeBuilder::<i32, (), Vec<i32>>::default();
+    tree.register_global("$global", |ctx| (*ctx).into());
+    tree.register_effect("emit-values", effect_fn!(_, a: i32, b: i32, c: i32, d: i32, e: i32 => {
+        Some(vec![a, b, c, d, e])
+    }));
+    tree.register_condition("eq", cond_fn!(_, a: i32, b: i32 => a == b));
+    tree.register_query_vec("values", (2, |_, args| args.to_vec()));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: emit $a $b $c $d $e
+        |  effects:
+        |    emit-values $a $b $c $d $e
+        |    emit-values $e $d $c $b $a
+        |  inherit:
+        |    with-first $x: values $a $b
+        |      emit-last $x $global
+        |action: emit-last $a $b
+        |  effects:
+        |    emit-values $a $b $a $b $a
+        |node: wide $a $b $c $d $e $f $g $h $i
+        |  eq $a $i
+        |  match [$x $y]: [$b $h]
+        |    deep $x $y $c $d $e $f $g
+        |node: deep $a $b $c $d $e $f $g
+        |  eq $a 2
+        |  eq $b 8
+        |  for-every $x: values $c $d
+        |    with-last $y: values $e $f
+        |      emit $x $y $g $global $a
+    ")).unwrap();
+    assert_matches!(
+        tree.evaluate(&23, "wide", [1, 2, 3, 4, 5, 6, 7, 8, 1]),
+        Ok(Outcome::Action(action)) => {
+            assert_eq!(tree.action_name(&action), "emit");
+            assert_eq!(action.effects(), [
+                vec![3, 6, 7, 23, 2],
+                vec![2, 23, 7, 6, 3],
+                vec![3, 23, 3, 23, 3],
+            ]);
+        }
+    );
+    assert_eq!(tree.evaluate(&23, "wide", [1, 2, 3, 4, 5, 6, 7, 8, 9]), Ok(Outcome::Failure));
+    assert_eq!(tree.evaluate(&23, "wide", [1, 2, 3, 4, 5, 6, 7, 9, 1]), Ok(Outcome::Failure));
+}
+
+#[test]
+fn empty_nodes() {
+    for source in [
+        ":",
+        "node:",
+        "node: test\n  :",
+        "action: test\n  :",
+        "action: test\n  effects:\n    :",
+    ] {
+        let tree = BehaviorTreeBuilder::<(), (), ()>::default();
+        assert_matches!(
+            tree.compile_str(INDENT, "test", source).err(),
+            Some(CompileError::Script(_)),
+            "source {source:?} should fail to compile"
+        );
+    }
+}
+
+#[test]
+fn tagged_patterns() {
+    let mut tree = BehaviorTreeBuilder::<Vec<Value<()>>, (), i32>::default();
+    tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+    tree.register_query("values", query_fn!(ctx => ctx.clone()));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: emit $value
+        |  effects:
+        |    emit-value $value
+        |node: test-match $value
+        |  match #point[$x $y]: $value
+        |    emit $x
+        |node: test-nested $value
+        |  match [#point[$ $y] #size[$w]]: $value
+        |    emit $w
+        |node: test-multiple $a $b
+        |  match #point[$x $] #size[$w]: $a $b
+        |    emit $x
+        |node: test-query
+        |  for-every #point[$x $]: values
+        |    emit $x
+    ")).unwrap();
+    let point = Value::tagged("point", [2, 3]);
+    let size = Value::tagged("size", [4]);
+    let view = vec![size.clone(), Value::tagged("point", [5, 6]), point.clone()];
+    assert_matches!(
+        tree.evaluate(&view, "test-match", [point.clone()]),
+        Ok(Outcome::Action(action)) => {
+            assert_matches!(action.effects(), [2]);
+        }
+    );
+    assert_eq!(tree.evaluate(&view, "test-match", [size.clone()]), Ok(Outcome::Failure));
+    assert_eq!(tree.evaluate(&view, "test-match", [Value::from([2, 3])]), Ok(Outcome::Failure));
+    let short = Value::tagged("point", [2]);
+    assert_eq!(tree.evaluate(&view, "test-match", [short]), Ok(Outcome::Failure));
+    assert_matches!(
+        tree.evaluate(&view, "test-nested", [Value::from([point.clone(), size.clone()])]),
+        Ok(Outcome::Action(action)) => {
+            assert_matches!(action.effects(), [4]);
+        }
+    );
+    assert_eq!(
+        tree.evaluate(&view, "test-nested", [Value::from([size.clone(), point.clone()])]),
+        Ok(Outcome::Failure),
+    );
+    assert_matches!(
+        tree.evaluate(&view, "test-multiple", (point.clone(), size.clone())),
+        Ok(Outcome::Action(action)) => {
+            assert_matches!(action.effects(), [2]);
+        }
+    );
+    assert_matches!(tree.evaluate(&view, "test-query", ()), Ok(Outcome::Action(action)) => {
+        assert_matches!(action.effects(), [5]);
+    });
+
+    for source in ["
+        |node: test $a
+        |  match #point[$x] #size[$w]: $a
+    ", "
+        |node: test
+        |  for-every #point[$x] $y: values
+    "] {
+        assert_matches!(
+            BehaviorTreeBuilder::<(), (), ()>::default()
+                .compile_str(INDENT, "test", &normalize(source))
+                .err(),
+            Some(CompileError::Script(_)),
+            "source {source:?} should fail to compile"
+        );
+    }
+}
+
+#[test]
+fn identical_redefinitions() {
+    let shared = normalize("
+        |node: shared $value
+        |  below $value [2 [3]]
+    ");
+    let builder = || {
+        let mut tree = BehaviorTreeBuilder::<(), (), ()>::default();
+        tree.register_condition("below", cond_fn!(_, a: i32, _b: Value<()> => a < 2));
+        tree
+    };
+    let sources = |other: &str| [
+        ScriptSource::Str { name: "first".into(), content: shared.as_str().into() },
+        ScriptSource::Str { name: "second".into(), content: other.into() },
+    ];
+
+    assert_matches!(
+        builder().compile(INDENT, sources(&shared)).err(),
+        Some(CompileError::Conflict(_))
+    );
+
+    let mut tree = builder();
+    tree.allow_identical_redefinition(true);
+    let tree = tree.compile(INDENT, sources(&format!("\n\n{shared}"))).unwrap();
+    assert_eq!(tree.evaluate(&(), "shared", [1]), Ok(Outcome::Success));
+    assert_eq!(tree.evaluate(&(), "shared", [2]), Ok(Outcome::Failure));
+
+    for other in [
+        "node: shared $value\n  below $value [2 [4]]",
+        "node: shared $other\n  below $other [2 [3]]",
+        "node: shared $value\n  below $value [2 [3]]\n  below $value [2 [3]]",
+        "action: shared $value\n  conditions:\n    below $value [2 [3]]",
+    ] {
+        let mut tree = builder();
+        tree.allow_identical_redefinition(true);
+        assert_matches!(
+            tree.compile(INDENT, sources(other)).err(),
+            Some(CompileError::Conflict(_)),
+            "source: {other:?}"
+        );
+    }
+}
+
+#[test]
+fn effect_iteration() {
+    let mut tree = BehaviorTreeBuilder::<(), (), i32>::default();
+    tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+    tree.register_condition("fail", cond_fn!(_ => false));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: none
+        |action: one
+        |  effects:
+        |    emit-value 1
+        |action: many
+        |  effects:
+        |    emit-value 1
+        |    emit-value 2
+        |    emit-value 3
+        |node: test-failure
+        |  fail
+    ")).unwrap();
+    for (name, expected) in [("none", &[][..]), ("one", &[1]), ("many", &[1, 2, 3])] {
+        let outcome = tree.evaluate(&(), name, ()).unwrap();
+        assert_eq!(outcome.effect_count(), expected.len(), "action {name}");
+        let mut seen = Vec::new();
+        outcome.each_effect(|effect| seen.push(*effect));
+        assert_eq!(seen, expected, "action {name}");
+        let mut seen = Vec::new();
+        assert_matches!(outcome, Outcome::Action(action) => {
+            action.for_each_effect(|effect| seen.push(*effect));
+        });
+        assert_eq!(seen, expected, "action {name}");
+    }
+    let outcome = tree.evaluate(&(), "test-failure", ()).unwrap();
+    assert_eq!(outcome.effect_count(), 0);
+    outcome.each_effect(|_| panic!("non-action outcomes have no effects"));
+}
+
+#[test]
+fn normalized_sources() {
+    let builder = || {
+        let mut tree = BehaviorTreeBuilder::<(), (), ()>::default();
+        tree.register_condition("below", cond_fn!(_, a: i32, b: i32 => a < b));
+        tree
+    };
+    for source in ["
+        |node: test $value
+        |  below $value 2
+        |
+        |node: other
+        |  test 1
+    ", "
+        node: test $value
+          below $value 2
+
+        node: other
+          test 1
+    "] {
+        let tree = builder().compile_str_normalized("test", source).unwrap();
+        assert_eq!(tree.evaluate(&(), "test", [1]), Ok(Outcome::Success));
+        assert_eq!(tree.evaluate(&(), "test", [2]), Ok(Outcome::Failure));
+        assert_eq!(tree.evaluate(&(), "other", ()), Ok(Outcome::Success));
+    }
+    assert_matches!(
+        builder().compile_str_normalized("test", "
+            |node: test
+            |   below 1 2
+        ").err(),
+        Some(CompileError::Script(_))
+    );
+}
+
+#[test]
+fn unordered_effects() {
+    let mut tree = BehaviorTreeBuilder::<(), (), i32>::default();
+    tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: emit $a $b $c
+        |  effects:
+        |    emit-value $a
+        |    emit-value $b
+        |    emit-value $c
+    ")).unwrap();
+    let emit = |values: [i32; 3]| match tree.evaluate(&(), "emit", values) {
+        Ok(Outcome::Action(action)) => action,
+        other => panic!("unexpected outcome {other:?}"),
+    };
+    let action = emit([1, 2, 2]);
+    assert!(action.effects_eq_unordered(&emit([1, 2, 2])));
+    assert!(action.effects_eq_unordered(&emit([2, 1, 2])));
+    assert_ne!(action, emit([2, 1, 2]));
+    assert!(!action.effects_eq_unordered(&emit([1, 1, 2])));
+    assert!(!action.effects_eq_unordered(&emit([1, 2, 3])));
+}
+
+#[test]
+fn assertions() {
+    let mut tree = BehaviorTreeBuilder::<(), (), ()>::default();
+    tree.register_condition("below", cond_fn!(_, a: i32, b: i32 => a < b));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |node: test $value
+        |  assert:
+        |    below 0 $value
+        |    below $value 10
+        |  below $value 5
+    ")).unwrap();
+    assert_eq!(tree.evaluate(&(), "test", [3]), Ok(Outcome::Success));
+    assert_eq!(tree.evaluate(&(), "test", [7]), Ok(Outcome::Failure));
+
+    let result = std::panic::catch_unwind(|| tree.evaluate(&(), "test", [23]));
+    if cfg!(debug_assertions) {
+        let message = result.unwrap_err().downcast::<String>().unwrap();
+        assert_eq!(*message, "assertion failed at test:4:5: `below $value 10` in `test`");
+    } else {
+        assert_eq!(result.unwrap(), Ok(Outcome::Failure));
+    }
+}
+
+#[test]
+fn once_blocks() {
+    use std::cell::Cell;
+
+    let mut tree = BehaviorTreeBuilder::<Cell<usize>, (), ()>::default();
+    tree.register_query("values", query_fn!(ctx => {
+        ctx.set(ctx.get() + 1);
+        (0..3).map(Value::from)
+    }));
+    tree.register_condition("eq", cond_fn!(_, a: i32, b: i32 => a == b));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |node: shared
+        |  once:
+        |    for-any $value: values
+        |      eq $value 1
+        |node: plain $unused
+        |  for-any $value: values
+        |    eq $value 1
+        |node: keyed $target
+        |  once:
+        |    for-any $value: values
+        |      eq $value $target
+        |node: test-shared
+        |  shared
+        |  shared
+        |  shared
+        |node: test-plain
+        |  plain 1
+        |  plain 2
+        |  plain 3
+        |node: test-keyed
+        |  keyed 1
+        |  keyed 1
+        |  keyed 2
+        |node: test-keyed-missing
+        |  keyed 1
+        |  keyed 5
+        |node: test-best
+        |  select-best:
+        |    shared
+        |    shared
+        |node: test-separate
+        |  once:
+        |    for-any $value: values
+        |      eq $value 1
+        |  once:
+        |    for-any $value: values
+        |      eq $value 1
+    ")).unwrap();
+    for (node, calls) in [
+        ("test-shared", 1),
+        ("test-plain", 3),
+        ("test-separate", 2),
+        ("test-keyed", 2),
+        ("test-best", 1),
+    ] {
+        let counter = Cell::new(0);
+        assert_eq!(tree.evaluate(&counter, node, ()), Ok(Outcome::Success));
+        assert_eq!(counter.get(), calls, "{node}");
+        assert_eq!(tree.evaluate(&counter, node, ()), Ok(Outcome::Success));
+        assert_eq!(counter.get(), calls * 2, "{node}");
+    }
+    assert_eq!(tree.evaluate(&Cell::new(0), "test-keyed-missing", ()), Ok(Outcome::Failure));
+
+    let cache = PersistentCache::new();
+    let counter = Cell::new(0);
+    assert_eq!(tree.evaluate_cached(&counter, "test-separate", (), &cache), Ok(Outcome::Success));
+    assert_eq!(counter.get(), 2);
+    assert_eq!(cache.stats(), CacheStats { hits: 2, misses: 2, evictions: 0 });
+    assert_eq!(cache.len(), 2);
+}
+
+#[test]
+fn empty_blocks() {
+    let builder = || {
+        let mut tree = BehaviorTreeBuilder::<(), (), ()>::default();
+        tree.register_query("values", query_fn!(_ => [1, 2].map(Value::from)));
+        tree
+    };
+    for (source, keyword) in [
+        ("node: test\n  select:", "select"),
+        ("node: test\n  for-any $value: values", "for-any"),
+        ("node: test\n  for-every $value: values", "for-every"),
+        ("node: test\n  with-first $value: [1 2]", "with-first"),
+        ("node: test $value\n  switch: $value", "switch"),
+    ] {
+        assert_matches!(
+            builder().compile_str(INDENT, "test", source).err(),
+            Some(CompileError::Script(error)) => {
+                assert_matches!(
+                    error.error(),
+                    ScriptError::EmptyBlock { keyword: found } => {
+                        assert_eq!(*found, keyword);
+                    }
+                );
+            },
+            "source: {source:?}"
+        );
+    }
+
+    let tree = builder().compile_str(INDENT, "test", &normalize("
+        |node: test-do
+        |  do:
+        |node: test-match $value
+        |  match 1: $value
+    ")).unwrap();
+    assert_eq!(tree.evaluate(&(), "test-do", ()), Ok(Outcome::Success));
+    assert_eq!(tree.evaluate(&(), "test-match", [1]), Ok(Outcome::Success));
+}
+
+#[test]
+fn aliases() {
+    let mut tree = BehaviorTreeBuilder::<i32, (), ()>::default();
+    tree.register_condition("within-distance", cond_fn!(ctx, max: i32 => *ctx <= max));
+    tree.register_global("$range", |_| 3.into());
+    tree.alias("near", "within-distance").unwrap();
+    tree.alias("$reach", "$range").unwrap();
+    assert_matches!(
+        tree.alias("near", "within-distance"),
+        Err(AliasError::Taken { name, kind: Kind::Cond }) => assert_eq!(name, "near")
+    );
+    assert_matches!(
+        tree.alias("far", "beyond-distance"),
+        Err(AliasError::Unknown { name }) => assert_eq!(name, "beyond-distance")
+    );
+    let invalid = |name: &str| Err(AliasError::InvalidName { name: name.into() });
+    assert_eq!(tree.alias("far away", "within-distance"), invalid("far away"));
+    assert_eq!(tree.alias("$far", "within-distance"), invalid("$far"));
+    assert_eq!(tree.alias("reach", "$range"), invalid("reach"));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |node: test-original
+        |  within-distance $range
+        |node: test-alias
+        |  near $reach
+    ")).unwrap();
+    for node in ["test-original", "test-alias"] {
+        assert_eq!(tree.evaluate(&2, node, ()), Ok(Outcome::Success), "{node}");
+        assert_eq!(tree.evaluate(&4, node, ()), Ok(Outcome::Failure), "{node}");
+    }
+}
+
+#[test]
+fn action_effect_arguments() {
+    let mut tree = BehaviorTreeBuilder::<(), (), (i32, i32)>::default();
+    tree.register_action_effect("emit-relative", (1, |_, args, action_args| {
+        let [Value::Int(offset)] = args else { return None };
+        let [_, Value::Int(target)] = action_args? else { return None };
+        Some((*offset, target + offset))
+    }));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: move $unit $target
+        |  effects:
+        |    emit-relative 1
+        |    emit-relative 2
+        |node: test
+        |  move 7 10
+    ")).unwrap();
+    assert_matches!(
+        tree.evaluate(&(), "test", ()),
+        Ok(Outcome::Action(action)) => {
+            assert_eq!(action.effects(), [(1, 11), (2, 12)]);
+        }
+    );
+    assert_eq!(tree.effects(&(), &[("emit-relative", &[1.into()])]), [Ok(None)]);
+}
+
+#[test]
+fn required_symbols() {
+    let mut tree = BehaviorTreeBuilder::<(), (), ()>::default();
+    tree.register_condition("ready", cond_fn!(_ => true));
+    let missing = tree.required_symbols(INDENT, [ScriptSource::Str {
+        name: "test".into(),
+        content: normalize("
+            |action: move $target
+            |  conditions:
+            |    ready
+            |    reachable? $target
+            |  effects:
+            |    emit-move $target
+            |node: test
+            |  select:
+            |    ready
+            |    applicable: move 1
+            |    for-any $target: targets
+            |      move $target
+            |    with-first $value: [1 2]
+            |      missing-node $value
+        ").into(),
+    }]).unwrap();
+    let mut missing: Vec<_> = missing.into_iter().collect();
+    missing.sort();
+    assert_eq!(missing, ["emit-move", "missing-node", "reachable", "targets"]);
+}
+
+#[test]
+fn discovery_pruning() {
+    let mut tree = BehaviorTreeBuilder::<&'static [i32], (), i32>::default();
+    tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+    tree.register_condition("below", cond_fn!(_, a: i32, b: i32 => a < b));
+    tree.register_query("values", query_fn!(ctx => ctx.iter().copied().map(Into::into)));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: emit $value
+        |  effects:
+        |    emit-value $value
+        |  discovery:
+        |    emit 1
+        |    select:
+        |      for-every $value: values
+        |        below $value 10
+        |      prune-discovery:
+        |    visit-every $value: values
+        |      emit $value
+        |action: other $value
+        |  effects:
+        |    emit-value $value
+        |  discovery:
+        |    prune-discovery:
+        |    other 2
+        |node: test
+        |  prune-discovery:
+        |  emit 3
+    ")).unwrap();
+    let discover = |view: &'static [i32]| {
+        let mut actions = Vec::new();
+        tree.discover_all(&view, &mut actions);
+        actions.iter().flat_map(|action| action.effects().to_vec()).collect::<Vec<_>>()
+    };
+    assert_eq!(discover(&[2, 3]), [1, 2, 3]);
+    assert_eq!(discover(&[2, 30]), [1]);
+    assert_matches!(
+        tree.evaluate(&&[][..], "test", ()),
+        Ok(Outcome::Action(action)) => assert_eq!(action.effects(), [3])
+    );
+}
+
+#[test]
+fn activity_invariant_conditions() {
+    use std::cell::Cell;
+
+    let mut tree = BehaviorTreeBuilder::<Cell<usize>, (), i32>::default();
+    tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+    tree.register_condition("counted", cond_fn!(ctx, _value: i32 => {
+        ctx.set(ctx.get() + 1);
+        true
+    }));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: emit $value
+        |  conditions:
+        |    counted $value
+        |  effects:
+        |    emit-value $value
+        |node: test $value
+        |  counted $value
+        |  emit $value
+    ")).unwrap();
+    let counter = Cell::new(0);
+    assert_matches!(
+        tree.evaluate(&counter, "test", [2]),
+        Ok(Outcome::Action(action)) => assert_eq!(action.effects(), [2])
+    );
+    assert_eq!(counter.get(), 1);
+}
+
+#[test]
+fn list_prefix_patterns() {
+    let mut tree = BehaviorTreeBuilder::<(), (), ()>::default();
+    tree.register_global("$limit", |_| 2.into());
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |node: test-prefix $value
+        |  match [$a 2 ...]: $value
+        |node: test-empty-prefix $value
+        |  match [...]: $value
+        |node: test-bound $value $expected
+        |  match [$a $limit ...]: $value
+        |    match $expected: $a
+    ")).unwrap();
+    for (value, expected) in [
+        (Value::from([1, 2]), Outcome::Success),
+        (Value::from([1, 2, 3, 4]), Outcome::Success),
+        (Value::from([1]), Outcome::Failure),
+        (Value::from([1, 3, 4]), Outcome::Failure),
+        (Value::from(1), Outcome::Failure),
+    ] {
+        assert_eq!(tree.evaluate(&(), "test-prefix", [value.clone()]), Ok(expected), "{value:?}");
+    }
+    assert_eq!(tree.evaluate(&(), "test-empty-prefix", [Value::from([1])]), Ok(Outcome::Success));
+    assert_eq!(tree.evaluate(&(), "test-empty-prefix", [Value::from(1)]), Ok(Outcome::Failure));
+    let bound = |value: Value<()>, expected: i32| {
+        tree.evaluate(&(), "test-bound", [value, expected.into()])
+    };
+    assert_eq!(bound(Value::from([7, 2, 9]), 7), Ok(Outcome::Success));
+    assert_eq!(bound(Value::from([7, 2, 9]), 9), Ok(Outcome::Failure));
+
+    let error = BehaviorTreeBuilder::<(), (), ()>::default()
+        .compile_str(INDENT, "test", "node: test $value\n  match [... $a]: $value\n")
+        .err();
+    assert_matches!(error, Some(CompileError::Script(error)) => {
+        assert_matches!(error.error(), ScriptError::UnrecognizedPattern);
+    });
+}
+
+#[test]
+fn repeated_action_sections() {
+    let builder = || {
+        let mut tree = BehaviorTreeBuilder::<(), (), i32>::default();
+        tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+        tree.register_condition("below", cond_fn!(_, a: i32, b: i32 => a < b));
+        tree
+    };
+    let tree = builder().compile_str(INDENT, "test", &normalize("
+        |action: emit $value
+        |  effects:
+        |    emit-value $value
+        |  conditions:
+        |    below $value 10
+        |  effects:
+        |    emit-value 23
+        |node: test
+        |  emit 1
+    ")).unwrap();
+    assert_matches!(
+        tree.evaluate(&(), "test", ()),
+        Ok(Outcome::Action(action)) => assert_eq!(action.effects(), [1, 23])
+    );
+
+    for keyword in ["conditions", "discovery"] {
+        let source = format!("action: emit\n  {keyword}:\n    below 1 2\n  {keyword}:\n");
+        assert_matches!(
+            builder().compile_str(INDENT, "test", &source).err(),
+            Some(CompileError::Script(error)) => {
+                assert_eq!(error.error().phase(), CompilePhase::Syntax);
+                assert_matches!(
+                    error.error(),
+                    ScriptError::DuplicateActionSection { keyword: found } => {
+                        assert_eq!(*found, keyword);
+                    }
+                );
+            }
+        );
+    }
+}
+
+#[test]
+fn named_arguments() {
+    let mut tree = BehaviorTreeBuilder::<(), (), (i32, i32)>::default();
+    tree.register_effect("emit-value", effect_fn!(_, a: i32, b: i32 => Some((a, b))));
+    tree.register_condition("below", cond_fn!(_, a: i32, b: i32 => a < b));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: strike $target $power
+        |  effects:
+        |    emit-value $target $power
+        |node: test $limit $value
+        |  below $value $limit
+    ")).unwrap();
+    assert_matches!(
+        tree.evaluate_named(&(), "strike", &[("$power", 3.into()), ("$target", 7.into())]),
+        Ok(Outcome::Action(action)) => assert_eq!(action.effects(), [(7, 3)])
+    );
+    let test = |value: i32| tree.evaluate_named(&(), "test", &[
+        ("$value", value.into()),
+        ("$limit", 10.into()),
+    ]);
+    assert_eq!(test(2), Ok(Outcome::Success));
+    assert_eq!(test(20), Ok(Outcome::Failure));
+
+    assert_matches!(
+        tree.evaluate_named(&(), "strike", &[("$target", 7.into())]),
+        Err(NamedArgumentsError::Missing { name }) => assert_eq!(name, "$power")
+    );
+    assert_matches!(
+        tree.evaluate_named(&(), "strike", &[("$target", 7.into()), ("$speed", 2.into())]),
+        Err(NamedArgumentsError::Unknown { name }) => assert_eq!(name, "$speed")
+    );
+    assert_matches!(
+        tree.evaluate_named(&(), "strike", &[("$target", 7.into()), ("$target", 2.into())]),
+        Err(NamedArgumentsError::Repeated { name }) => assert_eq!(name, "$target")
+    );
+    assert_matches!(
+        tree.evaluate_named(&(), "below", &[]),
+        Err(NamedArgumentsError::Evaluate(EvaluateError { source: IdError::Kind(_), .. }))
+    );
+    assert_matches!(
+        tree.evaluate_named(&(), "unknown", &[]),
+        Err(NamedArgumentsError::Evaluate(EvaluateError { source: IdError::Unknown, .. }))
+    );
+}
+
+#[test]
+fn within_time_limits() {
+    use std::cell::Cell;
+
+    struct Clock {
+        now: Cell<i32>,
+        step: i32,
+    }
+
+    let builder = || {
+        let mut tree = BehaviorTreeBuilder::<Clock, (), i32>::default();
+        tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+        tree.register_condition("fail", cond_fn!(_ => false));
+        tree.register_global("$now", |clock: &Clock| {
+            let now = clock.now.get();
+            clock.now.set(now + clock.step);
+            now.into()
+        });
+        tree
+    };
+    let tree = builder().compile_str(INDENT, "test", &normalize("
+        |action: emit $value
+        |  effects:
+        |    emit-value $value
+        |node: test $limit
+        |  within $limit:
+        |    emit 1
+        |node: test-failing
+        |  within 10:
+        |    fail
+    ")).unwrap();
+    let clock = |step| Clock { now: Cell::new(100), step };
+    assert_matches!(
+        tree.evaluate(&clock(2), "test", [2]),
+        Ok(Outcome::Action(action)) => assert_eq!(action.effects(), [1])
+    );
+    assert_eq!(tree.evaluate(&clock(3), "test", [2]), Ok(Outcome::Failure));
+    assert_matches!(tree.evaluate(&clock(3), "test", [3.5]), Ok(Outcome::Action(_)));
+    assert_eq!(tree.evaluate(&clock(0), "test-failing", ()), Ok(Outcome::Failure));
+
+    let mut without_now = BehaviorTreeBuilder::<Clock, (), i32>::default();
+    without_now.register_condition("fail", cond_fn!(_ => false));
+    assert_matches!(
+        without_now.compile_str(INDENT, "test", "node: test\n  within 10:\n    fail\n").err(),
+        Some(CompileError::Script(error)) => {
+            assert_matches!(
+                error.error(),
+                ScriptError::Identifier { name, error: IdError::Unknown } => {
+                    assert_eq!(name, "$now");
+                }
+            );
+        }
+    );
+}
+
+#[test]
+fn blocking_queries() {
+    use std::task::{Context, Poll, Waker};
+
+    async fn fetch(ctx: &[i32], offset: i32) -> Vec<Value<()>> {
+        ctx.iter().map(|value| Value::from(value + offset)).collect()
+    }
+
+    let mut tree = BehaviorTreeBuilder::<Vec<i32>, (), i32>::default();
+    tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+    tree.register_query_blocking(
+        "remote-values",
+        (1, |ctx, args| Box::pin(async move {
+            let offset = args[0].int().unwrap_or(0);
+            fetch(ctx, offset).await
+        })),
+        |mut future| {
+            let mut cx = Context::from_waker(Waker::noop());
+            loop {
+                if let Poll::Ready(values) = future.as_mut().poll(&mut cx) {
+                    return values;
+                }
+            }
+        },
+    );
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: emit $value
+        |  effects:
+        |    emit-value $value
+        |node: test
+        |  for-every-action $value: remote-values 10
+        |    emit $value
+    ")).unwrap();
+    assert_matches!(
+        tree.evaluate(&vec![1, 2], "test", ()),
+        Ok(Outcome::Action(action)) => assert_eq!(action.effects(), [11, 12])
+    );
+    assert_eq!(tree.evaluate(&vec![], "test", ()), Ok(Outcome::Success));
+}
+
+#[test]
+fn reserved_declarations() {
+    let mut source = String::new();
+    for index in 0..2000 {
+        source.push_str(&format!("node: step-{index} $value\n  below $value {index}\n"));
+        source.push_str(&format!("action: emit-{index}\n  effects:\n    emit-value {index}\n"));
+    }
+    source.push_str("node: test $value\n  for-any $index: values\n    step-1999 $value\n");
+    let build = |estimated| {
+        let mut tree = BehaviorTreeBuilder::<(), (), i32>::default();
+        tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+        tree.register_condition("below", cond_fn!(_, a: i32, b: i32 => a < b));
+        tree.register_query("values", query_fn!(_ => [1].map(Value::from)));
+        tree.reserve_declarations(estimated);
+        tree.compile_str(INDENT, "test", &source).unwrap()
+    };
+    let reserved = build(4001);
+    assert!(reserved.structural_eq(&build(0)));
+    assert!(reserved.structural_eq(&build(10)));
+    assert_eq!(reserved.evaluate(&(), "step-10", [3]), Ok(Outcome::Success));
+    assert_eq!(reserved.evaluate(&(), "step-10", [30]), Ok(Outcome::Failure));
+    assert_eq!(reserved.evaluate(&(), "test", [1998]), Ok(Outcome::Success));
+    assert_matches!(
+        reserved.evaluate(&(), "emit-1234", ()),
+        Ok(Outcome::Action(action)) => assert_eq!(action.effects(), [1234])
+    );
+}
+
+#[test]
+fn outcome_results() {
+    type TestOutcome = Outcome<(), i32>;
+
+    let mut tree = BehaviorTreeBuilder::<(), (), i32>::default();
+    tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: emit
+        |  effects:
+        |    emit-value 23
+    ")).unwrap();
+    let Ok(Outcome::Action(action)) = tree.evaluate(&(), "emit", ()) else {
+        panic!("expected action");
+    };
+
+    assert_eq!(TestOutcome::Success.into_result(), Ok(None));
+    assert_eq!(TestOutcome::Action(action.clone()).into_result(), Ok(Some(action.clone())));
+    assert_eq!(TestOutcome::Failure.into_result(), Err(Outcome::Failure));
+    assert_eq!(TestOutcome::Running.into_result(), Err(Outcome::Running));
+
+    assert_eq!(TestOutcome::from(Ok(None)), Outcome::Success);
+    assert_eq!(TestOutcome::from(Ok(Some(action.clone()))), Outcome::Action(action));
+    assert_eq!(TestOutcome::from(Err(())), Outcome::Failure);
+}
+
+#[test]
+fn match_else_branches() {
+    let mut tree = BehaviorTreeBuilder::<(), (), i32>::default();
+    tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+    tree.register_condition("fail", cond_fn!(_ => false));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: emit $value
+        |  effects:
+        |    emit-value $value
+        |node: test $value
+        |  match [$a 2]: $value
+        |    emit $a
+        |    else:
+        |      emit 0
+        |node: test-failing-body $value
+        |  match [$a]: $value
+        |    fail
+        |    else:
+        |      emit 0
+        |node: test-only-else $value
+        |  match 1: $value
+        |    else:
+        |      fail
+    ")).unwrap();
+    let effects = |node, value: Value<()>| match tree.evaluate(&(), node, [value]) {
+        Ok(Outcome::Action(action)) => action.effects().to_vec(),
+        other => panic!("unexpected outcome {other:?}"),
+    };
+    assert_eq!(effects("test", Value::from([7, 2])), [7]);
+    assert_eq!(effects("test", Value::from([7, 3])), [0]);
+    assert_eq!(effects("test", Value::from(7)), [0]);
+    assert_eq!(effects("test-failing-body", Value::from(1)), [0]);
+    assert_eq!(tree.evaluate(&(), "test-failing-body", [[1]]), Ok(Outcome::Failure));
+    assert_eq!(tree.evaluate(&(), "test-only-else", [1]), Ok(Outcome::Success));
+    assert_eq!(tree.evaluate(&(), "test-only-else", [2]), Ok(Outcome::Failure));
+}
+
+#[test]
+fn priority_roots() {
+    let mut tree = BehaviorTreeBuilder::<(), (), i32>::default();
+    tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+    tree.register_condition("below", cond_fn!(_, a: i32, b: i32 => a < b));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: emit $value
+        |  conditions:
+        |    below $value 10
+        |  effects:
+        |    emit-value $value
+        |node: flee $distance
+        |  below $distance 2
+        |  emit 1
+        |node: attack $target
+        |  emit $target
+        |node: idle
+        |  emit 3
+    ")).unwrap();
+    let roots = |distance: i32, target: i32| [
+        ("flee", vec![Value::from(distance)]),
+        ("attack", vec![Value::from(target)]),
+        ("idle", vec![]),
+    ];
+    let evaluate = |roots: &[(&str, Vec<Value<()>>)]| {
+        let roots: Vec<_> = roots.iter().map(|(name, args)| (*name, &args[..])).collect();
+        tree.evaluate_priority(&(), &roots)
+    };
+    let effects = |outcome: Result<Outcome<(), i32>, _>| match outcome {
+        Ok(Outcome::Action(action)) => action.effects().to_vec(),
+        other => panic!("unexpected outcome {other:?}"),
+    };
+    assert_eq!(effects(evaluate(&roots(5, 7))), [7]);
+    assert_eq!(effects(evaluate(&roots(1, 7))), [1]);
+    assert_eq!(effects(evaluate(&roots(5, 70))), [3]);
+    assert_eq!(evaluate(&roots(5, 70)[..2]), Ok(Outcome::Failure));
+    assert_eq!(evaluate(&[]), Ok(Outcome::Failure));
+    assert_matches!(
+        evaluate(&[("attack", vec![Value::from(70)]), ("unknown", vec![])]),
+        Err(EvaluateError { node, source: IdError::Unknown }) => assert_eq!(node, "unknown")
+    );
+}
+
+#[test]
+fn multi_effects() {
+    let mut tree = BehaviorTreeBuilder::<(), (), i32>::default();
+    tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+    tree.register_multi_effect("emit-range", effects_fn!(_, from: i32, count: i32 => {
+        (from..).take(count as usize)
+    }));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: emit $from $count
+        |  effects:
+        |    emit-value 0
+        |    emit-range $from $count
+        |    emit-value 99
+        |node: test
+        |  emit 5 3
+        |node: test-invalid
+        |  emit 5 none
+    ")).unwrap();
+    assert_matches!(
+        tree.evaluate(&(), "test", ()),
+        Ok(Outcome::Action(action)) => {
+            assert_eq!(action.effects(), [0, 5, 6, 7, 99]);
+        }
+    );
+    assert_eq!(tree.evaluate(&(), "test-invalid", ()), Ok(Outcome::Failure));
+    assert_eq!(
+        tree.effects(&(), &[("emit-range", &[1.into(), 2.into()])]),
+        [Ok(Some(vec![1, 2]))],
+    );
+}
+
+#[test]
+fn query_pages() {
+    let mut tree = BehaviorTreeBuilder::<(), (), ()>::default();
+    tree.register_query("range", query_fn!(_, count: i32 => (0..count).map(Into::into)));
+    tree.register_condition("ok", cond_fn!(_ => true));
+    let tree = tree.compile_str(INDENT, "test", "").unwrap();
+    let values = |range: std::ops::Range<i32>| range.map(Value::from).collect::<Vec<_>>();
+    let count = [100_000.into()];
+    let page = |offset, limit| tree.query_page(&(), "range", &count, offset, limit);
+    assert_eq!(page(0, 3), Ok((values(0..3), true)));
+    assert_eq!(page(500, 2), Ok((values(500..502), true)));
+    assert_eq!(page(99_998, 5), Ok((values(99_998..100_000), false)));
+    assert_eq!(page(99_997, 3), Ok((values(99_997..100_000), false)));
+    assert_eq!(page(200_000, 3), Ok((Vec::new(), false)));
+    assert_eq!(
+        tree.query_page(&(), "range", &[], 0, 3),
+        Err(IdError::Arity(ArityError { expected: 1, given: 0 })),
+    );
+    assert_eq!(
+        tree.query_page(&(), "ok", &[], 0, 3),
+        Err(IdError::Kind(KindError { expected: Kind::Query.into(), given: Kind::Cond })),
+    );
+}
+
+#[test]
+fn lexical_shadowing() {
+    let builder = |allow: bool| {
+        let mut tree = BehaviorTreeBuilder::<(), (), i32>::default();
+        tree.register_global("$limit", |_| 3.into());
+        tree.register_condition("is", cond_fn!(_, a: i32, b: i32 => a == b));
+        tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+        tree.allow_shadowing(allow);
+        tree
+    };
+    let source = normalize("
+        |action: emit $value
+        |  effects:
+        |    emit-value $value
+        |node: test $value
+        |  do:
+        |    with-first $value: [2 3]
+        |      is $value 2
+        |    match $value: 2
+        |      emit $value
+    ");
+    // patterns naming a parameter filter against it either way
+    for allow in [true, false] {
+        let tree = builder(allow).compile_str(INDENT, "test", &source).unwrap();
+        assert_matches!(
+            tree.evaluate(&(), "test", [2]),
+            Ok(Outcome::Action(action)) => {
+                assert_eq!(action.effects(), [2]);
+            },
+            "allow: {allow}"
+        );
+        assert_eq!(tree.evaluate(&(), "test", [3]), Ok(Outcome::Failure), "allow: {allow}");
+        assert_eq!(tree.evaluate(&(), "test", [1]), Ok(Outcome::Failure), "allow: {allow}");
+    }
+
+    for (source, expected) in [
+        ("node: test $value $value\n  is $value 1", "$value"),
+        ("node: test $limit\n  is $limit 1", "$limit"),
+    ] {
+        assert_matches!(
+            builder(true).compile_str(INDENT, "test", source).err(),
+            Some(CompileError::Script(error)) => {
+                let (ScriptError::ShadowedLexical { name } | ScriptError::ShadowedGlobal { name }) =
+                    error.error() else { panic!("unexpected error: {error}") };
+                assert_eq!(name, expected);
+            },
+            "source: {source:?}"
+        );
+    }
+}
+
+#[test]
+fn discovery_conditions() {
+    let mut tree = BehaviorTreeBuilder::<&[i32], (), i32>::default();
+    tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+    tree.register_condition("below", cond_fn!(_, a: i32, b: i32 => a < b));
+    tree.register_query("targets", query_fn!(ctx => ctx.iter().copied().map(Into::into)));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: attack $target
+        |  conditions:
+        |    below $target 10
+        |  effects:
+        |    emit-value $target
+        |  discovery:
+        |    with-each $target: targets
+        |      attack $target
+    ")).unwrap();
+    let view: &[i32] = &[1, 20, 3];
+    let mut actions = Vec::new();
+    tree.discover(&view, "attack", &mut actions).unwrap();
+    let arguments: Vec<_> = actions.iter().map(|action| action.arguments().to_vec()).collect();
+    assert_eq!(arguments, [[Value::from(1)], [Value::from(3)]]);
+    let mut actions = Vec::new();
+    tree.discover_all(&view, &mut actions);
+    assert_eq!(actions.len(), 2);
+}
+
+#[test]
+fn diagnosed_selections() {
+    let mut tree = BehaviorTreeBuilder::<(), (), ()>::default();
+    tree.register_condition("eq", cond_fn!(_, a: i32, b: i32 => a == b));
+    tree.register_condition("above", cond_fn!(_, a: i32, b: i32 => a > b));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |node: test $value
+        |  select:
+        |    eq $value 1
+        |    do:
+        |      above $value 0
+        |      eq $value 2
+        |    for-any $other: [3 4]
+        |      eq $other $value
+    ")).unwrap();
+    let failed = |a: i32, b: i32| Some(FailedCondition {
+        name: "eq".into(),
+        arguments: [Value::from(a), Value::from(b)].into(),
+    });
+
+    let diagnosed = tree.evaluate_diagnosed(&(), "test", [5]).unwrap();
+    assert_eq!(diagnosed.outcome, Outcome::Failure);
+    assert_matches!(&diagnosed.failures[..], [for_any, select] => {
+        assert_eq!(for_any.branches, [failed(3, 5), failed(4, 5)]);
+        assert_eq!(select.branches, [failed(5, 1), failed(5, 2), failed(4, 5)]);
+    });
+
+    let diagnosed = tree.evaluate_diagnosed(&(), "test", [2]).unwrap();
+    assert_eq!(diagnosed.outcome, Outcome::Success);
+    assert!(diagnosed.failures.is_empty());
+    assert_matches!(
+        tree.evaluate_diagnosed(&(), "unknown", ()),
+        Err(EvaluateError { source: IdError::Unknown, .. })
+    );
+}
+
+#[test]
+fn stateful_effects() {
+    let mut tree = BehaviorTreeBuilder::<(), (), (i32, u32)>::default();
+    tree.register_effect_stateful("emit-with-id", (1, |_, args, next_id: &mut u32| {
+        let [Value::Int(value)] = args else { return None };
+        *next_id += 1;
+        Some((*value, *next_id))
+    }));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: spawn $a $b
+        |  effects:
+        |    emit-with-id $a
+        |    emit-with-id $b
+        |node: test
+        |  spawn 7 8
+        |node: test-probe
+        |  applicable: spawn 7 8
+    ")).unwrap();
+    let mut next_id = 0u32;
+    assert_matches!(
+        tree.evaluate_with_scratch(&(), "test", (), &mut next_id),
+        Ok(Outcome::Action(action)) => {
+            assert_eq!(action.effects(), [(7, 1), (8, 2)]);
+        }
+    );
+    assert_eq!(next_id, 2);
+    let probed = tree.evaluate_with_scratch(&(), "test-probe", (), &mut next_id);
+    assert_eq!(probed, Ok(Outcome::Success));
+    assert_eq!(next_id, 2);
+
+    assert_eq!(tree.evaluate(&(), "test", ()), Ok(Outcome::Failure));
+    assert_eq!(tree.evaluate(&(), "test-probe", ()), Ok(Outcome::Failure));
+    assert_eq!(tree.effects(&(), &[("emit-with-id", &[7.into()])]), [Ok(None)]);
+    let mut wrong_type = "scratch";
+    let error = Err(EvalError::ScratchType);
+    assert_eq!(tree.evaluate_with_scratch(&(), "test", (), &mut wrong_type), error);
+    assert_eq!(tree.evaluate_with_scratch(&(), "test-probe", (), &mut wrong_type), error);
+}
+
+#[test]
+fn host_modules() {
+    struct Comparisons;
+
+    impl HostModule<(), (), i32> for Comparisons {
+        fn install(self, builder: &mut BehaviorTreeBuilder<(), (), i32>) {
+            builder.register_condition("below", cond_fn!(_, a: i32, b: i32 => a < b));
+            builder.register_condition("above", cond_fn!(_, a: i32, b: i32 => a > b));
+        }
+    }
+
+    let mut tree = BehaviorTreeBuilder::<(), (), i32>::default();
+    tree.register_module(Comparisons);
+    tree.register_module(|tree: &mut BehaviorTreeBuilder<_, _, _>| {
+        tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+    });
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: emit $value
+        |  effects:
+        |    emit-value $value
+        |node: test $value
+        |  above $value 0
+        |  below $value 3
+        |  emit $value
+    ")).unwrap();
+    assert_matches!(tree.evaluate(&(), "test", [2]), Ok(Outcome::Action(action)) => {
+        assert_eq!(action.effects(), [2]);
+    });
+    assert_eq!(tree.evaluate(&(), "test", [0]), Ok(Outcome::Failure));
+    assert_eq!(tree.evaluate(&(), "test", [3]), Ok(Outcome::Failure));
+}
+
+#[test]
+fn fallback_directives() {
+    let mut tree = BehaviorTreeBuilder::<(), (), i32>::default();
+    tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+    tree.register_condition("below", cond_fn!(_, a: i32, b: i32 => a < b));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: emit $value
+        |  effects:
+        |    emit-value $value
+        |node: test $value
+        |  fallback:
+        |    try:
+        |      below $value 10
+        |      emit $value
+        |    default: emit 0
+    ")).unwrap();
+    assert_matches!(tree.evaluate(&(), "test", [3]), Ok(Outcome::Action(action)) => {
+        assert_eq!(action.effects(), [3]);
+    });
+    assert_matches!(tree.evaluate(&(), "test", [23]), Ok(Outcome::Action(action)) => {
+        assert_eq!(action.effects(), [0]);
+    });
+
+    for (source, phase) in [
+        ("fallback:\n    try:\n      below 1 2", CompilePhase::Syntax),
+        ("fallback:\n    default: emit 0\n    try:\n      below 1 2", CompilePhase::Syntax),
+        ("fallback:\n    try:\n      below 1 2\n    default: below 1 2", CompilePhase::Resolution),
+        ("fallback:\n    try:\n      below 1 2\n    default: emit", CompilePhase::Resolution),
+    ] {
+        let mut tree = BehaviorTreeBuilder::<(), (), i32>::default();
+        tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+        tree.register_condition("below", cond_fn!(_, a: i32, b: i32 => a < b));
+        let emit = "action: emit $value\n  effects:\n    emit-value $value";
+        let source = format!("{emit}\nnode: test\n  {source}");
+        assert_matches!(
+            tree.compile_str(INDENT, "test", &source).err(),
+            Some(error @ CompileError::Script(_)) => {
+                assert_eq!(error.phase(), phase, "source: {source:?}");
+            },
+            "source: {source:?}"
+        );
+    }
+}
+
+#[test]
+fn projected_views() {
+    struct Units<'a> {
+        health: &'a [i32],
+    }
+
+    struct World<'a> {
+        units: Units<'a>,
+        turn: i32,
+    }
+
+    let health = vec![30, 5, 7];
+    let mut tree = BehaviorTreeBuilder::<Units<'_>, (), i32>::default();
+    tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+    tree.register_condition("below", cond_fn!(_, a: i32, b: i32 => a < b));
+    tree.register_query("health", query_fn!(units => units.health.iter().copied().map(Into::into)));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: heal $health
+        |  effects:
+        |    emit-value $health
+        |node: test
+        |  for-any $health: health
+        |    below $health 10
+        |    heal $health
+    ")).unwrap();
+    let world = World { units: Units { health: &health }, turn: 3 };
+    assert_eq!(world.turn, 3);
+    assert_matches!(
+        tree.evaluate_projected(&world, |world| &world.units, "test", ()),
+        Ok(Outcome::Action(action)) => {
+            assert_eq!(action.effects(), [5]);
+        }
+    );
+}
+
+#[test]
+fn pinned_discovery() {
+    let tree = BehaviorTreeBuilder::<(), (), ()>::default();
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: attack $target $weapon
+        |  discovery:
+        |    for-every $target: [orc goblin]
+        |      for-every $weapon: [sword bow]
+        |        attack $target $weapon
+    ")).unwrap();
+    let discover = |fixed: &[Option<Value<()>>]| {
+        let mut actions = Vec::new();
+        tree.discover_with(&(), "attack", fixed, &mut actions).map(|()| {
+            actions.iter().map(|action| action.arguments().to_vec()).collect::<Vec<_>>()
+        })
+    };
+    let pair = |target: &str, weapon: &str| vec![Value::from(target), Value::from(weapon)];
+    assert_eq!(
+        discover(&[Some("goblin".into()), None]),
+        Ok(vec![pair("goblin", "sword"), pair("goblin", "bow")])
+    );
+    assert_eq!(
+        discover(&[None, Some("bow".into())]),
+        Ok(vec![pair("orc", "bow"), pair("goblin", "bow")])
+    );
+    assert_eq!(discover(&[None, None]).unwrap().len(), 4);
+    assert_eq!(discover(&[Some("troll".into()), None]), Ok(Vec::new()));
+    assert_matches!(
+        discover(&[None]),
+        Err(EvaluateError { source: IdError::Arity(ArityError { expected: 2, given: 1 }), .. })
+    );
+}
+
+#[test]
+fn nesting_limits() {
+    let script = |depth: usize| normalize(&format!("
+        |node: test
+        |  match {open}$x{close}: {open}23{close}
+        |    is-23 $x
+    ", open = "[".repeat(depth), close = "]".repeat(depth)));
+    let compile = |limit: Option<usize>, depth: usize| {
+        let mut tree = BehaviorTreeBuilder::<(), (), ()>::default();
+        tree.register_condition("is-23", cond_fn!(_, value: i32 => value == 23));
+        if let Some(limit) = limit {
+            tree.max_nesting(limit);
+        }
+        tree.compile_str(INDENT, "test", &script(depth)).err()
+    };
+    assert_matches!(compile(None, DEFAULT_MAX_NESTING), None);
+    assert_matches!(compile(None, DEFAULT_MAX_NESTING + 1), Some(CompileError::Script(error)) => {
+        assert_matches!(error.error(), ScriptError::NestingTooDeep { limit: DEFAULT_MAX_NESTING });
+    });
+    assert_matches!(compile(Some(3), 3), None);
+    assert_matches!(compile(Some(3), 4), Some(CompileError::Script(error)) => {
+        assert_matches!(error.error(), ScriptError::NestingTooDeep { limit: 3 });
+    });
+}
+
+#[test]
+fn verified_cache_hits() {
+    use std::cell::Cell;
+
+    let mut tree = BehaviorTreeBuilder::<Cell<i32>, (), ()>::default();
+    tree.register_condition("is-stable", cond_fn!(ctx, value: i32 => ctx.get() == value));
+    tree.register_condition("is-flipping", cond_fn!(ctx => {
+        ctx.set(ctx.get() + 1);
+        ctx.get() % 2 == 1
+    }));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |node: stable
+        |  is-stable 0
+        |  is-stable 0
+        |node: flipping
+        |  is-flipping
+        |  is-flipping
+    ")).unwrap();
+    assert_eq!(tree.evaluate(&Cell::new(0), "flipping", ()), Ok(Outcome::Success));
+    assert_eq!(tree.evaluate_verified(&Cell::new(0), "stable", ()), Ok(Outcome::Success));
+    let divergence = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        tree.evaluate_verified(&Cell::new(0), "flipping", ())
+    }));
+    assert_eq!(divergence.is_err(), cfg!(debug_assertions));
+}
+
+#[test]
+fn includes() {
+    let root = std::env::temp_dir().join(format!("reagenz-includes-{}", std::process::id()));
+    let write = |path: &str, content: &str| {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, normalize(content)).unwrap();
+    };
+    write("scripts/main.rea", "
+        |include: ../lib/shared.rea
+        |include: \"../lib/shared.rea\"
+        |include: util.rea
+        |node: test $value
+        |  is-valid $value
+        |  is-nested $value
+        |  is-util $value
+    ");
+    write("scripts/util.rea", "
+        |node: is-util $value
+        |  is-23 $value
+    ");
+    write("scripts/nested/outer.rea", "
+        |include: inner.rea
+        |node: is-nested $value
+        |  is-inner $value
+    ");
+    write("scripts/nested/inner.rea", "
+        |node: is-inner $value
+        |  is-23 $value
+    ");
+    write("lib/shared.rea", "
+        |include: shared-util.rea
+        |node: is-valid $value
+        |  is-shared $value
+    ");
+    write("lib/shared-util.rea", "
+        |node: is-shared $value
+        |  is-23 $value
+    ");
+    write("lib/cycle-a.rea", "
+        |include: cycle-b.rea
+    ");
+    write("lib/cycle-b.rea", "
+        |include: cycle-a.rea
+    ");
+    let compile = |source| {
+        let mut tree = BehaviorTreeBuilder::<(), (), ()>::default();
+        tree.register_condition("is-23", cond_fn!(_, value: i32 => value == 23));
+        tree.compile(INDENT, [source])
+    };
+    let tree = compile(ScriptSource::from_path(root.join("scripts"))).unwrap();
+    assert_eq!(tree.evaluate(&(), "test", [23]), Ok(Outcome::Success));
+    assert_eq!(tree.evaluate(&(), "test", [42]), Ok(Outcome::Failure));
+    let cycle = format!("include: {}", root.join("lib/cycle-a.rea").display());
+    assert_matches!(
+        compile(ScriptSource::from_named("cycle", cycle.into())).err(),
+        Some(CompileError::IncludeCycle { path }) => {
+            assert!(path.ends_with("cycle-a.rea"));
+        }
+    );
+    assert_matches!(
+        compile(ScriptSource::from_named("missing", "include: missing.rea".into())).err(),
+        Some(error @ CompileError::Read { .. }) => {
+            assert_eq!(error.phase(), CompilePhase::Load);
+        }
+    );
+
+    // included files are part of the hash, even outside of the directory or for named sources
+    let shared = format!("include: {}", root.join("lib/shared.rea").display());
+    let hashes = || (
+        ScriptSource::from_path(root.join("scripts")).content_hash(),
+        ScriptSource::from_named("shared", shared.clone().into()).content_hash(),
+    );
+    let before = hashes();
+    assert_eq!(hashes(), before);
+    write("lib/shared-util.rea", "
+        |node: is-shared $value
+        |  is-23 $value
+        |  is-23 $value
+    ");
+    let after = hashes();
+    assert_ne!(after.0, before.0);
+    assert_ne!(after.1, before.1);
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn best_selection() {
+    let mut tree = BehaviorTreeBuilder::<(), (), i32>::default();
+    tree.register_effect("emit", effect_fn!(_, value: i32 => value));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: use $value $priority
+        |  score: $priority
+        |  effects:
+        |    emit $value
+        |action: idle
+        |  effects:
+        |    emit 0
+        |node: first
+        |  select:
+        |    use 1 0.5
+        |    use 2 3
+        |node: best
+        |  select-best:
+        |    use 1 0.5
+        |    use 2 3
+        |    idle
+        |node: tied
+        |  select-best:
+        |    use 1 2
+        |    use 2 2
+        |node: unscored
+        |  select-best:
+        |    use 1 -1
+        |    idle
+        |node: no-actions
+        |  select-best:
+        |    use 1 invalid
+        |    ok
+        |node: ok
+        |  do:
+    ")).unwrap();
+    let action = |name| match tree.evaluate(&(), name, ()) {
+        Ok(Outcome::Action(action)) => (action.effects().to_vec(), action.score()),
+        other => panic!("unexpected outcome {other:?}"),
+    };
+    assert_eq!(action("first"), (vec![1], 0.5));
+    assert_eq!(action("best"), (vec![2], 3.0));
+    assert_eq!(action("tied"), (vec![1], 2.0));
+    assert_eq!(action("unscored"), (vec![0], 0.0));
+    assert_eq!(tree.evaluate(&(), "no-actions", ()), Ok(Outcome::Success));
+}
+
+#[test]
+fn stepped_evaluation() {
+    use std::ops::ControlFlow;
+
+    let mut tree = BehaviorTreeBuilder::<(), (), ()>::default();
+    tree.register_condition("is-23", cond_fn!(_, value: i32 => value == 23));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |node: check $value
+        |  is-23 $value
+        |node: test
+        |  check 23
+        |  is-23 23
+        |  check 42
+    ")).unwrap();
+    let steps = |limit: usize| {
+        let mut steps = Vec::new();
+        let outcome = tree.evaluate_stepped(&(), "test", (), |step| {
+            steps.push((step.name.to_string(), step.arguments.to_vec(), step.depth));
+            if steps.len() < limit { ControlFlow::Continue(()) } else { ControlFlow::Break(()) }
+        });
+        (outcome, steps)
+    };
+    let step = |name: &str, arguments: &[i32], depth| {
+        (name.to_string(), arguments.iter().copied().map(Value::from).collect::<Vec<_>>(), depth)
+    };
+    assert_eq!(steps(usize::MAX), (Ok(Outcome::Failure), vec![
+        step("test", &[], 0),
+        step("check", &[23], 1),
+        step("is-23", &[23], 2),
+        step("is-23", &[23], 1),
+        step("check", &[42], 1),
+        step("is-23", &[42], 2),
+    ]));
+    assert_eq!(steps(3), (Ok(Outcome::Failure), vec![
+        step("test", &[], 0),
+        step("check", &[23], 1),
+        step("is-23", &[23], 2),
+    ]));
+    assert_eq!(steps(1).1.len(), 1);
+    assert_matches!(
+        tree.evaluate_stepped(&(), "test", [1], |_| ControlFlow::Continue(())),
+        Err(EvaluateError { source: IdError::Arity(_), .. })
+    );
+}
+
+#[test]
+fn namespaced_symbols() {
+    let mut tree = BehaviorTreeBuilder::<(), (), Value<()>>::default();
+    tree.register_effect("emit", effect_fn!(_, value: Value<()> => value));
+    let tree = tree.compile_str(INDENT, "test", &normalize("
+        |action: announce $faction
+        |  effects:
+        |    emit $faction
+        |node: test $faction
+        |  match faction/orc: $faction
+        |    announce faction/orc
+    ")).unwrap();
+    assert_matches!(
+        tree.evaluate(&(), "test", [Value::qualified("faction", "orc")]),
+        Ok(Outcome::Action(action)) => {
+            assert_eq!(action.effects(), [Value::qualified("faction", "orc")]);
+        }
+    );
+    assert_eq!(
+        tree.evaluate(&(), "test", [Value::qualified("faction", "elf")]),
+        Ok(Outcome::Failure)
+    );
+}
+
+#[test]
+fn compile_diagnostics() {
+    let compile = |content: &str| {
+        let mut tree = BehaviorTreeBuilder::<(), (), ()>::default();
+        tree.register_condition("is-23", cond_fn!(_, value: i32 => value == 23));
+        tree.compile_str(INDENT, "test", content).err().unwrap()
+    };
+    let unbound = normalize("
+        |node: test
+        |  is-23 $unknown
+    ");
+    let diagnostics = compile(&unbound).to_diagnostics(|_| Some(unbound.as_str()));
+    assert_matches!(&diagnostics[..], [diagnostic] => {
+        assert_eq!(diagnostic.message, "Unbound variable `$unknown`");
+        assert_eq!(diagnostic.severity, DiagnosticSeverity::Error);
+        assert_eq!(diagnostic.label.as_deref(), Some("unbound variable"));
+        assert_eq!((diagnostic.start, diagnostic.end), ((1, 8), (1, 8)));
+    });
+    assert!(compile(&unbound).to_diagnostics(|_| None::<&str>).is_empty());
+
+    let conflict = normalize("
+        |node: test
+        |  is-23 23
+        |node: test
+        |  is-23 42
+    ");
+    let diagnostics = compile(&conflict).to_diagnostics(|_| Some(conflict.as_str()));
+    let severities = diagnostics.iter()
+        .map(|diagnostic| (diagnostic.severity, diagnostic.start.0))
+        .collect::<Vec<_>>();
+    assert_eq!(severities, [(DiagnosticSeverity::Note, 0), (DiagnosticSeverity::Error, 2)]);
+}
@@ -0,0 +1,76 @@
+#![cfg(feature = "snapshot")]
+
+use reagenz::{
+    BehaviorTree, BehaviorTreeBuilder, Outcome, SnapshotError, effect_fn, cond_fn, query_fn,
+};
+use src_ctx::normalize;
+use treelang::{Indent};
+use assert_matches::assert_matches;
+
+
+const INDENT: Indent = Indent::spaces(2);
+
+type TestBuilder = BehaviorTreeBuilder<&'static [i32], (), i32>;
+
+fn builder() -> TestBuilder {
+    let mut tree = TestBuilder::default();
+    tree.register_global("$limit", |_| 2.into());
+    tree.register_condition("below", cond_fn!(_, a: i32, b: i32 => a < b));
+    tree.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+    tree.register_query("values", query_fn!(ctx => ctx.iter().copied().map(Into::into)));
+    tree
+}
+
+#[test]
+fn round_trip() {
+    let tree = builder().compile_str(INDENT, "test", &normalize("
+        |action: emit $value
+        |  effects:
+        |    emit-value $value
+        |node: test
+        |  with-last $value: values
+        |    select:
+        |      below $value $limit
+        |      emit $value
+    ")).unwrap();
+    let bytes = tree.to_bytes().unwrap();
+    let tree = BehaviorTree::from_bytes(builder(), &bytes).unwrap();
+    assert_eq!(tree.evaluate(&&[1, 0][..], "test", ()), Ok(Outcome::Success));
+    assert_matches!(
+        tree.evaluate(&&[1, 23][..], "test", ()),
+        Ok(Outcome::Action(action)) => {
+            assert_matches!(action.effects(), [23]);
+            assert_eq!(tree.action_name(&action), "emit");
+        }
+    );
+}
+
+#[test]
+fn host_mismatch() {
+    let tree = builder().compile_str(INDENT, "test", "").unwrap();
+    let bytes = tree.to_bytes().unwrap();
+
+    let mut missing = TestBuilder::default();
+    missing.register_global("$limit", |_| 2.into());
+    missing.register_effect("emit-value", effect_fn!(_, value: i32 => Some(value)));
+    assert_matches!(
+        BehaviorTree::from_bytes(missing, &bytes).err(),
+        Some(SnapshotError::MissingHost { name, arity: 2, .. }) => {
+            assert_eq!(name, "below");
+        }
+    );
+
+    let mut unexpected = builder();
+    unexpected.register_condition("extra", cond_fn!(_ => true));
+    assert_matches!(
+        BehaviorTree::from_bytes(unexpected, &bytes).err(),
+        Some(SnapshotError::UnexpectedHost { name, .. }) => {
+            assert_eq!(name, "extra");
+        }
+    );
+
+    assert_matches!(
+        BehaviorTree::from_bytes(builder(), &bytes[..2]).err(),
+        Some(SnapshotError::Data(_))
+    );
+}
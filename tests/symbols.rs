@@ -0,0 +1,29 @@
+use reagenz::{is_symbol, is_variable};
+
+
+#[test]
+fn symbols() {
+    for accepted in [
+        "a", "abc", "emit-value", "for-every", "a2", "snake_case", "größe", "e\u{301}",
+        "faction/orc", "a/b/c",
+    ] {
+        assert!(is_symbol(accepted), "`{accepted}` should be a symbol");
+    }
+    for rejected in [
+        "", "-", "-abc", "2abc", "_abc", "\u{301}e", "a b", "a\tb", "a\u{7}b", "\u{7}",
+        "a?", "a:", "a;", "(a)", "[a]", "{a}", "$a", "a.b", "a+b",
+        "/", "a/", "/a", "a//b", "a/-b", "a/2",
+    ] {
+        assert!(!is_symbol(rejected), "`{}` should not be a symbol", rejected.escape_debug());
+    }
+}
+
+#[test]
+fn variables() {
+    for accepted in ["$a", "$emit-value", "$größe"] {
+        assert!(is_variable(accepted), "`{accepted}` should be a variable");
+    }
+    for rejected in ["$", "a", "$$a", "$-a", "$2", "$\u{301}", "$a b", "$a/b"] {
+        assert!(!is_variable(rejected), "`{}` should not be a variable", rejected.escape_debug());
+    }
+}
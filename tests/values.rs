@@ -1,8 +1,12 @@
 use std::sync::Arc;
 
 use ordered_float::OrderedFloat;
-use reagenz::{Value, ExtValue, IntoValues, TryFromValues};
+use reagenz::{
+    Value, ExtValue, IntoValues, TryFromValues, ConversionError, ArityError, ValueParseError,
+    DEFAULT_MAX_NESTING,
+};
 use smol_str::SmolStr;
+use assert_matches::assert_matches;
 
 
 #[derive(Debug, Clone, PartialEq)]
@@ -30,6 +34,294 @@ fn into_value() {
     assert_eq!(TestValue::from([2, 3, 4]), List(Arc::new([Int(2), Int(3), Int(4)])));
 }
 
+#[test]
+fn into_value_conversions() {
+    use Value::*;
+
+    assert_eq!(TestValue::from('a'), Symbol("a".into()));
+    assert_eq!(TestValue::from('ä'), Symbol("ä".into()));
+
+    assert_eq!(TestValue::from(23u32), Int(23));
+    assert_eq!(TestValue::from(u32::MAX), Int(i32::MAX));
+
+    assert_eq!(TestValue::from(23u64), Int(23));
+    assert_eq!(TestValue::from(i32::MAX as u64 + 1), Int(i32::MAX));
+    assert_eq!(TestValue::from(u64::MAX), Int(i32::MAX));
+}
+
+#[test]
+fn list_flatten() {
+    use Value::*;
+
+    let nested = TestValue::from_iter([
+        TestValue::from(1),
+        TestValue::from_iter([TestValue::from(2), TestValue::from_iter([3, 4])]),
+        TestValue::from_iter(Vec::<i32>::new()),
+        TestValue::from(5),
+    ]);
+    assert_eq!(nested.list_flatten(), Some(TestValue::from([1, 2, 3, 4, 5])));
+    assert_eq!(TestValue::from([1, 2]).list_flatten(), Some(TestValue::from([1, 2])));
+    assert_eq!(TestValue::from(23).list_flatten(), None);
+
+    let deep = (0..1000).fold(TestValue::from([23]), |inner, _| List(Arc::new([inner])));
+    assert!(deep.list_flatten().is_some());
+}
+
+#[test]
+fn list_path() {
+    let nested = TestValue::from_iter([
+        TestValue::from(1),
+        TestValue::from_iter([TestValue::from(2), TestValue::from_iter([3, 4])]),
+    ]);
+    assert_eq!(nested.list_path(&TestValue::from([0])), Some(&TestValue::from(1)));
+    assert_eq!(nested.list_path(&TestValue::from([1, 1, 0])), Some(&TestValue::from(3)));
+    assert_eq!(nested.list_path(&TestValue::from([1, 1])), Some(&TestValue::from([3, 4])));
+    assert_eq!(nested.list_path(&TestValue::from_iter(Vec::<i32>::new())), Some(&nested));
+
+    assert_eq!(nested.list_path(&TestValue::from([1, 1, 2])), None);
+    assert_eq!(nested.list_path(&TestValue::from([0, 0])), None);
+    assert_eq!(nested.list_path(&TestValue::from([-1])), None);
+    assert_eq!(nested.list_path(&TestValue::from(["a"])), None);
+    assert_eq!(nested.list_path(&TestValue::from(0)), None);
+}
+
+#[test]
+fn list_sort() {
+    let mixed = Value::<()>::from_iter([
+        Value::from(3),
+        Value::from(1.5),
+        Value::from(-2),
+        Value::from(0.5),
+        Value::from(1),
+    ]);
+    assert_eq!(mixed.list_sort(), Some(Value::from_iter([
+        Value::from(-2),
+        Value::from(1),
+        Value::from(3),
+        Value::from(0.5),
+        Value::from(1.5),
+    ])));
+    assert_eq!(Value::<()>::from(["b", "a"]).list_sort(), Some(Value::from(["a", "b"])));
+    assert_eq!(Value::<()>::from(23).list_sort(), None);
+}
+
+#[test]
+fn list_unique() {
+    let values = TestValue::from_iter([
+        TestValue::from(ExtValue(TestEntity(1))),
+        TestValue::from(2),
+        TestValue::from(ExtValue(TestEntity(1))),
+        TestValue::from(3),
+        TestValue::from(2),
+    ]);
+    assert_eq!(values.list_unique(), Some(TestValue::from_iter([
+        TestValue::from(ExtValue(TestEntity(1))),
+        TestValue::from(2),
+        TestValue::from(3),
+    ])));
+    assert_eq!(TestValue::from([1, 2]).list_unique(), Some(TestValue::from([1, 2])));
+    assert_eq!(TestValue::from(23).list_unique(), None);
+}
+
+#[test]
+fn tagged() {
+    let point = TestValue::tagged("point", [2, 3]);
+    assert_eq!(point, TestValue::from_iter([TestValue::from("point"), 2.into(), 3.into()]));
+    assert_eq!(point.tag_fields("point"), Some(&[TestValue::from(2), 3.into()][..]));
+    assert_eq!(point.tag_fields("size"), None);
+    assert_eq!(TestValue::tagged("empty", Vec::<i32>::new()).tag_fields("empty"), Some(&[][..]));
+    assert_eq!(TestValue::from(["point"]).tag_fields("point"), Some(&[][..]));
+    assert_eq!(TestValue::from_iter(Vec::<i32>::new()).tag_fields("point"), None);
+    assert_eq!(TestValue::from("point").tag_fields("point"), None);
+}
+
+#[test]
+fn nan_equality() {
+    let nan = TestValue::from(f32::NAN);
+    assert_eq!(nan, nan.clone());
+    let list = TestValue::from_iter([nan.clone(), 1.into()]);
+    assert_eq!(list, TestValue::from_iter([nan.clone(), 1.into()]));
+    let nested = |value: TestValue| TestValue::from_iter([TestValue::from_iter([value])]);
+    assert_eq!(nested(nan.clone()), nested(nan.clone()));
+    assert_ne!(nested(nan.clone()), nested(TestValue::from(0.0)));
+    assert!(TestValue::from([nan.clone()]).contains(&nan));
+}
+
+#[test]
+fn int_arithmetic() {
+    use Value::*;
+
+    let int = |value| TestValue::Int(value);
+    assert_eq!(int(2).checked_add(&int(3)), Some(Int(5)));
+    assert_eq!(int(2).checked_sub(&int(3)), Some(Int(-1)));
+    assert_eq!(int(2).checked_mul(&int(3)), Some(Int(6)));
+
+    assert_eq!(int(i32::MAX).checked_add(&int(1)), None);
+    assert_eq!(int(i32::MIN).checked_sub(&int(1)), None);
+    assert_eq!(int(i32::MAX).checked_mul(&int(2)), None);
+    assert_eq!(int(i32::MIN).checked_mul(&int(-1)), None);
+
+    assert_eq!(int(i32::MAX).saturating_add(&int(1)), Some(Int(i32::MAX)));
+    assert_eq!(int(i32::MIN).saturating_add(&int(-1)), Some(Int(i32::MIN)));
+    assert_eq!(int(i32::MIN).saturating_sub(&int(1)), Some(Int(i32::MIN)));
+    assert_eq!(int(i32::MAX).saturating_sub(&int(-1)), Some(Int(i32::MAX)));
+    assert_eq!(int(i32::MAX).saturating_mul(&int(2)), Some(Int(i32::MAX)));
+    assert_eq!(int(i32::MIN).saturating_mul(&int(2)), Some(Int(i32::MIN)));
+    assert_eq!(int(2).saturating_add(&int(3)), Some(Int(5)));
+
+    assert_eq!(int(2).checked_add(&TestValue::from(3.0)), None);
+    assert_eq!(TestValue::from("a").saturating_add(&int(3)), None);
+}
+
+#[test]
+fn symbol_access() {
+    assert_eq!(TestValue::from("abc").as_symbol_str(), Some("abc"));
+    assert_eq!(TestValue::from(23).as_symbol_str(), None);
+    assert_eq!(TestValue::from(["abc"]).as_symbol_str(), None);
+
+    assert!(TestValue::from("abc").is_str("abc"));
+    assert!(!TestValue::from("abc").is_str("abd"));
+    assert!(!TestValue::from(23).is_str("23"));
+}
+
+#[test]
+fn symbol_namespaces() {
+    let orc = TestValue::qualified("faction", "orc");
+    assert_eq!(orc, TestValue::from("faction/orc"));
+    assert_eq!(orc.symbol_namespace(), Some(("faction", "orc")));
+    assert_eq!(TestValue::from("a/b/c").symbol_namespace(), Some(("a", "b/c")));
+    assert_eq!(TestValue::from("orc").symbol_namespace(), None);
+    assert_eq!(TestValue::from(23).symbol_namespace(), None);
+    assert_eq!(TestValue::from(["faction/orc"]).symbol_namespace(), None);
+    assert_eq!(TestValue::parse("faction/orc"), Ok(orc));
+}
+
+#[test]
+fn symbol_concat() {
+    let parts = [TestValue::from("slot-"), TestValue::from(3)];
+    assert_eq!(TestValue::symbol_concat(&parts), Some(TestValue::from("slot-3")));
+    let parts = [TestValue::from("a"), TestValue::from(-1), TestValue::from("b")];
+    assert_eq!(TestValue::symbol_concat(&parts), Some(TestValue::from("a-1b")));
+    assert_eq!(TestValue::symbol_concat(&[]), Some(TestValue::from("")));
+
+    assert_eq!(TestValue::symbol_concat(&[TestValue::from("a"), TestValue::from(1.5)]), None);
+    assert_eq!(TestValue::symbol_concat(&[TestValue::from(["a"])]), None);
+}
+
+#[test]
+fn vectors() {
+    let position = TestValue::vec2(1.0, 2.5);
+    assert_eq!(position, TestValue::from([1.0, 2.5]));
+    assert_eq!(position.as_vec2(), Some((1.0, 2.5)));
+    assert_eq!(position.as_vec3(), None);
+    assert_eq!(TestValue::vec3(1.0, 2.0, 3.0).as_vec3(), Some((1.0, 2.0, 3.0)));
+    assert_eq!(TestValue::from([3, 4]).as_vec2(), Some((3.0, 4.0)));
+
+    assert_eq!(TestValue::from([1.0]).as_vec2(), None);
+    assert_eq!(TestValue::from([1.0, 2.0, 3.0]).as_vec2(), None);
+    assert_eq!(TestValue::from(["a", "b"]).as_vec2(), None);
+    assert_eq!(TestValue::from(1.0).as_vec2(), None);
+
+    let a = TestValue::vec2(1.0, 2.0);
+    let b = TestValue::from([4, 6]);
+    assert_eq!(a.vec_add(&b), Some(TestValue::vec2(5.0, 8.0)));
+    assert_eq!(a.vec_dist(&b), Some(5.0));
+    let a = TestValue::vec3(1.0, 2.0, 3.0);
+    let b = TestValue::vec3(3.0, 5.0, 9.0);
+    assert_eq!(a.vec_add(&b), Some(TestValue::vec3(4.0, 7.0, 12.0)));
+    assert_eq!(a.vec_dist(&b), Some(7.0));
+    assert_eq!(a.vec_add(&TestValue::vec2(1.0, 2.0)), None);
+    assert_eq!(a.vec_dist(&TestValue::from("a")), None);
+}
+
+#[test]
+fn parse() {
+    assert_eq!(TestValue::parse("abc"), Ok(TestValue::from("abc")));
+    assert_eq!(TestValue::parse("move-to"), Ok(TestValue::from("move-to")));
+    assert_eq!(TestValue::parse("23"), Ok(TestValue::from(23)));
+    assert_eq!(TestValue::parse("-7"), Ok(TestValue::from(-7)));
+    assert_eq!(TestValue::parse("2.5"), Ok(TestValue::from(2.5)));
+    assert_eq!(TestValue::parse("  [1 abc 2.5] "), Ok(TestValue::from([
+        TestValue::from(1),
+        TestValue::from("abc"),
+        TestValue::from(2.5),
+    ])));
+    assert_eq!(TestValue::parse("[[1 [a]]b []]"), Ok(TestValue::from([
+        TestValue::from([TestValue::from(1), TestValue::from(["a"])]),
+        TestValue::from("b"),
+        TestValue::from(Vec::<TestValue>::new()),
+    ])));
+
+    assert_eq!(TestValue::parse(""), Err(ValueParseError::UnexpectedEnd { offset: 0 }));
+    assert_eq!(TestValue::parse("[1 [2]"), Err(ValueParseError::Unclosed { offset: 0 }));
+    assert_eq!(TestValue::parse("1]"), Err(ValueParseError::Trailing { offset: 1 }));
+    assert_eq!(TestValue::parse("]"), Err(ValueParseError::UnexpectedClose { offset: 0 }));
+    assert_eq!(TestValue::parse("a b"), Err(ValueParseError::Trailing { offset: 2 }));
+    assert_eq!(TestValue::parse("inf"), Ok(TestValue::from("inf")));
+    for (input, token, offset) in [("[1 $x]", "$x", 3), ("1.2.3", "1.2.3", 0), ("a.b", "a.b", 0)] {
+        assert_eq!(
+            TestValue::parse(input),
+            Err(ValueParseError::Unrecognized { token: token.into(), offset }),
+            "input: {input:?}"
+        );
+    }
+    let nested = |depth: usize| format!("{}1{}", "[".repeat(depth), "]".repeat(depth));
+    assert!(TestValue::parse(&nested(DEFAULT_MAX_NESTING)).is_ok());
+    assert_eq!(
+        TestValue::parse(&nested(DEFAULT_MAX_NESTING + 1)),
+        Err(ValueParseError::NestingTooDeep {
+            offset: DEFAULT_MAX_NESTING,
+            limit: DEFAULT_MAX_NESTING,
+        })
+    );
+    assert_matches!(
+        TestValue::parse(&nested(200_000)),
+        Err(ValueParseError::NestingTooDeep { .. })
+    );
+}
+
+#[test]
+fn compare() {
+    use std::cmp::Ordering::*;
+
+    let compare = |a: TestValue, b: TestValue| a.compare(&b);
+    assert_eq!(compare(1.into(), 2.into()), Some(Less));
+    assert_eq!(compare(2.into(), 1.5.into()), Some(Greater));
+    assert_eq!(compare(1.5.into(), 2.into()), Some(Less));
+    assert_eq!(compare(2.into(), 2.0.into()), Some(Equal));
+    assert_eq!(compare(2.5.into(), 2.5.into()), Some(Equal));
+    assert_eq!(compare("a".into(), "a".into()), Some(Equal));
+    assert_eq!(compare("a".into(), "b".into()), None);
+    assert_eq!(compare([1, 2].into(), [1, 2].into()), Some(Equal));
+    assert_eq!(compare([1, 2].into(), [1, 3].into()), None);
+    assert_eq!(compare("a".into(), 1.into()), None);
+    assert_eq!(compare(f32::NAN.into(), 1.into()), Some(Greater));
+    assert_eq!(compare(1.5.into(), f32::NAN.into()), Some(Less));
+    assert_eq!(compare(f32::NAN.into(), f32::NAN.into()), Some(Equal));
+    assert_eq!(compare(16_777_217.into(), 16_777_216.0.into()), Some(Greater));
+
+    type Op = fn(Option<std::cmp::Ordering>) -> bool;
+    let ops: [(&str, Op); 6] = [
+        ("lt", |ord| ord == Some(Less)),
+        ("le", |ord| matches!(ord, Some(Less | Equal))),
+        ("gt", |ord| ord == Some(Greater)),
+        ("ge", |ord| matches!(ord, Some(Greater | Equal))),
+        ("eq", |ord| ord == Some(Equal)),
+        ("ne", |ord| ord != Some(Equal)),
+    ];
+    let expected = [
+        ((1, 1.5), [true, true, false, false, false, true]),
+        ((2, 2.0), [false, true, false, true, true, false]),
+        ((3, 2.5), [false, false, true, true, false, true]),
+    ];
+    for ((a, b), results) in expected {
+        let ord = compare(a.into(), b.into());
+        for ((name, op), result) in ops.iter().zip(results) {
+            assert_eq!(op(ord), result, "{name} {a} {b}");
+        }
+    }
+}
+
 #[test]
 fn into_values() {
     use Value::*;
@@ -74,4 +366,40 @@ fn try_from_values() {
         <(i32, SmolStr)>::try_from_values([TestValue::Int(23), Symbol("abc".into()), Int(42)]),
         None
     );
-}
\ No newline at end of file
+}
+
+#[test]
+fn try_from_values_detailed() {
+    use Value::*;
+
+    assert_eq!(
+        <(i32, SmolStr)>::try_from_values_detailed([TestValue::Int(23), Symbol("abc".into())]),
+        Ok((23, "abc".into()))
+    );
+    assert_eq!(
+        <[i32; 2]>::try_from_values_detailed([TestValue::Int(2), Int(3)]),
+        Ok([2, 3])
+    );
+
+    assert_eq!(
+        <(i32, i32)>::try_from_values_detailed([TestValue::Int(23)]),
+        Err(ConversionError::Arity(ArityError { expected: 2, given: 1 }))
+    );
+    assert_eq!(
+        <()>::try_from_values_detailed([TestValue::Int(23)]),
+        Err(ConversionError::Arity(ArityError { expected: 0, given: 1 }))
+    );
+    assert_eq!(
+        <[i32; 3]>::try_from_values_detailed([TestValue::Int(2)]),
+        Err(ConversionError::Arity(ArityError { expected: 3, given: 1 }))
+    );
+
+    assert_eq!(
+        <(i32, i32)>::try_from_values_detailed([TestValue::Int(23), Float(OrderedFloat(1.5))]),
+        Err(ConversionError::Kind { index: 1, expected: "int", given: "float" })
+    );
+    assert_eq!(
+        <[i32; 2]>::try_from_values_detailed([TestValue::from("abc"), Int(3)]),
+        Err(ConversionError::Kind { index: 0, expected: "int", given: "symbol" })
+    );
+}